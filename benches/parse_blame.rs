@@ -0,0 +1,68 @@
+use criterion::{black_box, criterion_group, criterion_main, Criterion, Throughput};
+use git_whoknows::blame::parse_blame;
+
+/// Number of lines in the synthetic blame.
+const LINES: usize = 100_000;
+/// Number of distinct commits the lines are blamed on in a long history.
+const COMMITS: usize = 500;
+/// Number of consecutive lines blamed on the same commit.
+const GROUP: usize = 10;
+
+/// Porcelain blame output of a file of `LINES` lines, in groups of `GROUP`
+/// lines blamed on one of `commits` commits. The full header of each commit
+/// is only given the first time it appears, as git does.
+fn synthetic_blame(commits: usize) -> String {
+    let mut txt = String::new();
+    let mut seen = vec![false; commits];
+    for group in 0..LINES / GROUP {
+        let commit = group % commits;
+        let hash = format!("{:040x}", commit + 1);
+        for offset in 0..GROUP {
+            let line = group * GROUP + offset + 1;
+            if offset == 0 {
+                txt.push_str(&format!("{} {} {} {}\n", hash, line, line, GROUP));
+            } else {
+                txt.push_str(&format!("{} {} {}\n", hash, line, line));
+            }
+            if !seen[commit] {
+                seen[commit] = true;
+                txt.push_str(&format!(
+                    "author Author {0}\n\
+                     author-mail <author{0}@example.com>\n\
+                     author-time 1586576941\n\
+                     author-tz -0700\n\
+                     committer Author {0}\n\
+                     committer-mail <author{0}@example.com>\n\
+                     committer-time 1586577179\n\
+                     committer-tz -0700\n\
+                     summary Commit {0}\n\
+                     filename src/main.rs\n",
+                    commit
+                ));
+            }
+            txt.push_str(&format!("\tlet line_{} = {};\n", line, line));
+        }
+    }
+    txt
+}
+
+fn bench_parse_blame(c: &mut Criterion) {
+    // Few headers, as in a long file with a long history, and a header for
+    // every group of lines, as in the many small files sharing commits the
+    // commit cache is meant for.
+    for (name, commits) in &[("shared", COMMITS), ("distinct", LINES / GROUP)] {
+        let txt = synthetic_blame(*commits);
+        let mut group = c.benchmark_group(format!("parse_blame/{}", name));
+        group.throughput(Throughput::Bytes(txt.len() as u64));
+        group.bench_function("full headers", |b| {
+            b.iter(|| parse_blame(black_box(&txt), |_| false).unwrap())
+        });
+        group.bench_function("known commits", |b| {
+            b.iter(|| parse_blame(black_box(&txt), |_| true).unwrap())
+        });
+        group.finish();
+    }
+}
+
+criterion_group!(benches, bench_parse_blame);
+criterion_main!(benches);
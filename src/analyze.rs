@@ -0,0 +1,2190 @@
+//! Analysis of the files of a repository, running git to find which commit
+//! each line comes from and crediting the lines to the authors of those
+//! commits.
+
+use crate::error::{Warning, WhoKnowsError};
+use crate::identity::Identities;
+use crate::language::LineFilter;
+use crate::origin::{self, OriginCache};
+use crate::ownership::{BasicHunk, DateWindow, Hunk, TrackedFile, NOT_COMMITTED_EMAIL};
+use crate::{blame, numstat};
+
+use anyhow::Result;
+use chrono::{DateTime, FixedOffset, Local, TimeZone};
+use dashmap::DashMap;
+use git2::{BlameHunk, BlameOptions, Oid, Repository, Time};
+use rayon::prelude::*;
+use regex::Regex;
+use std::collections::{HashMap, HashSet};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::time::{Duration, Instant};
+
+/// How the paths of files are shown.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum PathStyle {
+    /// As given on the command line.
+    Given,
+    Absolute,
+    /// Relative to the root of the file's repository.
+    RepoRelative,
+    /// Relative to the current directory.
+    CwdRelative,
+}
+
+impl FromStr for PathStyle {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<PathStyle> {
+        match s {
+            "given" => Ok(PathStyle::Given),
+            "absolute" => Ok(PathStyle::Absolute),
+            "repo-relative" => Ok(PathStyle::RepoRelative),
+            "cwd-relative" => Ok(PathStyle::CwdRelative),
+            _ => Err(anyhow::Error::msg(
+                "Path style must be one of given, absolute, repo-relative or cwd-relative",
+            )),
+        }
+    }
+}
+
+impl PathStyle {
+    /// Show the file given as `path`, at `repo_path` in its repository, with
+    /// `/` separators if `posix`.
+    pub fn display(self, path: &Path, repo_path: &Path, posix: bool) -> String {
+        let absolute = || {
+            path.canonicalize()
+                .or_else(|_| std::env::current_dir().map(|dir| dir.join(path)))
+                .unwrap_or_else(|_| path.to_path_buf())
+        };
+        let shown = match self {
+            PathStyle::Given => path.to_path_buf(),
+            PathStyle::Absolute => absolute(),
+            PathStyle::RepoRelative => repo_path.to_path_buf(),
+            PathStyle::CwdRelative => {
+                match std::env::current_dir().and_then(|dir| dir.canonicalize()) {
+                    Ok(dir) => relative_to(&absolute(), &dir),
+                    Err(_) => path.to_path_buf(),
+                }
+            }
+        };
+        let shown = shown.display().to_string();
+        if posix {
+            shown.replace('\\', "/")
+        } else {
+            shown
+        }
+    }
+}
+
+/// `path` relative to `base`, both absolute, going up with `..` as needed.
+fn relative_to(path: &Path, base: &Path) -> PathBuf {
+    let path: Vec<_> = path.components().collect();
+    let base: Vec<_> = base.components().collect();
+    let common = path.iter().zip(&base).take_while(|(a, b)| a == b).count();
+    let mut relative = PathBuf::new();
+    base[common..].iter().for_each(|_| relative.push(".."));
+    path[common..].iter().for_each(|c| relative.push(c));
+    relative
+}
+
+/// Convert a libgit2 time into a date in the time zone it was recorded in,
+/// if the time zone and the date are in range.
+fn git_time(time: Time) -> Option<DateTime<FixedOffset>> {
+    time.offset_minutes()
+        .checked_mul(60)
+        .and_then(FixedOffset::east_opt)?
+        .timestamp_opt(time.seconds(), 0)
+        .single()
+}
+
+impl Hunk for BlameHunk<'_> {
+    fn sha1(&self) -> String {
+        self.final_commit_id().to_string()
+    }
+    fn author(&self) -> String {
+        String::from_utf8_lossy(self.final_signature().name_bytes()).to_string()
+    }
+    fn email(&self) -> String {
+        String::from_utf8_lossy(self.final_signature().email_bytes()).to_string()
+    }
+    fn author_time(&self) -> DateTime<FixedOffset> {
+        // Hunks cannot fail, so a date out of range in its time zone is read
+        // in UTC, or as the epoch when it is out of range there too.
+        let when = self.final_signature().when();
+        git_time(when)
+            .or_else(|| git_time(Time::new(when.seconds(), 0)))
+            .unwrap_or_else(|| git_time(Time::new(0, 0)).expect("the epoch is in range"))
+    }
+    fn lines(&self) -> usize {
+        self.lines_in_hunk()
+    }
+    fn summary(&self) -> Option<String> {
+        None
+    }
+    fn committer(&self) -> Option<(String, String)> {
+        None
+    }
+}
+
+/// Pattern matching the first line of a hunk in porcelain blame output,
+/// capturing the commit hash and the number of lines.
+///
+/// Hashes are lowercase hex, 40 characters for SHA-1 repositories and 64 for
+/// SHA-256 ones.
+fn blame_line_pattern() -> Regex {
+    Regex::new(
+        r"(?x)
+          ^([0-9a-f]{40}|[0-9a-f]{64})\s+ # SHA-1 or SHA-256 commit hash
+          [0-9]+\s+ # Original line number
+          [0-9]+\s+ # Final line number
+          ([0-9]+) # Line count",
+    )
+    .expect("Blame line pattern must be valid.")
+}
+
+/// Blame `path` with git, giving the hunks that could be credited to their
+/// commits and a warning for each one that could not.
+fn run_external_blame(
+    repo: &Repository,
+    path: &PathBuf,
+    blame_args: &[String],
+    contents: Option<&str>,
+) -> Result<(Vec<BasicHunk>, Vec<Warning>), WhoKnowsError> {
+    let mut hunks: Vec<BasicHunk> = Vec::new();
+    let mut warnings = Vec::new();
+    let (dir, file) = split_existing(path);
+
+    let stdout = blame::run_blame(&dir, &file, "--line-porcelain", blame_args, contents)?;
+
+    let pattern = blame_line_pattern();
+
+    // No hunk can have more lines than the whole file.
+    let file_lines = stdout.lines().filter(|line| line.starts_with('\t')).count();
+
+    stdout
+        .lines()
+        .filter_map(|line| pattern.captures(line))
+        .filter_map(|cap| {
+            let sha = &cap[1];
+            let hunk = CommitInfo::lookup(repo, sha)
+                .map_err(anyhow::Error::from)
+                .and_then(|commit| {
+                    let lines = cap[2].parse::<usize>()?;
+                    if lines > file_lines {
+                        return Err(anyhow::Error::msg(format!(
+                            "line count {} exceeds the {} lines of the file",
+                            lines, file_lines
+                        )));
+                    }
+                    Ok(commit.hunk(sha, lines))
+                });
+            match hunk {
+                Ok(hunk) => Some(hunk),
+                Err(e) => {
+                    warnings.push(Warning::SkippedBlame {
+                        sha: sha.to_string(),
+                        path: path.to_path_buf(),
+                        reason: e.to_string(),
+                    });
+                    None
+                }
+            }
+        })
+        .for_each(|hunk| hunks.push(hunk));
+
+    Ok((hunks, warnings))
+}
+
+/// Construct the path of `file` relative to the working tree of `repo`.
+///
+/// The working tree is taken from libgit2 rather than derived from the
+/// location of the `.git` directory, which is elsewhere for linked worktrees.
+pub fn repo_relative_path(repo: &Repository, file: &Path) -> Result<PathBuf, WhoKnowsError> {
+    let (dir, rest) = split_existing(file);
+    let arg_path = dir.canonicalize()?.join(rest);
+    let workdir = match repo.workdir() {
+        Some(workdir) => workdir.canonicalize()?,
+        None => return Ok(arg_path),
+    };
+    match arg_path.strip_prefix(&workdir) {
+        Ok(relative) => Ok(relative.to_path_buf()),
+        Err(_) => Ok(arg_path),
+    }
+}
+
+/// Split `path` into its closest existing directory and the rest of the path
+/// below that directory.
+///
+/// Files blamed at a past revision may no longer exist in the working tree,
+/// and neither may their directories.
+pub fn split_existing(path: &Path) -> (PathBuf, PathBuf) {
+    let mut dir = path.parent().unwrap_or_else(|| Path::new(""));
+    let mut rest = PathBuf::from(path.file_name().unwrap_or_default());
+    while !dir.as_os_str().is_empty() && !dir.is_dir() {
+        rest = Path::new(dir.file_name().unwrap_or_default()).join(rest);
+        dir = dir.parent().unwrap_or_else(|| Path::new(""));
+    }
+    if dir.as_os_str().is_empty() {
+        (PathBuf::from("."), rest)
+    } else {
+        (dir.to_path_buf(), rest)
+    }
+}
+
+/// Author information of a commit.
+#[derive(Clone)]
+pub struct CommitInfo {
+    author: String,
+    mail: String,
+    time: DateTime<FixedOffset>,
+    summary: Option<String>,
+    committer: Option<(String, String)>,
+}
+
+impl CommitInfo {
+    /// Author information of the commit `hash`, looked up through libgit2.
+    fn lookup(repo: &Repository, hash: &str) -> Result<CommitInfo, WhoKnowsError> {
+        let oid = Oid::from_str(hash)?;
+        if oid.is_zero() {
+            // Lines that are not committed yet have no commit to look up.
+            return Ok(CommitInfo {
+                author: "Not Committed Yet".to_string(),
+                mail: NOT_COMMITTED_EMAIL.to_string(),
+                time: Local::now().into(),
+                summary: None,
+                committer: None,
+            });
+        }
+        let commit = repo.find_commit(oid)?;
+        let signature = commit.author();
+        let committer = commit.committer();
+        let info = CommitInfo {
+            author: String::from_utf8_lossy(signature.name_bytes()).to_string(),
+            mail: String::from_utf8_lossy(signature.email_bytes()).to_string(),
+            time: git_time(signature.when()).ok_or_else(|| WhoKnowsError::BadDate {
+                hash: hash.to_string(),
+                seconds: signature.when().seconds(),
+                offset_minutes: signature.when().offset_minutes(),
+            })?,
+            summary: commit.summary().map(str::to_string),
+            committer: Some((
+                String::from_utf8_lossy(committer.name_bytes()).to_string(),
+                String::from_utf8_lossy(committer.email_bytes()).to_string(),
+            )),
+        };
+        Ok(info)
+    }
+
+    fn hunk(&self, hash: &str, num_lines: usize) -> BasicHunk {
+        BasicHunk {
+            hash: hash.to_string(),
+            author: self.author.to_string(),
+            mail: self.mail.to_string(),
+            time: self.time,
+            num_lines,
+            summary: self.summary.clone(),
+            committer: self.committer.clone(),
+        }
+    }
+}
+
+/// Author information for each commit SHA seen while parsing blame output.
+///
+/// Shared between files so the full header of a commit only needs to be parsed
+/// once per run rather than once per file.
+pub type CommitCache = DashMap<String, CommitInfo>;
+
+/// Number of counted lines of each group of lines blamed on the same commit.
+///
+/// Lines are counted from their content rather than the size of the group
+/// given in the header, so `filter` can leave lines out.
+fn counted_hunks<'a>(lines: &[blame::Line<'a>], filter: &LineFilter) -> Vec<(&'a str, usize)> {
+    let mut hunks: Vec<(&str, usize)> = Vec::new();
+    for line in lines {
+        if line.header.num_lines_in_group.is_some() {
+            hunks.push((line.header.hash, 0));
+        }
+        if filter.counts(line.line) {
+            if let Some((_, num_lines)) = hunks.last_mut() {
+                *num_lines += 1;
+            }
+        }
+    }
+    hunks.retain(|(_, num_lines)| *num_lines > 0);
+    hunks
+}
+
+pub fn analyze_file_nom(
+    repo: &Repository,
+    path: &Path,
+    cache: &CommitCache,
+    identities: &Identities,
+    filter: &LineFilter,
+    blame_args: &[String],
+    contents: Option<&str>,
+) -> Result<TrackedFile, WhoKnowsError> {
+    let (dir, file) = split_existing(path);
+    let txt = blame::generate_blame(&dir, &file, blame_args, contents)?;
+
+    let mut tracked_file =
+        TrackedFile::new(path.display().to_string(), repo_relative_path(repo, path)?);
+    add_porcelain(
+        &mut tracked_file,
+        &txt,
+        Some(repo),
+        cache,
+        identities,
+        filter,
+    )?;
+    Ok(tracked_file)
+}
+
+/// Analyze a file with the blame libgit2 computes in process, without running
+/// git, as of `settings.rev` or else the working tree.
+///
+/// libgit2 only blames what is committed, so a file with uncommitted changes
+/// in the working tree is blamed by git instead, which credits the changed
+/// lines as the other methods do.
+pub fn analyze_file_libgit2(
+    repo: &Repository,
+    path: &Path,
+    cache: &CommitCache,
+    identities: &Identities,
+    filter: &LineFilter,
+    settings: &BlameSettings,
+) -> Result<TrackedFile, WhoKnowsError> {
+    let repo_path = repo_relative_path(repo, path)?;
+    let mut opts = BlameOptions::new();
+    opts.first_parent(settings.first_parent);
+    if let Some(after) = &settings.after {
+        opts.oldest_commit(repo.revparse_single(after)?.peel_to_commit()?.id());
+    }
+    // The lines blamed are those of the file as committed.
+    let contents = match &settings.rev {
+        Some(rev) => {
+            let commit = repo.revparse_single(rev)?.peel_to_commit()?;
+            opts.newest_commit(commit.id());
+            let entry = commit.tree()?.get_path(&repo_path)?;
+            entry.to_object(repo)?.peel_to_blob()?.content().to_vec()
+        }
+        None if repo
+            .status_file(&repo_path)
+            .is_ok_and(|status| status.is_empty()) =>
+        {
+            std::fs::read(path)?
+        }
+        None => {
+            return analyze_file_nom(
+                repo,
+                path,
+                cache,
+                identities,
+                filter,
+                &settings.args(),
+                None,
+            )
+        }
+    };
+    let blame = repo.blame_file(&repo_path, Some(&mut opts))?;
+    let contents = String::from_utf8_lossy(&contents);
+    let lines: Vec<&str> = contents.lines().collect();
+
+    // libgit2 blames every line, so the hunks are clipped to the range, taken
+    // from 0 and without its end.
+    let (first, last) = match settings.lines {
+        Some((start, _)) if start > lines.len() => {
+            return Err(git2::Error::from_str(&format!(
+                "file {} has only {} lines",
+                repo_path.display(),
+                lines.len()
+            ))
+            .into())
+        }
+        Some((start, end)) => (start - 1, end.min(lines.len())),
+        None => (0, lines.len()),
+    };
+
+    let filter = filter.for_file(&repo_path);
+    let mut tracked_file = TrackedFile::new(path.display().to_string(), repo_path);
+    tracked_file.empty = lines.is_empty();
+    for hunk in blame.iter() {
+        let start = hunk.final_start_line().saturating_sub(1).max(first);
+        let end = (hunk.final_start_line().saturating_sub(1) + hunk.lines_in_hunk()).min(last);
+        let num_lines = lines
+            .get(start..end.max(start))
+            .unwrap_or_default()
+            .iter()
+            .filter(|line| filter.counts(line))
+            .count();
+        if num_lines == 0 {
+            continue;
+        }
+        let hash = hunk.final_commit_id().to_string();
+        let commit = match cache.get(&hash) {
+            Some(commit) => commit.clone(),
+            None => {
+                let commit = CommitInfo::lookup(repo, &hash)?;
+                cache.insert(hash.clone(), commit.clone());
+                commit
+            }
+        };
+        tracked_file.add_hunk(&commit.hunk(&hash, num_lines), identities);
+    }
+    Ok(tracked_file)
+}
+
+/// Ownership of the file at `path` from its porcelain blame output, without
+/// running git or reading the file.
+pub fn ownership_from_porcelain(path: &str, porcelain: &str) -> Result<TrackedFile, WhoKnowsError> {
+    let mut tracked_file = TrackedFile::new(path.to_string(), PathBuf::from(path));
+    add_porcelain(
+        &mut tracked_file,
+        porcelain,
+        None,
+        &CommitCache::new(),
+        &Identities::default(),
+        &LineFilter::default(),
+    )?;
+    Ok(tracked_file)
+}
+
+/// Add the lines of porcelain blame output `txt` to `tracked_file`.
+///
+/// Commits missing from `cache` are added to it, from their full header in
+/// `txt` or else looked up in `repo`. Without a repository a commit without its
+/// header is a parse error.
+fn add_porcelain(
+    tracked_file: &mut TrackedFile,
+    txt: &str,
+    repo: Option<&Repository>,
+    cache: &CommitCache,
+    identities: &Identities,
+    filter: &LineFilter,
+) -> Result<(), WhoKnowsError> {
+    let lines = blame::parse_blame(txt, |hash| cache.contains_key(hash))?;
+    tracked_file.empty = lines.is_empty();
+
+    lines.iter().for_each(|line| {
+        if let Some(extra) = &line.header.extra {
+            cache.insert(
+                line.header.hash.to_string(),
+                CommitInfo {
+                    author: extra.author.to_string(),
+                    mail: extra
+                        .author_mail
+                        .trim_start_matches("<")
+                        .trim_end_matches(">")
+                        .to_string(),
+                    time: extra.author_time,
+                    summary: Some(extra.summary.to_string()),
+                    committer: Some((
+                        extra.committer.to_string(),
+                        extra
+                            .committer_mail
+                            .trim_start_matches("<")
+                            .trim_end_matches(">")
+                            .to_string(),
+                    )),
+                },
+            );
+        }
+    });
+
+    let filter = filter.for_file(&tracked_file.repo_path);
+    for (hash, num_lines) in counted_hunks(&lines, &filter) {
+        let cached = cache.get(hash).map(|commit| commit.clone());
+        let commit = match (cached, repo) {
+            (Some(commit), _) => commit,
+            // The full header is normally given the first time a commit
+            // appears, but some orderings of the output only abridge it.
+            (None, Some(repo)) => {
+                let commit = CommitInfo::lookup(repo, hash)?;
+                cache.insert(hash.to_string(), commit.clone());
+                commit
+            }
+            (None, None) => {
+                let (number, line) = txt
+                    .lines()
+                    .enumerate()
+                    .find(|(_, line)| line.starts_with(hash))
+                    .unwrap_or((0, ""));
+                return Err(WhoKnowsError::parse(number + 1, line));
+            }
+        };
+        tracked_file.add_hunk(&commit.hunk(hash, num_lines), identities);
+    }
+    Ok(())
+}
+
+/// Analyze a file using only the abridged porcelain headers.
+///
+/// Authors are looked up through libgit2, once for each commit that still owns
+/// lines, instead of being parsed from the full porcelain headers.
+pub fn analyze_file_no_extra(
+    repo: &Repository,
+    path: &Path,
+    identities: &Identities,
+    filter: &LineFilter,
+    blame_args: &[String],
+    contents: Option<&str>,
+) -> Result<TrackedFile, WhoKnowsError> {
+    let (dir, file) = split_existing(path);
+    let txt = blame::generate_blame(&dir, &file, blame_args, contents)?;
+    let lines = blame::parse_blame(&txt, |_| true)?;
+
+    let mut tracked_file =
+        TrackedFile::new(path.display().to_string(), repo_relative_path(repo, path)?);
+    tracked_file.empty = lines.is_empty();
+    let mut authors: HashMap<&str, CommitInfo> = HashMap::new();
+
+    let filter = filter.for_file(&tracked_file.repo_path);
+    for (hash, num_lines) in counted_hunks(&lines, &filter) {
+        if !authors.contains_key(hash) {
+            authors.insert(hash, CommitInfo::lookup(repo, hash)?);
+        }
+
+        tracked_file.add_hunk(&authors[hash].hunk(hash, num_lines), identities);
+    }
+
+    Ok(tracked_file)
+}
+
+/// Analyze a file crediting each line to the commit that first wrote it.
+///
+/// Every line is followed back through the commits that replaced it, which
+/// runs git blame again for each step, so this is much slower than the other
+/// methods. The steps are cached in `origins` and shared between files.
+#[allow(clippy::too_many_arguments)]
+pub fn analyze_file_original(
+    repo: &Repository,
+    path: &Path,
+    cache: &CommitCache,
+    origins: &OriginCache,
+    identities: &Identities,
+    filter: &LineFilter,
+    blame_args: &[String],
+    contents: Option<&str>,
+) -> Result<TrackedFile, WhoKnowsError> {
+    let (dir, file) = split_existing(path);
+    let txt = blame::run_blame(&dir, &file, "--line-porcelain", blame_args, contents)?;
+    let lines = blame::parse_blame(&txt, |_| false)?;
+    let commits = origin::original_commits(repo, &txt, &lines, origins, blame_args)?;
+
+    let mut tracked_file =
+        TrackedFile::new(path.display().to_string(), repo_relative_path(repo, path)?);
+    tracked_file.empty = lines.is_empty();
+    let filter = filter.for_file(&tracked_file.repo_path);
+    let mut hunks: Vec<(&str, usize)> = Vec::new();
+    for (line, hash) in lines.iter().zip(&commits) {
+        if !filter.counts(line.line) {
+            continue;
+        }
+        match hunks.last_mut() {
+            Some((last, num_lines)) if last == hash => *num_lines += 1,
+            _ => hunks.push((hash, 1)),
+        }
+    }
+    for (hash, num_lines) in hunks {
+        let cached = cache.get(hash).map(|commit| commit.clone());
+        let commit = match cached {
+            Some(commit) => commit,
+            None => {
+                let commit = CommitInfo::lookup(repo, hash)?;
+                cache.insert(hash.to_string(), commit.clone());
+                commit
+            }
+        };
+        tracked_file.add_hunk(&commit.hunk(hash, num_lines), identities);
+    }
+    Ok(tracked_file)
+}
+
+pub fn analyze_file(
+    repo: &Repository,
+    file: &PathBuf,
+    identities: &Identities,
+    blame_args: &[String],
+    contents: Option<&str>,
+) -> Result<TrackedFile, WhoKnowsError> {
+    // Construct the path relative to the Git repository.
+    let path = repo_relative_path(repo, file)?;
+
+    let mut tracker = TrackedFile::new(path.display().to_string(), path);
+
+    let (blame, warnings) = run_external_blame(repo, &file, blame_args, contents)?;
+    tracker.empty = blame.is_empty();
+    tracker.warnings = warnings;
+
+    for hunk in blame.iter() {
+        tracker.add_hunk(hunk, identities);
+    }
+
+    Ok(tracker)
+}
+
+/// Analyze a file from the lines added by each commit over its history, as
+/// reported by `git log --numstat`.
+///
+/// Each commit is credited with the lines it added, or with the lines it added
+/// less those it deleted when `net` is set.
+pub fn analyze_file_numstat(
+    repo: &Repository,
+    path: &Path,
+    identities: &Identities,
+    net: bool,
+) -> Result<TrackedFile, WhoKnowsError> {
+    let (dir, file) = split_existing(path);
+    let txt = numstat::generate_numstat(&dir, &file)?;
+
+    let mut tracked_file =
+        TrackedFile::new(path.display().to_string(), repo_relative_path(repo, path)?);
+    for change in numstat::parse_numstat(&txt)? {
+        let num_lines = if net {
+            change.added.saturating_sub(change.deleted)
+        } else {
+            change.added
+        };
+        if num_lines == 0 {
+            continue;
+        }
+        let hunk = BasicHunk {
+            hash: change.hash.to_string(),
+            author: change.author.to_string(),
+            mail: change.email.to_string(),
+            time: change.time,
+            num_lines,
+            summary: None,
+            committer: Some((
+                change.committer.to_string(),
+                change.committer_email.to_string(),
+            )),
+        };
+        tracked_file.add_hunk(&hunk, identities);
+    }
+    Ok(tracked_file)
+}
+
+/// How the blame of each file is obtained and parsed.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum Method {
+    /// Blame in process with libgit2. Files with uncommitted changes, or
+    /// blamed with options libgit2 cannot honour as listed by
+    /// `Options::git_only`, are blamed as with `Nom`.
+    #[default]
+    Libgit2,
+    /// Parse the full porcelain output of git blame with nom.
+    Nom,
+    /// Parse only the line counts and look up commit authors separately.
+    NoExtra,
+    /// Scan the line porcelain output for commit hashes.
+    Regex,
+    /// Sum the lines added by each commit in the log, less those deleted if
+    /// `net` is set.
+    Numstat { net: bool },
+    /// Credit the lines to the commits that first wrote them rather than those
+    /// that last changed them.
+    Original,
+}
+
+/// How the lines of a file are blamed and credited to owners.
+#[derive(Default)]
+pub struct Options {
+    pub method: Method,
+    pub identities: Identities,
+    pub filter: LineFilter,
+    /// Arguments passed on to git blame.
+    pub blame_args: Vec<String>,
+    pub settings: BlameSettings,
+    /// Content blamed in place of the file in the working tree.
+    pub contents: Option<String>,
+    /// Prefixes of the hashes of commits whose lines are not counted.
+    pub ignored_revs: Vec<String>,
+    /// Author times of the commits whose lines are counted.
+    pub window: DateWindow,
+    /// Whether the lines not committed yet are left out.
+    pub skip_uncommitted: bool,
+}
+
+impl Options {
+    /// Arguments given to git blame, those passed on followed by the ones
+    /// asking for `settings`.
+    fn git_args(&self) -> Vec<String> {
+        let mut args = self.blame_args.clone();
+        args.extend(self.settings.args());
+        args
+    }
+
+    /// Options libgit2 has no equivalent for, with which `Method::Libgit2`
+    /// blames files with git instead.
+    pub fn git_only(&self) -> Vec<String> {
+        let mut options = Vec::new();
+        if !self.blame_args.is_empty() {
+            options.push(self.blame_args.join(" "));
+        }
+        if self.settings.ignore_whitespace {
+            options.push("-w".to_string());
+        }
+        // libgit2 takes flags to track moves and copies, but leaves them
+        // unimplemented and blames as without them.
+        if self.settings.detect_moves {
+            options.push("-M".to_string());
+        }
+        if self.settings.detect_copies {
+            options.push("-C".to_string());
+        }
+        if !self.settings.ignore_revs.is_empty() {
+            options.push("--ignore-rev".to_string());
+        }
+        if self.contents.is_some() {
+            options.push("--contents".to_string());
+        }
+        options
+    }
+}
+
+/// Settings of the blame of every file, given to git blame as arguments or to
+/// libgit2 as `BlameOptions`.
+#[derive(Clone, Debug, Default)]
+pub struct BlameSettings {
+    /// Revision whose files are blamed, rather than those in the working
+    /// tree.
+    pub rev: Option<String>,
+    /// Commit whose history is not blamed, its lines and older ones credited
+    /// to it.
+    pub after: Option<String>,
+    /// Range of lines blamed, from the first to the last counting from 1.
+    pub lines: Option<(usize, usize)>,
+    /// Whether changes to whitespace alone are passed over.
+    pub ignore_whitespace: bool,
+    /// Whether lines moved within a file are credited to the commit that
+    /// wrote them there first, as with `git blame -M`.
+    pub detect_moves: bool,
+    /// Whether lines moved or copied from other files are credited to the
+    /// commit that first wrote them, as with `git blame -C`.
+    pub detect_copies: bool,
+    /// Whether only the first parent of merges is followed.
+    pub first_parent: bool,
+    /// Full hashes of the commits whose lines are credited to the commits
+    /// before them.
+    pub ignore_revs: Vec<String>,
+}
+
+impl BlameSettings {
+    /// Arguments asking git blame for the settings.
+    pub fn args(&self) -> Vec<String> {
+        let mut args = Vec::new();
+        if self.ignore_whitespace {
+            args.push("-w".to_string());
+        }
+        if self.detect_moves {
+            args.push("-M".to_string());
+        }
+        if self.detect_copies {
+            args.push("-C".to_string());
+        }
+        if let Some((start, end)) = self.lines {
+            args.push("-L".to_string());
+            args.push(format!("{},{}", start, end));
+        }
+        if self.first_parent {
+            args.push("--first-parent".to_string());
+        }
+        for hash in &self.ignore_revs {
+            args.push("--ignore-rev".to_string());
+            args.push(hash.to_string());
+        }
+        if let Some(after) = &self.after {
+            args.push(format!("^{}", after));
+        }
+        args.extend(self.rev.clone());
+        args
+    }
+}
+
+/// Analyze the file at `path`, crediting its lines to their owners as set out
+/// in `opts`.
+///
+/// Each call starts with empty caches, so an `Analyzer` is faster for several
+/// files.
+pub fn analyze(path: &Path, opts: &Options) -> Result<TrackedFile, WhoKnowsError> {
+    let repo = Repository::discover(split_existing(path).0)?;
+    let mut tracked_file = blame_file(&repo, path, opts, &CommitCache::new(), &OriginCache::new())?;
+    tracked_file.path = PathStyle::Given.display(path, &tracked_file.repo_path, false);
+    Ok(tracked_file)
+}
+
+/// Analyze the file at `path` in `repo` with the method of `opts`, sharing the
+/// commits and line origins already seen in `cache` and `origins`, and keep
+/// the lines `opts` counts.
+///
+/// The lines of the boundary commit of `BlameSettings::after`, which stands in
+/// for the history before it, are credited to a single boundary owner.
+fn blame_file(
+    repo: &Repository,
+    path: &Path,
+    opts: &Options,
+    cache: &CommitCache,
+    origins: &OriginCache,
+) -> Result<TrackedFile, WhoKnowsError> {
+    let contents = opts.contents.as_deref();
+    let blame_args = opts.git_args();
+    let mut tracked_file = match opts.method {
+        Method::Libgit2 if opts.git_only().is_empty() => analyze_file_libgit2(
+            repo,
+            path,
+            cache,
+            &opts.identities,
+            &opts.filter,
+            &opts.settings,
+        ),
+        Method::Libgit2 | Method::Nom => analyze_file_nom(
+            repo,
+            path,
+            cache,
+            &opts.identities,
+            &opts.filter,
+            &blame_args,
+            contents,
+        ),
+        Method::NoExtra => analyze_file_no_extra(
+            repo,
+            path,
+            &opts.identities,
+            &opts.filter,
+            &blame_args,
+            contents,
+        ),
+        Method::Regex => analyze_file(
+            repo,
+            &path.to_path_buf(),
+            &opts.identities,
+            &blame_args,
+            contents,
+        ),
+        Method::Numstat { net } => analyze_file_numstat(repo, path, &opts.identities, net),
+        Method::Original => analyze_file_original(
+            repo,
+            path,
+            cache,
+            origins,
+            &opts.identities,
+            &opts.filter,
+            &blame_args,
+            contents,
+        ),
+    }?;
+    if let Some(boundary) = &opts.settings.after {
+        tracked_file.bucket_boundary(boundary);
+    }
+    if !opts.ignored_revs.is_empty() {
+        tracked_file.ignore_revs(&opts.ignored_revs);
+    }
+    if opts.skip_uncommitted {
+        tracked_file.skip_uncommitted();
+    }
+    if !opts.window.is_open() {
+        tracked_file.keep_within(&opts.window);
+    }
+    Ok(tracked_file)
+}
+
+/// Analyzes files with the settings shared by all the files of a run.
+pub struct Analyzer {
+    options: Options,
+    cache: CommitCache,
+    /// Time taken by each file, when recording timings.
+    timings: Option<DashMap<PathBuf, Duration>>,
+    /// Commit that first wrote each line followed for `Method::Original`.
+    origins: OriginCache,
+    path_style: PathStyle,
+    /// Whether paths are shown with `/` separators on every platform.
+    posix_paths: bool,
+}
+
+impl Analyzer {
+    pub fn new(
+        method: Method,
+        identities: Identities,
+        filter: LineFilter,
+        blame_args: Vec<String>,
+    ) -> Analyzer {
+        Analyzer::with_options(Options {
+            method,
+            identities,
+            filter,
+            blame_args,
+            ..Options::default()
+        })
+    }
+
+    pub fn with_options(options: Options) -> Analyzer {
+        Analyzer {
+            options,
+            cache: CommitCache::new(),
+            timings: None,
+            origins: OriginCache::new(),
+            path_style: PathStyle::Given,
+            posix_paths: false,
+        }
+    }
+
+    /// Show the paths of the files in `style`, with `/` separators if `posix`.
+    pub fn show_paths(&mut self, style: PathStyle, posix: bool) {
+        self.path_style = style;
+        self.posix_paths = posix;
+    }
+
+    /// Blame `contents` in place of the working tree content of the files.
+    pub fn blame_contents(&mut self, contents: String) {
+        self.options.contents = Some(contents);
+    }
+
+    /// Only blame the history after the commit `hash`, crediting the lines
+    /// older than that to a single boundary owner.
+    pub fn blame_after(&mut self, hash: String) {
+        self.options.settings.after = Some(hash);
+    }
+
+    /// Options of the run libgit2 has no equivalent for, as listed by
+    /// `Options::git_only`.
+    pub fn git_only(&self) -> Vec<String> {
+        self.options.git_only()
+    }
+
+    /// Leave out the lines of commits whose hash starts with one of `revs`,
+    /// such as those `git blame --ignore-rev` could not credit to an earlier
+    /// commit.
+    pub fn ignore_revs(&mut self, revs: Vec<String>) {
+        self.options.ignored_revs = revs;
+    }
+
+    /// Leave out the lines that are not committed yet.
+    pub fn skip_uncommitted(&mut self) {
+        self.options.skip_uncommitted = true;
+    }
+
+    /// Leave out the lines of commits authored outside `window`.
+    pub fn keep_within(&mut self, window: DateWindow) {
+        self.options.window = window;
+    }
+
+    /// Record the time taken by each analyzed file.
+    pub fn record_timings(&mut self) {
+        self.timings = Some(DashMap::new());
+    }
+
+    /// Problems met resolving identities since the last call, as given by
+    /// `Identities::take_warnings`. Those met blaming a file are in its
+    /// `TrackedFile::warnings`.
+    pub fn take_warnings(&self) -> Vec<Warning> {
+        self.options.identities.take_warnings()
+    }
+
+    /// Time taken by each analyzed file, if recording timings.
+    pub fn into_timings(self) -> Option<DashMap<PathBuf, Duration>> {
+        self.timings
+    }
+
+    pub fn analyze(&self, path: &Path) -> Result<TrackedFile, WhoKnowsError> {
+        let repo = Repository::discover(split_existing(path).0)?;
+        self.analyze_in(&repo, path)
+    }
+
+    /// Analyze the file at `path` in `repo`, the repository it is in.
+    fn analyze_in(&self, repo: &Repository, path: &Path) -> Result<TrackedFile, WhoKnowsError> {
+        let start = Instant::now();
+        let tracked_file = blame_file(repo, path, &self.options, &self.cache, &self.origins);
+        if let Some(timings) = &self.timings {
+            timings.insert(path.to_path_buf(), start.elapsed());
+        }
+        tracked_file.map(|mut tracked_file| {
+            tracked_file.path =
+                self.path_style
+                    .display(path, &tracked_file.repo_path, self.posix_paths);
+            tracked_file
+        })
+    }
+
+    /// Analyze the files one at a time as the iterator is advanced.
+    ///
+    /// Unlike `analyze_par` nothing is held beyond the current file, so
+    /// callers can process the results of large repositories incrementally.
+    pub fn analyze_all<'a, I>(
+        &'a self,
+        paths: I,
+    ) -> impl Iterator<Item = Result<TrackedFile, WhoKnowsError>> + 'a
+    where
+        I: IntoIterator,
+        I::IntoIter: 'a,
+        I::Item: AsRef<Path>,
+    {
+        paths
+            .into_iter()
+            .map(move |path| self.analyze(path.as_ref()))
+    }
+
+    /// Analyze the files in parallel, giving the result of each.
+    ///
+    /// The results are in the same order as `paths`.
+    pub fn analyze_par(&self, paths: &[PathBuf]) -> Vec<Result<TrackedFile, WhoKnowsError>> {
+        self.analyze_par_with(paths, |_| ())
+    }
+
+    /// Analyze the files in parallel as `analyze_par` does, calling
+    /// `on_done` with each file once it has been analyzed, whether or not it
+    /// failed, such as to show progress.
+    ///
+    /// The repository of each directory is found once rather than for each
+    /// file. A `Repository` cannot be shared between threads, so each thread
+    /// opens the repositories it needs once and keeps them for its files.
+    pub fn analyze_par_with<F>(
+        &self,
+        paths: &[PathBuf],
+        on_done: F,
+    ) -> Vec<Result<TrackedFile, WhoKnowsError>>
+    where
+        F: Fn(&Path) + Sync,
+    {
+        let git_dirs = git_dirs(paths);
+        paths
+            .par_iter()
+            .map_init(HashMap::new, |repos, path| {
+                let tracked_file = match git_dirs.get(&split_existing(path).0) {
+                    Some(git_dir) => open_once(repos, git_dir)
+                        .map_err(WhoKnowsError::from)
+                        .and_then(|repo| self.analyze_in(repo, path)),
+                    None => self.analyze(path),
+                };
+                on_done(path);
+                tracked_file
+            })
+            .collect()
+    }
+}
+
+/// Git directory of the repository each directory of `paths` is in, for the
+/// directories in a repository.
+fn git_dirs(paths: &[PathBuf]) -> HashMap<PathBuf, PathBuf> {
+    let dirs: HashSet<PathBuf> = paths.iter().map(|path| split_existing(path).0).collect();
+    dirs.into_par_iter()
+        .filter_map(|dir| {
+            let git_dir = Repository::discover(&dir).ok()?.path().to_path_buf();
+            Some((dir, git_dir))
+        })
+        .collect()
+}
+
+/// The repository at `git_dir` from `repos`, opening it the first time.
+fn open_once<'a>(
+    repos: &'a mut HashMap<PathBuf, Repository>,
+    git_dir: &Path,
+) -> Result<&'a Repository, git2::Error> {
+    if !repos.contains_key(git_dir) {
+        repos.insert(git_dir.to_path_buf(), Repository::open(git_dir)?);
+    }
+    Ok(&repos[git_dir])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixture::{git, repo_with_file};
+    use crate::identity::{Attribute, Mailmap};
+    use crate::ownership::BOUNDARY_EMAIL;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_analyze() {
+        let repo = repo_with_file("file.txt", "one\ntwo\n", "Jane <jane@example.com>");
+        let path = repo.path().join("file.txt");
+        for method in &[
+            Method::Libgit2,
+            Method::Nom,
+            Method::NoExtra,
+            Method::Regex,
+            Method::Original,
+        ] {
+            let opts = Options {
+                method: *method,
+                ..Options::default()
+            };
+            let tracked_file = analyze(&path, &opts).unwrap();
+            assert_eq!(tracked_file.path, path.display().to_string());
+            assert_eq!(tracked_file.repo_path, PathBuf::from("file.txt"));
+            assert_eq!(tracked_file.owners["jane@example.com"].lines(), 2);
+        }
+
+        let opts = Options {
+            contents: Some("one\ntwo\nthree\n".to_string()),
+            ..Options::default()
+        };
+        let tracked_file = analyze(&path, &opts).unwrap();
+        assert_eq!(tracked_file.owners["jane@example.com"].lines(), 2);
+        assert_eq!(tracked_file.owners[NOT_COMMITTED_EMAIL].lines(), 1);
+    }
+
+    #[test]
+    fn test_analyze_applies_options() {
+        let repo = repo_with_file("file.txt", "one\n", "Jane <jane@example.com>");
+        let first = git(repo.path(), &["rev-parse", "HEAD"]).trim().to_string();
+        std::fs::write(repo.path().join("file.txt"), "one\ntwo\n").unwrap();
+        git(
+            repo.path(),
+            &["commit", "-qam", "two", "--author", "Joe <joe@example.com>"],
+        );
+        let second = git(repo.path(), &["rev-parse", "HEAD"]).trim().to_string();
+        std::fs::write(repo.path().join("file.txt"), "one\ntwo\nthree\n").unwrap();
+
+        // The owners found by `analyze`, which an `Analyzer` must agree with.
+        let path = repo.path().join("file.txt");
+        let owners = |opts: Options| {
+            let emails = |file: TrackedFile| {
+                let mut emails: Vec<String> = file.owners.keys().cloned().collect();
+                emails.sort();
+                emails
+            };
+            let owners = emails(analyze(&path, &opts).unwrap());
+            let analyzer = Analyzer::with_options(opts);
+            assert_eq!(emails(analyzer.analyze(&path).unwrap()), owners);
+            owners
+        };
+        assert_eq!(
+            owners(Options::default()),
+            ["jane@example.com", "joe@example.com", NOT_COMMITTED_EMAIL]
+        );
+        let opts = Options {
+            settings: BlameSettings {
+                after: Some(first),
+                ..BlameSettings::default()
+            },
+            ..Options::default()
+        };
+        assert_eq!(
+            owners(opts),
+            [BOUNDARY_EMAIL, "joe@example.com", NOT_COMMITTED_EMAIL]
+        );
+        let opts = Options {
+            ignored_revs: vec![second[..8].to_string()],
+            ..Options::default()
+        };
+        assert_eq!(owners(opts), ["jane@example.com", NOT_COMMITTED_EMAIL]);
+        let opts = Options {
+            skip_uncommitted: true,
+            ..Options::default()
+        };
+        assert_eq!(owners(opts), ["jane@example.com", "joe@example.com"]);
+        let opts = Options {
+            window: DateWindow::new(None, Some("2000-01-01")).unwrap(),
+            ..Options::default()
+        };
+        assert!(owners(opts).is_empty());
+    }
+
+    #[test]
+    fn test_relative_to() {
+        let root = Path::new("/repo");
+        assert_eq!(
+            relative_to(&root.join("src/lib.rs"), &root.join("docs/api")),
+            PathBuf::from("../../src/lib.rs")
+        );
+        assert_eq!(
+            relative_to(&root.join("src/lib.rs"), root),
+            PathBuf::from("src/lib.rs")
+        );
+    }
+
+    #[test]
+    fn test_git_time() {
+        let time = git_time(Time::new(86_400, -90)).unwrap();
+        assert_eq!(time.offset().local_minus_utc(), -90 * 60);
+        assert_eq!(time.timestamp(), 86_400);
+        // Time zones a day or more away from UTC, as in some broken commits.
+        assert!(git_time(Time::new(0, 24 * 60)).is_none());
+        assert!(git_time(Time::new(0, i32::MAX)).is_none());
+        assert!(git_time(Time::new(i64::MAX, 0)).is_none());
+    }
+
+    #[test]
+    fn test_split_existing() {
+        // A bare file name is taken relative to the current directory.
+        assert_eq!(
+            split_existing(Path::new("main.rs")),
+            (PathBuf::from("."), PathBuf::from("main.rs"))
+        );
+        assert_eq!(
+            split_existing(Path::new("no-such-dir/main.rs")),
+            (PathBuf::from("."), PathBuf::from("no-such-dir/main.rs"))
+        );
+
+        let dir = TempDir::new().unwrap();
+        assert_eq!(
+            split_existing(&dir.path().join("gone/main.rs")),
+            (dir.path().to_path_buf(), PathBuf::from("gone/main.rs"))
+        );
+    }
+
+    #[test]
+    fn test_blame_line_pattern() {
+        let pattern = blame_line_pattern();
+        let sha1 = "dbdf0caee4e14c03e5c3b8c7575219b3affe5657";
+        let sha256 = "3c5f0c4a9b2d1e8f7a6b5c4d3e2f1a0b9c8d7e6f5a4b3c2d1e0f9a8b7c6d5e4f";
+        for sha in &[sha1, sha256] {
+            let line = format!("{} 1 1 3", sha);
+            let cap = pattern.captures(&line).unwrap();
+            assert_eq!(&cap[1], *sha);
+            assert_eq!(&cap[2], "3");
+        }
+        assert!(pattern
+            .captures(&format!("{} 1 1 3", sha1.to_uppercase()))
+            .is_none());
+        assert!(pattern.captures(&format!("{}a 1 1 3", sha1)).is_none());
+    }
+
+    #[test]
+    fn test_counted_hunks() {
+        let input = "dbdf0caee4e14c03e5c3b8c7575219b3affe5657 1 1 3\n\
+                     \t// Comment\n\
+                     dbdf0caee4e14c03e5c3b8c7575219b3affe5657 2 2\n\
+                     \t\n\
+                     dbdf0caee4e14c03e5c3b8c7575219b3affe5657 3 3\n\
+                     \tfn main() {\n\
+                     85e11fdb2d3a6bbd5a8d79fbc8d8e0a5e2b2b0b2 4 4 1\n\
+                     \t}\n\
+                     dbdf0caee4e14c03e5c3b8c7575219b3affe5657 5 5 1\n\
+                     \t\n";
+        let lines = blame::parse_blame(input, |_| true).unwrap();
+        assert_eq!(
+            counted_hunks(&lines, &LineFilter::default()),
+            vec![
+                ("dbdf0caee4e14c03e5c3b8c7575219b3affe5657", 3),
+                ("85e11fdb2d3a6bbd5a8d79fbc8d8e0a5e2b2b0b2", 1),
+                ("dbdf0caee4e14c03e5c3b8c7575219b3affe5657", 1),
+            ]
+        );
+        assert_eq!(
+            counted_hunks(&lines, &LineFilter::new(true, Some("rust")).unwrap()),
+            vec![
+                ("dbdf0caee4e14c03e5c3b8c7575219b3affe5657", 1),
+                ("85e11fdb2d3a6bbd5a8d79fbc8d8e0a5e2b2b0b2", 1),
+            ]
+        );
+    }
+
+    /// Full porcelain header of a commit of src/main.rs by `author` with
+    /// `email`, who also committed it, following its first line.
+    fn header(author: &str, email: &str) -> String {
+        format!(
+            "author {0}\n\
+             author-mail <{1}>\n\
+             author-time 1586576941\n\
+             author-tz -0700\n\
+             committer {0}\n\
+             committer-mail <{1}>\n\
+             committer-time 1586577179\n\
+             committer-tz -0700\n\
+             summary Change\n\
+             filename src/main.rs\n",
+            author, email
+        )
+    }
+
+    #[test]
+    fn test_ownership_from_porcelain() {
+        let jane = "dbdf0caee4e14c03e5c3b8c7575219b3affe5657";
+        let joe = "85e11fdb2d3a6bbd5a8d79fbc8d8e0a5e2b2b0b2";
+        let porcelain = format!(
+            "{jane} 1 1 2\n{}\tfn main() {{\n\
+             {jane} 2 2\n\tlet a = 1;\n\
+             {joe} 3 3 1\n{}\tlet b = 2;\n\
+             {jane} 4 4 1\n\t}}\n",
+            header("Jane Doe", "jane@example.com"),
+            header("Joe", "joe@example.com"),
+            jane = jane,
+            joe = joe,
+        );
+
+        let tracked_file = ownership_from_porcelain("src/main.rs", &porcelain).unwrap();
+        assert_eq!(tracked_file.repo_path, PathBuf::from("src/main.rs"));
+        assert_eq!(tracked_file.lines(), 4);
+        let jane_owner = &tracked_file.owners["jane@example.com"];
+        assert_eq!(jane_owner.name, "Jane Doe");
+        assert_eq!(jane_owner.lines(), 3);
+        assert_eq!(jane_owner.commits.len(), 1);
+        assert_eq!(tracked_file.owners["joe@example.com"].lines(), 1);
+
+        // A commit seen without its header cannot be attributed.
+        let abridged = format!("{} 1 1 1\n\tfn main() {{\n", joe);
+        assert!(matches!(
+            ownership_from_porcelain("src/main.rs", &abridged),
+            Err(WhoKnowsError::Parse { line: 1, .. })
+        ));
+        assert!(ownership_from_porcelain("src/main.rs", "not porcelain\n").is_err());
+    }
+
+    #[test]
+    fn test_uncommitted() {
+        let zero = "0000000000000000000000000000000000000000";
+        let jane = "dbdf0caee4e14c03e5c3b8c7575219b3affe5657";
+        let porcelain = format!(
+            "{jane} 1 1 1\n{}\tfn main() {{\n\
+             {zero} 2 2 2\n{}\tlet a = 1;\n\
+             {zero} 3 3\n\tlet b = 2;\n",
+            header("Jane Doe", "jane@example.com"),
+            header("Not Committed Yet", "not.committed.yet"),
+            jane = jane,
+            zero = zero,
+        );
+
+        let mut tracked_file = ownership_from_porcelain("src/main.rs", &porcelain).unwrap();
+        let uncommitted = &tracked_file.owners[NOT_COMMITTED_EMAIL];
+        assert_eq!(uncommitted.lines(), 2);
+        assert!(uncommitted.is_synthetic());
+
+        tracked_file.skip_uncommitted();
+        assert_eq!(tracked_file.owners.len(), 1);
+        assert_eq!(tracked_file.owners["jane@example.com"].lines(), 1);
+
+        // The regex parser matches the zero hash too, crediting its lines to
+        // the same owner as the other parsers.
+        let line = format!("{} 2 2 2", zero);
+        assert!(blame_line_pattern().is_match(&line));
+        assert!(crate::ownership::is_uncommitted(zero));
+        assert!(!crate::ownership::is_uncommitted(jane));
+    }
+
+    #[test]
+    fn test_abridged_first_header() {
+        let repo = repo_with_file("file.txt", "one\n", "Jane <jane@example.com>");
+        let git2_repo = Repository::open(repo.path()).unwrap();
+        let hash = git2_repo.head().unwrap().target().unwrap().to_string();
+        let porcelain = format!("{} 1 1 1\n\tone\n", hash);
+
+        let mut tracked_file = TrackedFile::new("file.txt".to_string(), PathBuf::from("file.txt"));
+        add_porcelain(
+            &mut tracked_file,
+            &porcelain,
+            Some(&git2_repo),
+            &CommitCache::new(),
+            &Identities::default(),
+            &LineFilter::default(),
+        )
+        .unwrap();
+        assert_eq!(tracked_file.owners["jane@example.com"].name, "Jane");
+        assert_eq!(tracked_file.owners["jane@example.com"].lines(), 1);
+    }
+
+    #[test]
+    fn test_attribute_both() {
+        let repo = repo_with_file("file.txt", "one\ntwo\n", "Jane <jane@example.com>");
+        let path = repo.path().join("file.txt");
+        std::fs::write(&path, "one\ntwo\nthree\n").unwrap();
+        git(
+            repo.path(),
+            &[
+                "commit",
+                "-qam",
+                "Add",
+                "--author",
+                "Test <test@example.com>",
+            ],
+        );
+
+        for method in [Method::Nom, Method::NoExtra, Method::Regex] {
+            let mut identities = Identities::default();
+            identities.credit(Attribute::Both);
+            let tracked_file = Analyzer::new(method, identities, LineFilter::default(), Vec::new())
+                .analyze(&path)
+                .unwrap();
+            // The lines Test both wrote and committed are credited once.
+            assert_eq!(tracked_file.owners.len(), 3);
+            assert_eq!(tracked_file.owners["jane@example.com"].lines(), 2);
+            assert_eq!(tracked_file.owners["test@example.com"].lines(), 1);
+            let committer = &tracked_file.owners["test@example.com (committer)"];
+            assert_eq!(committer.name, "Test (committer)");
+            assert_eq!(committer.email, "test@example.com");
+            assert_eq!(committer.lines(), 2);
+            assert_eq!(tracked_file.lines(), 5);
+        }
+    }
+
+    #[test]
+    fn test_attribute_committer() {
+        let repo = repo_with_file("file.txt", "one\ntwo\n", "Jane <jane@example.com>");
+        let path = repo.path().join("file.txt");
+        std::fs::write(&path, "one\ntwo\nthree\n").unwrap();
+        git(repo.path(), &["commit", "-qam", "Add"]);
+        std::fs::write(&path, "one\ntwo\nthree\nfour\n").unwrap();
+
+        for method in [
+            Method::Libgit2,
+            Method::Nom,
+            Method::NoExtra,
+            Method::Regex,
+            Method::Numstat { net: false },
+        ] {
+            let mut identities = Identities::default();
+            identities.credit(Attribute::Committer);
+            let tracked_file = Analyzer::new(method, identities, LineFilter::default(), Vec::new())
+                .analyze(&path)
+                .unwrap();
+            // Every commit was made by the test committer, whoever wrote it.
+            assert_eq!(
+                tracked_file.owners["test@example.com"].lines(),
+                3,
+                "{:?}",
+                method
+            );
+            assert!(!tracked_file.owners.contains_key("jane@example.com"));
+            if let Method::Libgit2 | Method::Nom | Method::NoExtra = method {
+                // Lines not committed yet have no committer and keep their owner.
+                assert_eq!(tracked_file.owners[NOT_COMMITTED_EMAIL].lines(), 1);
+            }
+        }
+    }
+
+    #[test]
+    fn test_mailmap() {
+        let repo = repo_with_file("file.txt", "one\n", "Jane <jane@old.example.com>");
+        std::fs::write(repo.path().join("file.txt"), "one\ntwo\n").unwrap();
+        git(
+            repo.path(),
+            &[
+                "commit",
+                "-qam",
+                "Add line",
+                "--author",
+                "Jane Doe <jane@example.com>",
+            ],
+        );
+
+        let mailmap = Mailmap::parse("Jane Doe <jane@example.com> <jane@old.example.com>\n");
+        for method in [
+            Method::Libgit2,
+            Method::Nom,
+            Method::NoExtra,
+            Method::Regex,
+            Method::Original,
+        ] {
+            let mut identities = Identities::default();
+            identities.use_mailmap(mailmap.clone());
+            let tracked_file = Analyzer::new(method, identities, LineFilter::default(), Vec::new())
+                .analyze(&repo.path().join("file.txt"))
+                .unwrap();
+            assert_eq!(tracked_file.owners.len(), 1);
+            let owner = &tracked_file.owners["jane@example.com"];
+            assert_eq!(owner.name, "Jane Doe");
+            assert_eq!(owner.lines(), 2);
+        }
+    }
+
+    #[test]
+    fn test_blame_after() {
+        let repo = repo_with_file("file.txt", "one\n", "Jane <jane@example.com>");
+        let path = repo.path().join("file.txt");
+        let commit = |contents: &str, author: &str| {
+            std::fs::write(&path, contents).unwrap();
+            git(repo.path(), &["commit", "-qam", "Edit", "--author", author]);
+        };
+        commit("one\ntwo\n", "Joe <joe@example.com>");
+        commit("one\ntwo\nthree\n", "Ann <ann@example.com>");
+
+        let boundary = git(repo.path(), &["rev-parse", "HEAD~1"])
+            .trim()
+            .to_string();
+        for method in [Method::Libgit2, Method::Nom, Method::NoExtra, Method::Regex] {
+            let mut analyzer = Analyzer::new(
+                method,
+                Identities::default(),
+                LineFilter::default(),
+                Vec::new(),
+            );
+            analyzer.blame_after(boundary.clone());
+            let tracked_file = analyzer.analyze(&path).unwrap();
+            assert_eq!(tracked_file.owners.len(), 2);
+            assert_eq!(tracked_file.owners["ann@example.com"].lines(), 1);
+            let bucket = &tracked_file.owners[BOUNDARY_EMAIL];
+            assert_eq!(bucket.name, "Boundary");
+            assert_eq!(bucket.lines(), 2);
+            assert!(bucket.is_synthetic());
+            assert!(!tracked_file.owners["ann@example.com"].is_synthetic());
+        }
+    }
+
+    #[test]
+    fn test_blame_rev() {
+        let repo = repo_with_file("file.txt", "one\n", "Jane <jane@example.com>");
+        let path = repo.path().join("file.txt");
+        std::fs::write(&path, "one\ntwo\n").unwrap();
+        git(
+            repo.path(),
+            &[
+                "commit",
+                "-qam",
+                "Edit",
+                "--author",
+                "Joe <joe@example.com>",
+            ],
+        );
+        // Neither the working tree nor the last commit is blamed.
+        std::fs::write(&path, "one\ntwo\nthree\n").unwrap();
+
+        for method in [Method::Libgit2, Method::Nom, Method::NoExtra, Method::Regex] {
+            let analyzer = Analyzer::with_options(Options {
+                method,
+                settings: BlameSettings {
+                    rev: Some("HEAD~1".to_string()),
+                    ..BlameSettings::default()
+                },
+                ..Options::default()
+            });
+            assert!(analyzer.git_only().is_empty());
+            let tracked_file = analyzer.analyze(&path).unwrap();
+            assert_eq!(tracked_file.owners.len(), 1, "{:?}", method);
+            assert_eq!(tracked_file.owners["jane@example.com"].lines(), 1);
+        }
+    }
+
+    #[test]
+    fn test_path_styles() {
+        let repo = repo_with_file("src/lib.rs", "one\n", "Jane <jane@example.com>");
+        let root = repo.path().canonicalize().unwrap();
+        let path = root.join("src").join("lib.rs");
+
+        for style in [
+            PathStyle::Given,
+            PathStyle::Absolute,
+            PathStyle::RepoRelative,
+        ] {
+            let paths: Vec<String> = [Method::Nom, Method::NoExtra, Method::Regex]
+                .iter()
+                .map(|method| {
+                    let mut analyzer = Analyzer::new(
+                        *method,
+                        Identities::default(),
+                        LineFilter::default(),
+                        Vec::new(),
+                    );
+                    analyzer.show_paths(style, true);
+                    analyzer.analyze(&path).unwrap().path
+                })
+                .collect();
+            let expected = match style {
+                PathStyle::RepoRelative => "src/lib.rs".to_string(),
+                _ => path.display().to_string(),
+            };
+            assert_eq!(paths, vec![expected; 3], "{:?}", style);
+        }
+    }
+
+    #[test]
+    fn test_empty_file() {
+        let repo = repo_with_file("empty.txt", "", "Jane <jane@example.com>");
+        std::fs::write(repo.path().join("blank.txt"), "\n").unwrap();
+        git(repo.path(), &["add", "blank.txt"]);
+        git(repo.path(), &["commit", "-qm", "Add blank line"]);
+
+        for method in [
+            Method::Libgit2,
+            Method::Nom,
+            Method::NoExtra,
+            Method::Regex,
+            Method::Original,
+        ] {
+            let analyzer = Analyzer::new(
+                method,
+                Identities::default(),
+                LineFilter::new(true, None).unwrap(),
+                Vec::new(),
+            );
+            let empty = analyzer.analyze(&repo.path().join("empty.txt")).unwrap();
+            assert!(empty.empty);
+            assert!(empty.owners.is_empty());
+
+            // A file whose lines are all filtered out is not empty.
+            let blank = analyzer.analyze(&repo.path().join("blank.txt")).unwrap();
+            assert!(!blank.empty);
+        }
+    }
+
+    #[test]
+    fn test_no_extra_matches_full_headers() {
+        let repo = repo_with_file("file.txt", "one\ntwo\n", "Jane <jane@example.com>");
+        let path = repo.path().join("file.txt");
+        std::fs::write(&path, "one\ntwo\nthree\n").unwrap();
+        git(
+            repo.path(),
+            &[
+                "commit",
+                "-qam",
+                "Extend",
+                "--author",
+                "Joe <joe@example.com>",
+            ],
+        );
+        std::fs::write(&path, "one\ntwo\nthree\nfour\n").unwrap();
+
+        let identities = Identities::default();
+        let git_repo = Repository::discover(repo.path()).unwrap();
+        let full = analyze_file_nom(
+            &git_repo,
+            &path,
+            &CommitCache::new(),
+            &identities,
+            &LineFilter::default(),
+            &[],
+            None,
+        )
+        .unwrap();
+        let no_extra = analyze_file_no_extra(
+            &git_repo,
+            &path,
+            &identities,
+            &LineFilter::default(),
+            &[],
+            None,
+        )
+        .unwrap();
+
+        assert_eq!(no_extra.owners.len(), 3);
+        for (email, owner) in &full.owners {
+            assert_eq!(no_extra.owners[email].name, owner.name);
+            assert_eq!(no_extra.owners[email].lines(), owner.lines());
+        }
+    }
+
+    #[test]
+    fn test_analyze_all_matches_analyze_par() {
+        let repo = repo_with_file("file.txt", "one\ntwo\n", "Jane <jane@example.com>");
+        let other = repo_with_file("other.txt", "one\n", "Joe <joe@example.com>");
+        let outside = TempDir::new().unwrap();
+        std::fs::write(outside.path().join("loose.txt"), "one\n").unwrap();
+        let paths = vec![
+            repo.path().join("file.txt"),
+            repo.path().join("missing.txt"),
+            other.path().join("other.txt"),
+            outside.path().join("loose.txt"),
+        ];
+        let analyzer = Analyzer::new(
+            Method::Nom,
+            Identities::default(),
+            LineFilter::default(),
+            Vec::new(),
+        );
+
+        let mut all = analyzer.analyze_all(&paths);
+        let first = all.next().unwrap().unwrap();
+        assert!(all.next().unwrap().is_err());
+        let second = all.next().unwrap().unwrap();
+        assert!(all.next().unwrap().is_err());
+        assert!(all.next().is_none());
+
+        // Files from several repositories, and outside any, in one batch.
+        let results = analyzer.analyze_par(&paths);
+        assert_eq!(results.len(), 4);
+        assert!(results[1].is_err());
+        assert!(results[3].is_err());
+        let par: Vec<TrackedFile> = results.into_iter().filter_map(Result::ok).collect();
+        assert_eq!(par.len(), 2);
+        assert_eq!(par[0].path, first.path);
+        assert_eq!(par[0].lines(), first.lines());
+        assert_eq!(par[1].path, second.path);
+        assert_eq!(par[1].owners["joe@example.com"].lines(), 1);
+    }
+
+    #[test]
+    fn test_head_ignores_working_tree() {
+        let repo = repo_with_file("file.txt", "one\ntwo\n", "Jane <jane@example.com>");
+        let path = repo.path().join("file.txt");
+        std::fs::write(&path, "one\ntwo\nthree\n").unwrap();
+
+        let analyzer = Analyzer::new(
+            Method::Nom,
+            Identities::default(),
+            LineFilter::default(),
+            vec!["HEAD".to_string()],
+        );
+        let tracked_file = analyzer.analyze(&path).unwrap();
+        assert_eq!(tracked_file.owners.len(), 1);
+        assert_eq!(tracked_file.owners["jane@example.com"].lines(), 2);
+    }
+
+    #[test]
+    fn test_deleted_file_at_rev() {
+        let repo = repo_with_file("old/removed.txt", "one\ntwo\n", "Jane <jane@example.com>");
+        git(repo.path(), &["tag", "v1.0"]);
+        git(repo.path(), &["rm", "-qr", "old"]);
+        git(repo.path(), &["commit", "-qm", "Remove"]);
+
+        let path = repo.path().join("old/removed.txt");
+        assert!(!path.exists());
+
+        for method in [Method::Nom, Method::NoExtra, Method::Regex] {
+            let analyzer = Analyzer::new(
+                method,
+                Identities::default(),
+                LineFilter::default(),
+                vec!["v1.0".to_string()],
+            );
+            let tracked_file = analyzer.analyze(&path).unwrap();
+            assert_eq!(tracked_file.repo_path, PathBuf::from("old/removed.txt"));
+            assert_eq!(tracked_file.owners["jane@example.com"].lines(), 2);
+        }
+    }
+
+    #[test]
+    fn test_numstat_backend() {
+        let repo = repo_with_file("file.txt", "one\ntwo\n", "Jane <jane@example.com>");
+        let path = repo.path().join("file.txt");
+        std::fs::write(&path, "three\n").unwrap();
+        git(
+            repo.path(),
+            &[
+                "commit",
+                "-qam",
+                "Rewrite",
+                "--author",
+                "Joe <joe@example.com>",
+            ],
+        );
+
+        let analyze = |net| {
+            Analyzer::new(
+                Method::Numstat { net },
+                Identities::default(),
+                LineFilter::default(),
+                Vec::new(),
+            )
+            .analyze(&path)
+            .unwrap()
+        };
+        let added = analyze(false);
+        assert_eq!(added.repo_path, PathBuf::from("file.txt"));
+        assert_eq!(added.owners["jane@example.com"].lines(), 2);
+        assert_eq!(added.owners["joe@example.com"].lines(), 1);
+
+        // Joe added one line and deleted two.
+        let net = analyze(true);
+        assert_eq!(net.owners.len(), 1);
+        assert_eq!(net.owners["jane@example.com"].lines(), 2);
+    }
+
+    #[test]
+    fn test_original_author() {
+        let repo = repo_with_file("file.txt", "one\ntwo\nthree\n", "Jane <jane@example.com>");
+        let path = repo.path().join("file.txt");
+        let commit = |contents: &str, author: &str| {
+            std::fs::write(&path, contents).unwrap();
+            git(repo.path(), &["commit", "-qam", "Edit", "--author", author]);
+        };
+        commit("one\nTWO\nthree\nfour\n", "Joe <joe@example.com>");
+        commit("one\nTwo\nthree\nfour\n", "Ann <ann@example.com>");
+
+        let analyze = |method| {
+            Analyzer::new(
+                method,
+                Identities::default(),
+                LineFilter::default(),
+                Vec::new(),
+            )
+            .analyze(&path)
+            .unwrap()
+        };
+        let latest = analyze(Method::Nom);
+        assert_eq!(latest.owners["jane@example.com"].lines(), 2);
+        assert_eq!(latest.owners["ann@example.com"].lines(), 1);
+
+        // The line Ann and Joe edited goes back to Jane, while the line Joe
+        // added stays his.
+        let original = analyze(Method::Original);
+        assert_eq!(original.owners.len(), 2);
+        assert_eq!(original.owners["jane@example.com"].lines(), 3);
+        assert_eq!(original.owners["joe@example.com"].lines(), 1);
+    }
+
+    #[test]
+    fn test_original_author_follows_moves() {
+        let moved = "alpha is a line long enough to move\n\
+                     beta is a line long enough to move\n";
+        let filler = "one\ntwo\nthree\nfour\n";
+        let repo = repo_with_file(
+            "file.txt",
+            &format!("{}{}", moved, filler),
+            "Jane <jane@example.com>",
+        );
+        let path = repo.path().join("file.txt");
+        let commit = |contents: &str, author: &str| {
+            std::fs::write(&path, contents).unwrap();
+            git(repo.path(), &["commit", "-qam", "Edit", "--author", author]);
+        };
+        commit(&format!("{}{}", filler, moved), "Joe <joe@example.com>");
+        commit(
+            &format!("{}{}", filler, moved.replace("beta", "BETA")),
+            "Ann <ann@example.com>",
+        );
+
+        let analyze = |blame_args: &[&str]| {
+            let original = Analyzer::new(
+                Method::Original,
+                Identities::default(),
+                LineFilter::default(),
+                blame_args.iter().map(|arg| arg.to_string()).collect(),
+            )
+            .analyze(&path)
+            .unwrap();
+            let mut owners: Vec<(String, usize)> = original
+                .owners
+                .values()
+                .map(|o| (o.email.to_string(), o.lines()))
+                .collect();
+            owners.sort();
+            owners
+        };
+        // Without -M, the line Ann edited goes back to Joe, who moved it,
+        // and with it to Jane, who wrote it.
+        assert_eq!(
+            analyze(&[]),
+            [
+                ("jane@example.com".to_string(), 4),
+                ("joe@example.com".to_string(), 2)
+            ]
+        );
+        assert_eq!(analyze(&["-M"]), [("jane@example.com".to_string(), 6)]);
+    }
+
+    #[test]
+    fn test_ignore_whitespace() {
+        let repo = repo_with_file("file.txt", "one\ntwo\n", "Jane <jane@example.com>");
+        let path = repo.path().join("file.txt");
+        std::fs::write(&path, "    one\n    two\n").unwrap();
+        git(
+            repo.path(),
+            &[
+                "commit",
+                "-qam",
+                "Reindent",
+                "--author",
+                "Joe <joe@example.com>",
+            ],
+        );
+
+        // The original author is followed through the reindent either way,
+        // with libgit2 running git for it.
+        for method in [Method::Libgit2, Method::Nom, Method::NoExtra, Method::Regex] {
+            let analyze = |ignore_whitespace: bool| {
+                let analyzer = Analyzer::with_options(Options {
+                    method,
+                    settings: BlameSettings {
+                        ignore_whitespace,
+                        ..BlameSettings::default()
+                    },
+                    ..Options::default()
+                });
+                assert_eq!(analyzer.git_only().is_empty(), !ignore_whitespace);
+                analyzer.analyze(&path).unwrap()
+            };
+            assert!(analyze(false).owners.contains_key("joe@example.com"));
+            let ignoring = analyze(true);
+            assert_eq!(ignoring.owners.len(), 1);
+            assert_eq!(ignoring.owners["jane@example.com"].lines(), 2);
+        }
+    }
+
+    #[test]
+    fn test_detect_copies() {
+        let lines = "fn first_function_with_a_long_name() {}\n\
+                     fn second_function_with_a_long_name() {}\n";
+        let repo = repo_with_file("a.rs", lines, "Jane <jane@example.com>");
+        std::fs::write(repo.path().join("a.rs"), "").unwrap();
+        std::fs::write(repo.path().join("b.rs"), lines).unwrap();
+        git(repo.path(), &["add", "a.rs", "b.rs"]);
+        git(
+            repo.path(),
+            &["commit", "-qm", "Move", "--author", "Joe <joe@example.com>"],
+        );
+
+        let path = repo.path().join("b.rs");
+        for method in [Method::Libgit2, Method::Nom, Method::NoExtra, Method::Regex] {
+            let analyze = |detect_copies: bool| {
+                let analyzer = Analyzer::with_options(Options {
+                    method,
+                    settings: BlameSettings {
+                        detect_copies,
+                        ..BlameSettings::default()
+                    },
+                    ..Options::default()
+                });
+                assert_eq!(analyzer.git_only().is_empty(), !detect_copies);
+                let tracked_file = analyzer.analyze(&path).unwrap();
+                tracked_file.owners.keys().cloned().collect::<Vec<_>>()
+            };
+            assert_eq!(analyze(false), ["joe@example.com"]);
+            assert_eq!(analyze(true), ["jane@example.com"]);
+        }
+    }
+
+    #[test]
+    fn test_regex_counts_uncommitted_lines() {
+        let repo = repo_with_file("file.txt", "one\n", "Jane <jane@example.com>");
+        let path = repo.path().join("file.txt");
+        std::fs::write(&path, "one\nuncommitted\n").unwrap();
+
+        let git_repo = Repository::discover(repo.path()).unwrap();
+        let tracked_file =
+            analyze_file(&git_repo, &path, &Identities::default(), &[], None).unwrap();
+        assert_eq!(tracked_file.owners.len(), 2);
+        assert_eq!(tracked_file.owners["jane@example.com"].lines(), 1);
+        assert_eq!(tracked_file.owners[NOT_COMMITTED_EMAIL].lines(), 1);
+        assert!(tracked_file.warnings.is_empty());
+
+        // The same owners as with the other methods.
+        for method in [Method::Libgit2, Method::Nom, Method::NoExtra] {
+            let other = Analyzer::new(
+                method,
+                Identities::default(),
+                LineFilter::default(),
+                Vec::new(),
+            )
+            .analyze(&path)
+            .unwrap();
+            assert_eq!(other.owners.len(), 2);
+            assert_eq!(other.owners[NOT_COMMITTED_EMAIL].lines(), 1);
+        }
+    }
+
+    #[test]
+    fn test_linked_worktree() {
+        let repo = repo_with_file("file.txt", "one\ntwo\n", "Jane <jane@example.com>");
+        let worktree = TempDir::new().unwrap();
+        let worktree_path = worktree.path().join("wt");
+        git(
+            repo.path(),
+            &["worktree", "add", "-q", worktree_path.to_str().unwrap()],
+        );
+
+        let path = worktree_path.join("file.txt");
+        let identities = Identities::default();
+        let git_repo = Repository::discover(&worktree_path).unwrap();
+        let nom = analyze_file_nom(
+            &git_repo,
+            &path,
+            &CommitCache::new(),
+            &identities,
+            &LineFilter::default(),
+            &[],
+            None,
+        )
+        .unwrap();
+        let regex = analyze_file(&git_repo, &path, &identities, &[], None).unwrap();
+
+        assert_eq!(nom.repo_path, PathBuf::from("file.txt"));
+        assert_eq!(regex.repo_path, PathBuf::from("file.txt"));
+        assert_eq!(regex.path, "file.txt");
+        assert_eq!(nom.owners["jane@example.com"].lines(), 2);
+        assert_eq!(regex.owners["jane@example.com"].lines(), 2);
+    }
+
+    #[test]
+    fn test_blame_contents() {
+        let repo = repo_with_file("file.txt", "one\n", "Jane <jane@example.com>");
+        let path = repo.path().join("file.txt");
+        for method in &[Method::Nom, Method::NoExtra, Method::Regex] {
+            let mut analyzer = Analyzer::new(
+                *method,
+                Identities::default(),
+                LineFilter::default(),
+                Vec::new(),
+            );
+            analyzer.blame_contents("one\ntwo\nthree\n".to_string());
+            let mut tracked_file = analyzer.analyze(&path).unwrap();
+            assert_eq!(tracked_file.owners["jane@example.com"].lines(), 1);
+            tracked_file.label_synthetic("Uncommitted", "Older");
+            let uncommitted = &tracked_file.owners[NOT_COMMITTED_EMAIL];
+            assert_eq!(uncommitted.name, "Uncommitted", "{:?}", method);
+            assert_eq!(uncommitted.lines(), 2);
+            assert!(uncommitted.is_synthetic());
+
+            analyzer.skip_uncommitted();
+            let tracked_file = analyzer.analyze(&path).unwrap();
+            assert_eq!(tracked_file.owners.len(), 1);
+        }
+    }
+
+    #[test]
+    fn test_ignore_revs() {
+        let repo = repo_with_file("file.txt", "one\n", "Jane <jane@example.com>");
+        std::fs::write(repo.path().join("file.txt"), "one\ntwo\nthree\n").unwrap();
+        git(repo.path(), &["add", "file.txt"]);
+        git(
+            repo.path(),
+            &["commit", "-qm", "bulk", "--author", "Joe <joe@example.com>"],
+        );
+        let bulk = git(repo.path(), &["rev-parse", "--short=8", "HEAD"])
+            .trim()
+            .to_string();
+
+        let path = repo.path().join("file.txt");
+        for method in &[Method::Nom, Method::NoExtra, Method::Regex] {
+            let mut analyzer = Analyzer::new(
+                *method,
+                Identities::default(),
+                LineFilter::default(),
+                Vec::new(),
+            );
+            analyzer.ignore_revs(vec![bulk.clone()]);
+            let tracked_file = analyzer.analyze(&path).unwrap();
+            assert_eq!(tracked_file.owners.len(), 1);
+            assert_eq!(tracked_file.owners["jane@example.com"].lines(), 1);
+        }
+    }
+
+    #[test]
+    fn test_ignore_revs_reattributed() {
+        let repo = repo_with_file("file.txt", "one\ntwo\n", "Jane <jane@example.com>");
+        std::fs::write(repo.path().join("file.txt"), "ONE\nTWO\n").unwrap();
+        git(repo.path(), &["add", "file.txt"]);
+        git(
+            repo.path(),
+            &[
+                "commit",
+                "-qm",
+                "reformat",
+                "--author",
+                "Joe <joe@example.com>",
+            ],
+        );
+        let reformat = git(repo.path(), &["rev-parse", "HEAD"]).trim().to_string();
+
+        // The lines are credited to the commit before the ignored one, rather
+        // than left out, with libgit2 running git for it.
+        let path = repo.path().join("file.txt");
+        for method in &[Method::Libgit2, Method::Nom, Method::NoExtra, Method::Regex] {
+            let mut analyzer = Analyzer::with_options(Options {
+                method: *method,
+                settings: BlameSettings {
+                    ignore_revs: vec![reformat.clone()],
+                    ..BlameSettings::default()
+                },
+                ..Options::default()
+            });
+            analyzer.ignore_revs(vec![reformat[..10].to_string()]);
+            let tracked_file = analyzer.analyze(&path).unwrap();
+            assert_eq!(tracked_file.owners.len(), 1);
+            assert_eq!(tracked_file.owners["jane@example.com"].lines(), 2);
+        }
+    }
+
+    #[test]
+    fn test_date_window() {
+        let repo = repo_with_file("file.txt", "one\n", "Jane <jane@example.com>");
+        git(
+            repo.path(),
+            &[
+                "commit",
+                "-q",
+                "--amend",
+                "--no-edit",
+                "--date",
+                "2020-06-01T12:00:00+00:00",
+            ],
+        );
+        std::fs::write(repo.path().join("file.txt"), "one\ntwo\n").unwrap();
+        git(
+            repo.path(),
+            &[
+                "commit",
+                "-qam",
+                "two",
+                "--author",
+                "Joe <joe@example.com>",
+                "--date",
+                "2022-06-01T12:00:00+00:00",
+            ],
+        );
+
+        let path = repo.path().join("file.txt");
+        for method in &[Method::Nom, Method::NoExtra, Method::Regex] {
+            let analyze = |since: Option<&str>, until: Option<&str>| {
+                let mut analyzer = Analyzer::new(
+                    *method,
+                    Identities::default(),
+                    LineFilter::default(),
+                    Vec::new(),
+                );
+                analyzer.keep_within(DateWindow::new(since, until).unwrap());
+                let tracked_file = analyzer.analyze(&path).unwrap();
+                let mut emails: Vec<String> = tracked_file.owners.keys().cloned().collect();
+                emails.sort();
+                emails
+            };
+            assert_eq!(analyze(None, None), ["jane@example.com", "joe@example.com"]);
+            assert_eq!(analyze(Some("2021-01-01"), None), ["joe@example.com"]);
+            assert_eq!(analyze(None, Some("2020-06-01")), ["jane@example.com"]);
+            assert_eq!(
+                analyze(Some("2020-06-01T12:00:01+00:00"), Some("2022-01-01")),
+                Vec::<String>::new()
+            );
+        }
+
+        assert!(DateWindow::new(Some("last week"), None).is_err());
+        assert!(DateWindow::new(None, Some("2023-02-30")).is_err());
+    }
+
+    #[test]
+    fn test_line_range() {
+        let repo = repo_with_file("file.txt", "one\ntwo\n", "Jane <jane@example.com>");
+        std::fs::write(repo.path().join("file.txt"), "one\ntwo\nthree\nfour\n").unwrap();
+        git(
+            repo.path(),
+            &[
+                "commit",
+                "-qam",
+                "more",
+                "--author",
+                "Joe <joe@example.com>",
+            ],
+        );
+
+        let path = repo.path().join("file.txt");
+        for method in [
+            Method::Libgit2,
+            Method::Nom,
+            Method::NoExtra,
+            Method::Regex,
+            Method::Original,
+        ] {
+            let analyzer = Analyzer::with_options(Options {
+                method,
+                settings: BlameSettings {
+                    lines: Some((2, 3)),
+                    ..BlameSettings::default()
+                },
+                ..Options::default()
+            });
+            assert!(analyzer.git_only().is_empty());
+            let tracked_file = analyzer.analyze(&path).unwrap();
+            assert_eq!(tracked_file.lines(), 2);
+            assert_eq!(tracked_file.owners["jane@example.com"].lines(), 1);
+            assert_eq!(tracked_file.owners["joe@example.com"].lines(), 1);
+        }
+    }
+
+    #[test]
+    fn test_blame_date_option() {
+        let repo = repo_with_file("file.txt", "one\n", "Jane <jane@example.com>");
+        let path = repo.path().join("file.txt");
+        let dates = |blame_args: Vec<String>| {
+            let tracked_file = Analyzer::new(
+                Method::Nom,
+                Identities::default(),
+                LineFilter::default(),
+                blame_args,
+            )
+            .analyze(&path)
+            .unwrap();
+            tracked_file.owners["jane@example.com"].dates.clone()
+        };
+        // The porcelain times are not affected by how git would show dates.
+        assert_eq!(dates(vec!["--date=local".to_string()]), dates(Vec::new()));
+    }
+}
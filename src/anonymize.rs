@@ -0,0 +1,257 @@
+use git_whoknows::ownership::{is_uncommitted, Owner};
+use git_whoknows::TrackedFile;
+
+use chrono::{DateTime, FixedOffset, TimeZone};
+use std::collections::{BTreeMap, HashMap};
+use std::hash::BuildHasher;
+
+/// Name and email standing in for a person, with the name they had.
+pub struct Pseudonym {
+    pub name: String,
+    pub email: String,
+    real_name: String,
+}
+
+/// Pick a pseudonym for each person owning lines in `files`, by their
+/// lowercased email.
+///
+/// People are lettered in the order of the hash of their email keyed by
+/// `salt`, so the same people get the same pseudonyms whatever order the
+/// files are in, while without the salt the letters say nothing about who
+/// they stand for. Owners standing in for lines rather than people keep
+/// their names.
+pub fn pseudonyms(files: &[TrackedFile], salt: &impl BuildHasher) -> HashMap<String, Pseudonym> {
+    let mut people: BTreeMap<(u64, String), String> = BTreeMap::new();
+    files
+        .iter()
+        .flat_map(|file| file.owners.values())
+        .filter(|owner| !owner.is_synthetic())
+        .for_each(|owner| {
+            let email = owner.email.to_lowercase();
+            people
+                .entry((salt.hash_one(&email), email))
+                .or_insert_with(|| owner.name.to_string());
+        });
+    people
+        .into_iter()
+        .enumerate()
+        .map(|(i, ((_, email), real_name))| {
+            let letters = letters(i);
+            let pseudonym = Pseudonym {
+                name: format!("Contributor {}", letters),
+                email: format!("contributor-{}@anonymous.invalid", letters.to_lowercase()),
+                real_name,
+            };
+            (email, pseudonym)
+        })
+        .collect()
+}
+
+/// Replace the names and emails of the owners of `file` with their
+/// pseudonyms, dropping the identities and commit summaries that could give
+/// them away.
+///
+/// Commit hashes, which would lead back to the authors through the history,
+/// are replaced by hashes of them keyed by `salt`, and author times are
+/// rounded down to the start of their day in UTC, dropping the time zone.
+pub fn apply(
+    file: &mut TrackedFile,
+    pseudonyms: &HashMap<String, Pseudonym>,
+    salt: &impl BuildHasher,
+) {
+    file.owners = file
+        .owners
+        .drain()
+        .map(|(key, mut owner)| {
+            hide_commits(&mut owner, salt);
+            match pseudonyms.get(&owner.email.to_lowercase()) {
+                Some(pseudonym) => {
+                    // Committers are keyed and named with a suffix after the email.
+                    let suffix = key
+                        .strip_prefix(owner.email.to_lowercase().as_str())
+                        .unwrap_or("");
+                    owner.name = format!("{}{}", pseudonym.name, suffix);
+                    owner.email = pseudonym.email.to_string();
+                    owner.aliases.clear();
+                    owner.summaries.clear();
+                    (format!("{}{}", pseudonym.email, suffix), owner)
+                }
+                None => (key, owner),
+            }
+        })
+        .collect();
+}
+
+/// Rekey the commits of `owner` by their pseudonymous hashes, with their
+/// dates rounded to the day.
+fn hide_commits(owner: &mut Owner, salt: &impl BuildHasher) {
+    owner.commits = owner
+        .commits
+        .drain()
+        .map(|(hash, lines)| (commit(&hash, salt), lines))
+        .collect();
+    owner.dates = owner
+        .dates
+        .drain()
+        .map(|(hash, date)| (commit(&hash, salt), day(date)))
+        .collect();
+    owner.summaries = owner
+        .summaries
+        .drain()
+        .map(|(hash, summary)| (commit(&hash, salt), summary))
+        .collect();
+}
+
+/// Hash standing in for the commit `hash`, the same for each commit
+/// throughout the run. The all-zero hash of lines not committed yet is kept,
+/// as it names no commit.
+fn commit(hash: &str, salt: &impl BuildHasher) -> String {
+    if is_uncommitted(hash) {
+        hash.to_string()
+    } else {
+        format!("{:016x}", salt.hash_one(hash))
+    }
+}
+
+/// Start of the day of `date` in UTC.
+fn day(date: DateTime<FixedOffset>) -> DateTime<FixedOffset> {
+    let utc = FixedOffset::east_opt(0).expect("UTC is a valid offset.");
+    let midnight = date
+        .naive_utc()
+        .date()
+        .and_hms_opt(0, 0, 0)
+        .expect("Midnight is a valid time.");
+    utc.from_utc_datetime(&midnight)
+}
+
+/// Email an owner with `email` is given by `apply`, their pseudonym's or else
+/// their own.
+pub fn email(pseudonyms: &HashMap<String, Pseudonym>, email: &str) -> String {
+    match pseudonyms.get(&email.to_lowercase()) {
+        Some(pseudonym) => pseudonym.email.to_string(),
+        None => email.to_string(),
+    }
+}
+
+/// Tab separated lines of each pseudonym with the real name and email it
+/// stands for, ordered by pseudonym.
+pub fn key(pseudonyms: &HashMap<String, Pseudonym>) -> String {
+    let mut lines: Vec<(usize, String)> = pseudonyms
+        .iter()
+        .map(|(email, p)| {
+            (
+                p.name.len(),
+                format!("{}\t{}\t{}\t{}\n", p.name, p.email, p.real_name, email),
+            )
+        })
+        .collect();
+    lines.sort();
+    lines.into_iter().map(|(_, line)| line).collect()
+}
+
+/// Letters numbering `i` from A, going on to AA after Z.
+fn letters(i: usize) -> String {
+    let mut letters = Vec::new();
+    let mut n = i + 1;
+    while n > 0 {
+        n -= 1;
+        letters.push((b'A' + (n % 26) as u8) as char);
+        n /= 26;
+    }
+    letters.iter().rev().collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixture::{self, hunk};
+    use git_whoknows::identity::{Attribute, Identities};
+    use git_whoknows::ownership::BasicHunk;
+    use std::collections::hash_map::RandomState;
+
+    fn tracked_file(path: &str, owners: &[(&str, &str)], identities: &Identities) -> TrackedFile {
+        let mut file = fixture::tracked_file(path, &[]);
+        for (i, (name, email)) in owners.iter().enumerate() {
+            let hunk = BasicHunk {
+                author: name.to_string(),
+                summary: Some(format!("Work by {}", name)),
+                committer: Some(("Lead".to_string(), "lead@example.com".to_string())),
+                ..hunk(&i.to_string(), email, 1)
+            };
+            file.add_hunk(&hunk, identities);
+        }
+        file
+    }
+
+    #[test]
+    fn test_letters() {
+        assert_eq!(letters(0), "A");
+        assert_eq!(letters(25), "Z");
+        assert_eq!(letters(26), "AA");
+        assert_eq!(letters(27), "AB");
+        assert_eq!(letters(26 * 27), "AAA");
+    }
+
+    #[test]
+    fn test_anonymize() {
+        let mut identities = Identities::default();
+        identities.credit(Attribute::Both);
+        let mut files = vec![
+            tracked_file(
+                "a",
+                &[("Jane", "jane@example.com"), ("Joe", "joe@example.com")],
+                &identities,
+            ),
+            tracked_file(
+                "b",
+                &[
+                    ("Joe", "joe@example.com"),
+                    ("Not Committed Yet", "not.committed.yet"),
+                ],
+                &identities,
+            ),
+        ];
+        let salt = RandomState::new();
+        let pseudonyms = pseudonyms(&files, &salt);
+        assert_eq!(pseudonyms.len(), 3);
+
+        // The same people get the same pseudonyms whatever the file order.
+        files.reverse();
+        let reversed = super::pseudonyms(&files, &salt);
+        for (email, pseudonym) in &pseudonyms {
+            assert_eq!(reversed[email].name, pseudonym.name);
+        }
+
+        files
+            .iter_mut()
+            .for_each(|file| apply(file, &pseudonyms, &salt));
+        let joe = &pseudonyms["joe@example.com"];
+        for file in &files {
+            assert!(file.owners.contains_key(&joe.email));
+            assert!(file
+                .owners
+                .values()
+                .all(|o| !o.email.ends_with("@example.com")));
+            assert!(file
+                .owners
+                .values()
+                .filter(|o| !o.is_synthetic())
+                .all(|o| o.aliases.is_empty() && o.summaries.is_empty()));
+        }
+        let lead = &pseudonyms["lead@example.com"];
+        let committer = &files[0].owners[&format!("{} (committer)", lead.email)];
+        assert_eq!(committer.name, format!("{} (committer)", lead.name));
+        assert_eq!(files[0].path, "b");
+        assert_eq!(
+            files[0].owners["not.committed.yet"].name,
+            "Not Committed Yet"
+        );
+
+        let key = key(&pseudonyms);
+        assert_eq!(key.lines().count(), 3);
+        assert!(key.contains(&format!(
+            "{}\t{}\tJoe\tjoe@example.com\n",
+            joe.name, joe.email
+        )));
+    }
+}
@@ -1,30 +1,158 @@
 use nom::character::complete::{digit1, hex_digit1, newline, space0, space1};
+use nom::IResult;
+
+use crate::error::WhoKnowsError;
 
 use anyhow::Result;
 use chrono::offset::FixedOffset;
 use chrono::{DateTime, NaiveDateTime, TimeZone};
 
+use std::io::Write;
 use std::path::Path;
-use std::process::Command;
-
-pub fn generate_blame(path: &Path) -> Result<String> {
-    let output = Command::new("git")
-        .current_dir(path.parent().unwrap())
-        .args(&["blame", "--porcelain", "--", &path.to_str().unwrap()])
-        .output()
-        .expect("Failure to run blame command.");
+use std::process::{Command, Stdio};
+
+/// Options of git blame that change its output format, which would break
+/// parsing.
+const FORMAT_OPTIONS: &[&str] = &[
+    "-p",
+    "--porcelain",
+    "--line-porcelain",
+    "--incremental",
+    "-c",
+];
+
+/// Check that extra arguments passed through to git blame leave the porcelain
+/// output format intact.
+///
+/// Options that only change how dates are shown, such as `--date=local`, are
+/// accepted: the porcelain output always gives times as seconds since the
+/// epoch with a separate time zone, so the parsed times are unaffected.
+pub fn check_blame_args(args: &[String]) -> Result<()> {
+    match args.iter().find(|arg| FORMAT_OPTIONS.contains(&arg.as_str())) {
+        Some(arg) => Err(anyhow::Error::msg(format!(
+            "Cannot pass {} to git blame, it changes the output format",
+            arg
+        ))),
+        None => Ok(()),
+    }
+}
+
+/// Options of git blame that take their value as the next argument and change
+/// which commit lines are credited to.
+const ATTRIBUTION_OPTIONS: &[&str] = &["--ignore-rev", "--ignore-revs-file"];
+
+/// Arguments among `args` that change which commit git blame credits lines
+/// to, rather than which lines or revision are blamed: `-w`, `-M`, `-C` and
+/// the options ignoring revisions, with their values.
+pub fn attribution_args(args: &[String]) -> Vec<String> {
+    let mut kept = Vec::new();
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        if ATTRIBUTION_OPTIONS.contains(&arg.as_str()) {
+            kept.push(arg.to_string());
+            kept.extend(args.next().cloned());
+        } else if arg == "-w"
+            || arg.starts_with("-M")
+            || arg.starts_with("-C")
+            || ATTRIBUTION_OPTIONS
+                .iter()
+                .any(|option| arg.starts_with(&format!("{}=", option)))
+        {
+            kept.push(arg.to_string());
+        }
+    }
+    kept
+}
+
+/// Run git blame in `dir` on `file`, relative to `dir`, with porcelain output,
+/// adding `args` to the command line.
+///
+/// When `contents` is given it is blamed in place of the file in the working
+/// tree, as with `git blame --contents`.
+pub fn generate_blame(
+    dir: &Path,
+    file: &Path,
+    args: &[String],
+    contents: Option<&str>,
+) -> Result<String, WhoKnowsError> {
+    run_blame(dir, file, "--porcelain", args, contents)
+}
+
+/// Run git blame with the given porcelain `format` option.
+pub fn run_blame(
+    dir: &Path,
+    file: &Path,
+    format: &str,
+    args: &[String],
+    contents: Option<&str>,
+) -> Result<String, WhoKnowsError> {
+    let mut command = Command::new("git");
+    command.current_dir(dir).args(["blame", format]).args(args);
+    if contents.is_some() {
+        command.args(["--contents", "-"]).stdin(Stdio::piped());
+    }
+    let mut child = command
+        .arg("--")
+        .arg(file)
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .map_err(WhoKnowsError::spawn)?;
+    if let (Some(contents), Some(mut stdin)) = (contents, child.stdin.take()) {
+        // git reads all of the contents before writing any output, so this
+        // cannot block on a full stdout pipe.
+        stdin.write_all(contents.as_bytes())?;
+    }
+    let output = child.wait_with_output()?;
+    if !output.status.success() {
+        return Err(WhoKnowsError::git_failed("blame", dir.join(file), &output.stderr));
+    }
     Ok(String::from_utf8_lossy(&output.stdout).to_string())
 }
 
-pub fn parse_blame(txt: &str) -> Vec<Line> {
+/// Parse porcelain blame output.
+///
+/// The extra header information is not parsed for commits where `known`
+/// returns true, allowing callers that already have the commit information to
+/// skip the work. Output that does not parse is an error quoting the line
+/// where parsing stopped.
+pub fn parse_blame<'a>(
+    txt: &'a str,
+    known: impl Fn(&str) -> bool,
+) -> Result<Vec<Line<'a>>, WhoKnowsError> {
+    let whole = txt;
     let mut lines = Vec::new();
     let mut txt = txt;
     while txt != "" {
-        let (i, line) = parse_line(txt).expect("Failure to parse line");
+        let (i, line) = parse_line(txt, &known).map_err(|e| {
+            let rest = match e {
+                nom::Err::Error((rest, _)) | nom::Err::Failure((rest, _)) => rest,
+                nom::Err::Incomplete(_) => txt,
+            };
+            WhoKnowsError::parse_rest(whole, rest)
+        })?;
         lines.push(line);
         txt = i;
     }
-    lines
+    Ok(lines)
+}
+
+/// Number, counting from 1, and text of the header line of the entry at
+/// `index` of the porcelain output `txt`, each entry ending with its content
+/// line, which starts with a tab.
+pub fn header_line(txt: &str, index: usize) -> (usize, &str) {
+    let mut entry = 0;
+    let mut starts_entry = true;
+    for (number, line) in txt.lines().enumerate() {
+        if starts_entry && entry == index {
+            return (number + 1, line);
+        }
+        starts_entry = line.starts_with('\t');
+        if starts_entry {
+            entry += 1;
+        }
+    }
+    (txt.lines().count() + 1, "")
 }
 
 fn is_newline(c: char) -> bool {
@@ -41,32 +169,65 @@ pub struct HeaderExtra<'a> {
     pub committer_time: DateTime<FixedOffset>,
     pub summary: &'a str,
     pub boundary: Option<bool>,
-    pub previous: Option<&'a str>,
+    pub previous: Option<Previous<'a>>,
     pub filename: &'a str,
 }
 
+/// Commit and name of the file before the blamed commit changed it.
+#[derive(Debug, PartialEq)]
+pub struct Previous<'a> {
+    pub sha: &'a str,
+    pub filename: &'a str,
+}
+
+named!(parse_previous <&str, Previous<'_>>,
+       do_parse!(
+           tag!("previous ") >>
+           sha: hex_digit1 >>
+           tag!(" ") >>
+           filename: take_till!(is_newline) >>
+           tag!("\n") >>
+           (Previous { sha, filename })
+       )
+       );
+
+/// Parse a time zone offset of the form `±HHMM`, as git gives it, into the
+/// offset from UTC.
+pub fn parse_tz(tz: &str) -> Option<FixedOffset> {
+    let sign = match tz.get(..1)? {
+        "+" => 1,
+        "-" => -1,
+        _ => return None,
+    };
+    let digits = &tz[1..];
+    if digits.len() != 4 || !digits.bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+    let (hours, minutes): (i32, i32) = (digits[..2].parse().ok()?, digits[2..].parse().ok()?);
+    if minutes >= 60 {
+        return None;
+    }
+    FixedOffset::east_opt(sign * (hours * 3600 + minutes * 60))
+}
+
 named!(parse_header_extra <&str, HeaderExtra>,
        do_parse!(
            author: delimited!(tag!("author "), take_till!(is_newline), tag!("\n")) >>
            author_mail: delimited!(tag!("author-mail "), take_till!(is_newline), tag!("\n")) >>
-           author_time: delimited!(tag!("author-time "), take_till!(is_newline), tag!("\n")) >>
-           author_tz: delimited!(tag!("author-tz "), take_till!(is_newline), tag!("\n")) >>
+           author_time: map_res!(delimited!(tag!("author-time "), take_till!(is_newline), tag!("\n")), str::parse::<i64>) >>
+           author_tz: map_opt!(delimited!(tag!("author-tz "), take_till!(is_newline), tag!("\n")), parse_tz) >>
            committer: delimited!(tag!("committer "), take_till!(is_newline), tag!("\n")) >>
            committer_mail: delimited!(tag!("committer-mail "), take_till!(is_newline), tag!("\n")) >>
-           committer_time: delimited!(tag!("committer-time "), take_till!(is_newline), tag!("\n")) >>
-           committer_tz: delimited!(tag!("committer-tz "), take_till!(is_newline), tag!("\n")) >>
+           committer_time: map_res!(delimited!(tag!("committer-time "), take_till!(is_newline), tag!("\n")), str::parse::<i64>) >>
+           committer_tz: map_opt!(delimited!(tag!("committer-tz "), take_till!(is_newline), tag!("\n")), parse_tz) >>
            summary: delimited!(tag!("summary "), take_till!(is_newline), tag!("\n")) >>
            boundary: opt!(terminated!(tag!("boundary"), newline)) >>
-           previous: opt!(delimited!(tag!("previous "), take_till!(is_newline), tag!("\n"))) >>
+           previous: opt!(parse_previous) >>
            filename: delimited!(tag!("filename "), take_till!(is_newline), tag!("\n")) >>
            (
                {
-                   let author_time = i64::from_str_radix(author_time, 10).expect("Failure to convert author time to integer.");
-                   let author_tz = i32::from_str_radix(author_tz, 10).expect("Failure to convert author tz to integer.");
-                   let author_time = DateTime::<FixedOffset>::from_utc(NaiveDateTime::from_timestamp(author_time, 0), TimeZone::from_offset(&FixedOffset::east(author_tz)));
-                   let committer_time = i64::from_str_radix(committer_time, 10).expect("Failure to convert committer time to integer.");
-                   let committer_tz = i32::from_str_radix(committer_tz, 10).expect("Failure to convert committer tz to integer.");
-                   let committer_time = DateTime::<FixedOffset>::from_utc(NaiveDateTime::from_timestamp(committer_time, 0), TimeZone::from_offset(&FixedOffset::east(committer_tz)));
+                   let author_time = DateTime::<FixedOffset>::from_utc(NaiveDateTime::from_timestamp(author_time, 0), TimeZone::from_offset(&author_tz));
+                   let committer_time = DateTime::<FixedOffset>::from_utc(NaiveDateTime::from_timestamp(committer_time, 0), TimeZone::from_offset(&committer_tz));
                    let boundary = boundary.map(|b| b == "boundary");
                    HeaderExtra {
                        author,
@@ -84,6 +245,22 @@ named!(parse_header_extra <&str, HeaderExtra>,
            ))
        );
 
+named!(skip_header_extra <&str, &str>,
+       recognize!(
+           tuple!(
+               peek!(tag!("author ")),
+               take_until!("\nfilename "),
+               tag!("\nfilename "),
+               take_till!(is_newline),
+               tag!("\n")
+           ))
+       );
+
+// Counts too large for a usize fail to parse rather than wrapping.
+named!(parse_count <&str, usize>,
+       map_res!(digit1, |count: &str| count.parse::<usize>())
+       );
+
 #[derive(Debug, PartialEq)]
 pub struct Header<'a> {
     pub hash: &'a str,
@@ -97,20 +274,19 @@ named!(parse_header <&str, Header>,
        do_parse!(
            hash: hex_digit1 >>
            space1 >>
-           line_num_orig: digit1 >>
+           line_num_orig: parse_count >>
            space1 >>
-           line_num_final: digit1 >>
+           line_num_final: parse_count >>
            space0 >>
-           num_lines_in_group: opt!(digit1) >>
+           num_lines_in_group: opt!(parse_count) >>
            newline >>
-           extra: opt!(parse_header_extra) >>
            (
                Header {
                    hash: hash,
-                   line_num_orig: usize::from_str_radix(line_num_orig, 10).expect("failure to parse original line number"),
-                   line_num_final: usize::from_str_radix(line_num_final, 10).expect("failure to parse final line number"),
-                   num_lines_in_group: num_lines_in_group.map(|x| usize::from_str_radix(x, 10).expect("Failure to parse number of lines in group")),
-                   extra: extra,
+                   line_num_orig,
+                   line_num_final,
+                   num_lines_in_group,
+                   extra: None,
                }
            ))
        );
@@ -121,17 +297,18 @@ pub struct Line<'a> {
     pub line: &'a str,
 }
 
-named!(parse_line <&str, Line>,
-       do_parse!(
-           header: parse_header >>
-           line: delimited!(tag!("\t"), take_till!(is_newline), newline) >>
-           (
-               Line {
-                   header,
-                   line
-               }
-            ))
-       );
+fn parse_line<'a>(txt: &'a str, known: &impl Fn(&str) -> bool) -> IResult<&'a str, Line<'a>> {
+    let (txt, mut header) = parse_header(txt)?;
+    let txt = if known(header.hash) {
+        opt!(txt, skip_header_extra)?.0
+    } else {
+        let (txt, extra) = opt!(txt, parse_header_extra)?;
+        header.extra = extra;
+        txt
+    };
+    let (txt, line) = delimited!(txt, tag!("\t"), take_till!(is_newline), newline)?;
+    Ok((txt, Line { header, line }))
+}
 
 #[cfg(test)]
 mod tests {
@@ -160,30 +337,43 @@ filename src/main.rs
                     author_mail: "<brandonedens@gmail.com>",
                     author_time: DateTime::<FixedOffset>::from_utc(
                         NaiveDateTime::from_timestamp(1586576941, 0),
-                        TimeZone::from_offset(&FixedOffset::east(-0700))
+                        TimeZone::from_offset(&FixedOffset::west(7 * 3600))
                     ),
                     committer: "Brandon Edens",
                     committer_mail: "<brandonedens@gmail.com>",
                     committer_time: DateTime::<FixedOffset>::from_utc(
                         NaiveDateTime::from_timestamp(1586577179, 0),
-                        TimeZone::from_offset(&FixedOffset::east(-0700))
+                        TimeZone::from_offset(&FixedOffset::west(7 * 3600))
                     ),
                     summary: "Switch to anyhow and modify main to return Result.",
                     boundary: None,
                     filename: "src/main.rs",
-                    previous: Some("dbdf0caee4e14c03e5c3b8c7575219b3affe5657 src/main.rs"),
+                    previous: Some(Previous {
+                        sha: "dbdf0caee4e14c03e5c3b8c7575219b3affe5657",
+                        filename: "src/main.rs",
+                    }),
                 }
             ))
         );
     }
 
+    #[test]
+    fn test_parse_tz() {
+        assert_eq!(parse_tz("-0700").unwrap().local_minus_utc(), -25200);
+        assert_eq!(parse_tz("+0530").unwrap().local_minus_utc(), 19800);
+        assert_eq!(parse_tz("+0000").unwrap().local_minus_utc(), 0);
+        assert_eq!(parse_tz("0700"), None);
+        assert_eq!(parse_tz("+07"), None);
+        assert_eq!(parse_tz("+0760"), None);
+    }
+
     #[test]
     fn test_abridged_line() {
         let input = r#"dbdf0caee4e14c03e5c3b8c7575219b3affe5657 42 54
 	.add_hunk(commit);
 "#;
         assert_eq!(
-            parse_line(input),
+            parse_line(input, &|_| false),
             Ok((
                 "",
                 Line {
@@ -199,4 +389,132 @@ filename src/main.rs
             ))
         );
     }
+
+    #[test]
+    fn test_skip_known_header_extra() {
+        let input = r#"dbdf0caee4e14c03e5c3b8c7575219b3affe5657 1 1 1
+author Brandon Edens
+author-mail <brandonedens@gmail.com>
+author-time 1586576941
+author-tz -0700
+committer Brandon Edens
+committer-mail <brandonedens@gmail.com>
+committer-time 1586577179
+committer-tz -0700
+summary Mention the filename in the summary
+filename src/main.rs
+	fn main() {
+dbdf0caee4e14c03e5c3b8c7575219b3affe5657 2 2
+	}
+"#;
+        let unknown = parse_blame(input, |_| false).unwrap();
+        assert!(unknown[0].header.extra.is_some());
+
+        let known = parse_blame(input, |hash| {
+            hash == "dbdf0caee4e14c03e5c3b8c7575219b3affe5657"
+        })
+        .unwrap();
+        assert_eq!(known.len(), 2);
+        assert_eq!(known[0].header.extra, None);
+        assert_eq!(known[0].header.num_lines_in_group, Some(1));
+        assert_eq!(known[0].line, "fn main() {");
+        assert_eq!(known[1].line, "}");
+    }
+
+    #[test]
+    fn test_huge_line_count() {
+        let input = "dbdf0caee4e14c03e5c3b8c7575219b3affe5657 1 1 99999999999999999999999999\n\tfn main() {\n";
+        assert!(parse_line(input, &|_| true).is_err());
+        assert!(parse_blame(input, |_| true).is_err());
+    }
+
+    #[test]
+    fn test_parse_blame_error() {
+        let input = r#"dbdf0caee4e14c03e5c3b8c7575219b3affe5657 1 1 1
+author Brandon Edens
+author-mail <brandonedens@gmail.com>
+author-time yesterday
+author-tz -0700
+committer Brandon Edens
+committer-mail <brandonedens@gmail.com>
+committer-time 1586577179
+committer-tz -0700
+summary Mention the filename in the summary
+filename src/main.rs
+	fn main() {
+"#;
+        let error = parse_blame(input, |_| false).unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "Failure to parse git output at line 2, near \"author Brandon Edens\""
+        );
+
+        let input = "dbdf0caee4e14c03e5c3b8c7575219b3affe5657 1 1 1\n\tfn main() {\nnot porcelain\n";
+        assert!(matches!(
+            parse_blame(input, |_| true),
+            Err(WhoKnowsError::Parse { line: 3, .. })
+        ));
+    }
+
+    #[test]
+    fn test_header_line() {
+        let input = "dbdf0caee4e14c03e5c3b8c7575219b3affe5657 1 1 1\n\
+                     author Jane\n\
+                     \tfn main() {\n\
+                     a4fc0e41821a208c1b81687393380df8886f0e99 2 2 1\n\
+                     \t}\n";
+        assert_eq!(header_line(input, 0).0, 1);
+        assert_eq!(
+            header_line(input, 1),
+            (4, "a4fc0e41821a208c1b81687393380df8886f0e99 2 2 1")
+        );
+        assert_eq!(header_line(input, 2), (6, ""));
+    }
+
+    #[test]
+    fn test_parse_blame_error_line() {
+        // The header line of the second commit is malformed, the fifth line
+        // of the output, rather than its second entry.
+        let input = "dbdf0caee4e14c03e5c3b8c7575219b3affe5657 1 1 2\n\
+                     \tfn main() {\n\
+                     dbdf0caee4e14c03e5c3b8c7575219b3affe5657 2 2\n\
+                     \t}\n\
+                     a4fc0e41821a208c1b81687393380df8886f0e99 x 3 1\n\
+                     \tfn other() {}\n";
+        match parse_blame(input, |_| true) {
+            Err(WhoKnowsError::Parse { line, input }) => {
+                assert_eq!(line, 5);
+                assert_eq!(input, "x 3 1");
+            }
+            other => panic!("expected a parse error, got {:?}", other.map(|l| l.len())),
+        }
+    }
+
+    #[test]
+    fn test_check_blame_args() {
+        let args = |args: &[&str]| -> Vec<String> { args.iter().map(|a| a.to_string()).collect() };
+        assert!(check_blame_args(&args(&["-w", "--date=iso", "HEAD~2"])).is_ok());
+        assert!(check_blame_args(&args(&["-w", "--line-porcelain"])).is_err());
+        assert!(check_blame_args(&args(&["--incremental"])).is_err());
+    }
+
+    #[test]
+    fn test_attribution_args() {
+        let args = |args: &[&str]| -> Vec<String> { args.iter().map(|a| a.to_string()).collect() };
+        assert_eq!(
+            attribution_args(&args(&[
+                "-w",
+                "-L",
+                "1,3",
+                "-M20",
+                "--ignore-rev",
+                "abc",
+                "--ignore-revs-file=.revs",
+                "^def",
+                "HEAD",
+                "-C",
+            ])),
+            args(&["-w", "-M20", "--ignore-rev", "abc", "--ignore-revs-file=.revs", "-C"])
+        );
+    }
 }
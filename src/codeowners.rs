@@ -0,0 +1,182 @@
+use git_whoknows::{Owner, TrackedFile};
+
+use anyhow::Result;
+
+use std::collections::{BTreeMap, HashMap};
+use std::path::{Path, PathBuf};
+
+/// Generate the contents of a CODEOWNERS file from the analyzed files.
+///
+/// Files are rolled up by the directory containing them, unless `per_file` is
+/// set. Each directory or file gets a single entry listing at most `top`
+/// owners accepted by `filter`, ordered by lines owned, that own at least
+/// `min_percent` of its lines. Lines not committed yet or older than the
+/// boundary have no one to review them, so their synthetic owners are never
+/// listed. Owners are listed by their handle in `handles`, keyed by
+/// lowercased email, or by their email when they have none.
+pub fn generate(
+    files: &[TrackedFile],
+    top: usize,
+    min_percent: f64,
+    filter: impl Fn(&Owner) -> bool,
+    per_file: bool,
+    handles: &HashMap<String, String>,
+) -> String {
+    let mut entries: BTreeMap<PathBuf, TrackedFile> = BTreeMap::new();
+    for file in files {
+        let path = if per_file {
+            file.repo_path.clone()
+        } else {
+            file.repo_path
+                .parent()
+                .map(Path::to_path_buf)
+                .unwrap_or_default()
+        };
+        entries
+            .entry(path.clone())
+            .or_insert_with(|| TrackedFile::new(path.display().to_string(), path))
+            .merge(file);
+    }
+
+    let mut output = String::from("# Generated by git-whoknows\n");
+    for (path, rollup) in &entries {
+        let mut owners: Vec<&Owner> = rollup
+            .owners
+            .values()
+            .filter(|o| !o.is_synthetic() && filter(o))
+            .filter(|o| rollup.owner_percentage(o) >= min_percent)
+            .collect();
+        owners.sort_by(|a, b| b.lines().cmp(&a.lines()).then(a.email.cmp(&b.email)));
+        owners.truncate(top);
+
+        if owners.is_empty() {
+            continue;
+        }
+
+        output.push_str(&if per_file {
+            file_pattern(path)
+        } else {
+            pattern(path)
+        });
+        owners.iter().for_each(|o| {
+            output.push(' ');
+            output.push_str(handles.get(&o.email.to_lowercase()).unwrap_or(&o.email));
+        });
+        output.push('\n');
+    }
+    output
+}
+
+/// CODEOWNERS pattern matching everything below `dir`.
+fn pattern(dir: &Path) -> String {
+    if dir.as_os_str().is_empty() {
+        return String::from("*");
+    }
+    format!("{}/", file_pattern(dir))
+}
+
+/// CODEOWNERS pattern matching only `path`, from the root of the repository.
+fn file_pattern(path: &Path) -> String {
+    let path = path
+        .iter()
+        .map(|c| c.to_string_lossy().replace(' ', "\\ "))
+        .collect::<Vec<_>>()
+        .join("/");
+    format!("/{}", path)
+}
+
+/// Parse a map of emails to CODEOWNERS handles, one `email @handle` pair per
+/// line, keyed by lowercased email.
+pub fn parse_email_map(lines: &[String]) -> Result<HashMap<String, String>> {
+    lines
+        .iter()
+        .map(
+            |line| match line.split_whitespace().collect::<Vec<_>>()[..] {
+                [email, handle] if handle.starts_with('@') => {
+                    Ok((email.to_lowercase(), handle.to_string()))
+                }
+                _ => Err(anyhow::Error::msg(format!(
+                    "Email map lines must be of the form `email @handle`, found `{}`",
+                    line
+                ))),
+            },
+        )
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixture::tracked_file;
+
+    #[test]
+    fn test_generate_rolls_up_directories() {
+        let files = vec![
+            tracked_file("README.md", &[("a@example.com", 10)]),
+            tracked_file(
+                "src/main.rs",
+                &[("a@example.com", 5), ("b@example.com", 20)],
+            ),
+            tracked_file("src/lib.rs", &[("c@example.com", 1), ("b@example.com", 4)]),
+            tracked_file("my docs/x.md", &[("c@example.com", 3)]),
+        ];
+        assert_eq!(
+            generate(&files, 2, 10.0, |_| true, false, &HashMap::new()),
+            "# Generated by git-whoknows\n\
+             * a@example.com\n\
+             /my\\ docs/ c@example.com\n\
+             /src/ b@example.com a@example.com\n"
+        );
+    }
+
+    #[test]
+    fn test_generate_per_file() {
+        let files = vec![
+            tracked_file(
+                "src/main.rs",
+                &[("a@example.com", 5), ("B@example.com", 20)],
+            ),
+            tracked_file("my docs/x.md", &[("c@example.com", 3)]),
+        ];
+        let handles = parse_email_map(&["b@example.com @bee".to_string()]).unwrap();
+        assert_eq!(
+            generate(&files, 1, 0.0, |_| true, true, &handles),
+            "# Generated by git-whoknows\n\
+             /my\\ docs/x.md c@example.com\n\
+             /src/main.rs @bee\n"
+        );
+    }
+
+    #[test]
+    fn test_generate_skips_synthetic_and_filtered_owners() {
+        let files = vec![tracked_file(
+            "src/main.rs",
+            &[
+                ("not.committed.yet", 30),
+                ("boundary", 20),
+                ("a@example.com", 5),
+                ("b@example.com", 4),
+            ],
+        )];
+        assert_eq!(
+            generate(
+                &files,
+                2,
+                0.0,
+                |o| o.email != "a@example.com",
+                false,
+                &HashMap::new()
+            ),
+            "# Generated by git-whoknows\n\
+             /src/ b@example.com\n"
+        );
+    }
+
+    #[test]
+    fn test_parse_email_map() {
+        let map = parse_email_map(&["Jane@Example.com   @jane".to_string()]).unwrap();
+        assert_eq!(map["jane@example.com"], "@jane");
+        assert!(parse_email_map(&["jane@example.com jane".to_string()]).is_err());
+        assert!(parse_email_map(&["jane@example.com".to_string()]).is_err());
+    }
+}
@@ -0,0 +1,221 @@
+use git_whoknows::{Owner, TrackedFile};
+
+use std::str::FromStr;
+
+/// What each row of the CSV output describes.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Granularity {
+    /// One row per owner of each file.
+    Owner,
+    /// One row per commit of each file, keyed by its hash.
+    Commit,
+}
+
+impl FromStr for Granularity {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> anyhow::Result<Granularity> {
+        match s {
+            "owner" => Ok(Granularity::Owner),
+            "commit" => Ok(Granularity::Commit),
+            _ => Err(anyhow::Error::msg(
+                "Granularity must be one of owner or commit",
+            )),
+        }
+    }
+}
+
+/// Generate CSV with a header row and a row for each owner, or each commit,
+/// accepted by `filter` in each file, naming owners by `name`.
+///
+/// Owners are ordered by the lines they own and commits by the lines they
+/// have, with ties broken by email and hash so the output is stable. Only the
+/// first `top` owners of each file are kept, with all of their commits.
+pub fn generate(
+    files: &[TrackedFile],
+    granularity: Granularity,
+    filter: impl Fn(&Owner) -> bool,
+    top: Option<usize>,
+    name: impl Fn(&Owner) -> String,
+) -> String {
+    let mut output = match granularity {
+        Granularity::Owner => String::from("path,name,email,lines,commits,synthetic\n"),
+        Granularity::Commit => String::from("path,sha,author,email,lines,date,summary,synthetic\n"),
+    };
+    for file in files {
+        let mut owners: Vec<&Owner> = file.owners.values().filter(|o| filter(o)).collect();
+        owners.sort_by(|a, b| b.lines().cmp(&a.lines()).then(a.email.cmp(&b.email)));
+        if let Some(top) = top {
+            owners.truncate(top);
+        }
+        match granularity {
+            Granularity::Owner => owners.iter().for_each(|owner| {
+                push_row(
+                    &mut output,
+                    &[
+                        &file.path,
+                        &name(owner),
+                        &owner.email,
+                        &owner.lines().to_string(),
+                        &owner.commits.len().to_string(),
+                        &owner.is_synthetic().to_string(),
+                    ],
+                )
+            }),
+            Granularity::Commit => {
+                let mut commits: Vec<(&Owner, &String, usize)> = owners
+                    .iter()
+                    .flat_map(|owner| {
+                        owner
+                            .commits
+                            .iter()
+                            .map(move |(hash, lines)| (*owner, hash, *lines))
+                    })
+                    .collect();
+                commits.sort_by(|a, b| b.2.cmp(&a.2).then(a.1.cmp(b.1)));
+                commits.iter().for_each(|(owner, hash, lines)| {
+                    let date = owner.dates.get(*hash).map(|date| date.to_rfc3339());
+                    push_row(
+                        &mut output,
+                        &[
+                            &file.path,
+                            hash,
+                            &name(owner),
+                            &owner.email,
+                            &lines.to_string(),
+                            date.as_deref().unwrap_or(""),
+                            owner.summaries.get(*hash).map_or("", String::as_str),
+                            &owner.is_synthetic().to_string(),
+                        ],
+                    )
+                });
+            }
+        }
+    }
+    output
+}
+
+fn push_row(output: &mut String, fields: &[&str]) {
+    let row: Vec<String> = fields.iter().map(|f| field(f)).collect();
+    output.push_str(&row.join(","));
+    output.push('\n');
+}
+
+/// Quote a field if it contains a separator, quote or line break, doubling
+/// any quotes, as in RFC 4180.
+fn field(s: &str) -> String {
+    if s.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", s.replace('"', "\"\""))
+    } else {
+        s.to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixture::{hunk, tracked_file};
+    use chrono::{FixedOffset, TimeZone};
+    use git_whoknows::identity::Identities;
+    use git_whoknows::ownership::{BasicHunk, NOT_COMMITTED_EMAIL};
+    use std::path::PathBuf;
+
+    #[test]
+    fn test_generate() {
+        let mut file = TrackedFile::new("src/a.rs".to_string(), PathBuf::from("src/a.rs"));
+        for (hash, email, lines) in &[
+            ("1111", "jane@example.com", 3),
+            ("2222", "jane@example.com", 5),
+            ("3333", "joe@example.com", 4),
+        ] {
+            let hunk = BasicHunk {
+                author: email.split('@').next().unwrap().to_string(),
+                ..hunk(hash, email, *lines)
+            };
+            file.add_hunk(&hunk, &Identities::default());
+        }
+        let uncommitted = BasicHunk {
+            author: "Not Committed Yet".to_string(),
+            ..hunk(&"0".repeat(40), NOT_COMMITTED_EMAIL, 1)
+        };
+        file.add_hunk(&uncommitted, &Identities::default());
+        let files = [file];
+
+        assert_eq!(
+            generate(
+                &files,
+                Granularity::Owner,
+                |_| true,
+                None,
+                |o| o.name.clone()
+            ),
+            "path,name,email,lines,commits,synthetic\n\
+             src/a.rs,jane,jane@example.com,8,2,false\n\
+             src/a.rs,joe,joe@example.com,4,1,false\n\
+             src/a.rs,Not Committed Yet,not.committed.yet,1,1,true\n"
+        );
+        assert_eq!(
+            generate(
+                &files,
+                Granularity::Commit,
+                |_| true,
+                Some(1),
+                |o| o.name.clone()
+            ),
+            "path,sha,author,email,lines,date,summary,synthetic\n\
+             src/a.rs,2222,jane,jane@example.com,5,2023-01-01T00:00:00+00:00,,false\n\
+             src/a.rs,1111,jane,jane@example.com,3,2023-01-01T00:00:00+00:00,,false\n"
+        );
+    }
+
+    #[test]
+    fn test_generate_files() {
+        let files: Vec<TrackedFile> = ["a.rs", "b.rs"]
+            .iter()
+            .map(|path| {
+                let mut file = tracked_file(path, &[]);
+                let hunk = BasicHunk {
+                    author: "Doe, Jane".to_string(),
+                    ..hunk("1111", "jane@example.com", 2)
+                };
+                file.add_hunk(&hunk, &Identities::default());
+                file
+            })
+            .collect();
+
+        // The header is only written once, before the rows of the first file.
+        assert_eq!(
+            generate(
+                &files,
+                Granularity::Owner,
+                |_| true,
+                None,
+                |o| o.name.clone()
+            ),
+            "path,name,email,lines,commits,synthetic\n\
+             a.rs,\"Doe, Jane\",jane@example.com,2,1,false\n\
+             b.rs,\"Doe, Jane\",jane@example.com,2,1,false\n"
+        );
+    }
+
+    #[test]
+    fn test_generate_by_commit() {
+        let mut file = TrackedFile::new("a,b.rs".to_string(), PathBuf::from("a,b.rs"));
+        for (hash, lines, summary) in &[("1111", 3, "Add a, b"), ("2222", 5, "Say \"hi\"")] {
+            let hunk = BasicHunk {
+                author: "Jane".to_string(),
+                time: FixedOffset::east(0).ymd(2023, 1, 2).and_hms(3, 4, 5),
+                summary: Some(summary.to_string()),
+                ..hunk(hash, "jane@example.com", *lines)
+            };
+            file.add_hunk(&hunk, &Identities::default());
+        }
+
+        assert_eq!(
+            generate(&[file], Granularity::Commit, |_| true, None, |o| o.name.clone()),
+            "path,sha,author,email,lines,date,summary,synthetic\n\
+             \"a,b.rs\",2222,Jane,jane@example.com,5,2023-01-02T03:04:05+00:00,\"Say \"\"hi\"\"\",false\n\
+             \"a,b.rs\",1111,Jane,jane@example.com,3,2023-01-02T03:04:05+00:00,\"Add a, b\",false\n"
+        );
+    }
+}
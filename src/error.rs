@@ -0,0 +1,158 @@
+use thiserror::Error;
+
+use std::io;
+use std::path::PathBuf;
+
+/// Errors from analyzing the ownership of a file.
+#[derive(Error, Debug)]
+pub enum WhoKnowsError {
+    /// The git executable could not be run.
+    #[error("Unable to run git: {0}")]
+    GitNotFound(#[source] io::Error),
+
+    /// The file is not tracked by git, at the blamed revision if one is given.
+    #[error("{} is not tracked by git", path.display())]
+    NotTracked { path: PathBuf },
+
+    /// A git command exited with an error for another reason.
+    #[error("Error running git {command} for {}: {message}", path.display())]
+    GitFailed {
+        command: &'static str,
+        path: PathBuf,
+        message: String,
+    },
+
+    /// The output of git stops following the expected format at `line`,
+    /// counting from 1, where it reads `input`.
+    #[error("Failure to parse git output at line {line}, near {input:?}")]
+    Parse { line: usize, input: String },
+
+    /// The date of commit `hash` is out of the range a date can hold, as for
+    /// a time zone a day or more away from UTC.
+    #[error(
+        "Commit {hash} has a date out of range, {seconds} seconds at UTC offset {offset_minutes} minutes"
+    )]
+    BadDate {
+        hash: String,
+        seconds: i64,
+        offset_minutes: i32,
+    },
+
+    #[error(transparent)]
+    Git(#[from] git2::Error),
+
+    #[error(transparent)]
+    Io(#[from] io::Error),
+}
+
+/// Problems that did not stop the analysis, returned for the caller to
+/// report.
+#[derive(Error, Debug, Clone, PartialEq)]
+pub enum Warning {
+    /// A hunk of blame output was left out, as it could not be credited to
+    /// its commit.
+    #[error("skipping blame of {sha} for {}: {reason}", path.display())]
+    SkippedBlame {
+        sha: String,
+        path: PathBuf,
+        reason: String,
+    },
+
+    /// The resolve command gave no identity for `email`, which was kept.
+    #[error("resolve command failed for {email}: {reason}, keeping the email")]
+    ResolveFailed { email: String, reason: String },
+}
+
+/// Longest part of the unparsed input quoted in a parse error, in characters.
+const SNIPPET_LENGTH: usize = 80;
+
+impl WhoKnowsError {
+    /// Error for output failing to parse at `line`, quoting the start of
+    /// `input`, the unparsed rest of that line.
+    pub fn parse(line: usize, input: &str) -> WhoKnowsError {
+        WhoKnowsError::Parse {
+            line,
+            input: input
+                .lines()
+                .next()
+                .unwrap_or("")
+                .chars()
+                .take(SNIPPET_LENGTH)
+                .collect(),
+        }
+    }
+
+    /// Error for output `txt` failing to parse at `rest`, the unparsed end of
+    /// `txt`, numbering the line of `txt` that `rest` starts in.
+    pub fn parse_rest(txt: &str, rest: &str) -> WhoKnowsError {
+        let offset = txt.len().saturating_sub(rest.len());
+        WhoKnowsError::parse(txt[..offset].matches('\n').count() + 1, rest)
+    }
+
+    /// Error for a failure to start git.
+    pub fn spawn(error: io::Error) -> WhoKnowsError {
+        match error.kind() {
+            io::ErrorKind::NotFound => WhoKnowsError::GitNotFound(error),
+            _ => WhoKnowsError::Io(error),
+        }
+    }
+
+    /// Error for git `command` on `path` exiting unsuccessfully with `stderr`.
+    pub fn git_failed(command: &'static str, path: PathBuf, stderr: &[u8]) -> WhoKnowsError {
+        let message = String::from_utf8_lossy(stderr).trim().to_string();
+        if message.contains("no such path") {
+            WhoKnowsError::NotTracked { path }
+        } else {
+            WhoKnowsError::GitFailed {
+                command,
+                path,
+                message,
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_git_failed() {
+        let error = WhoKnowsError::git_failed(
+            "blame",
+            PathBuf::from("new.rs"),
+            b"fatal: no such path 'new.rs' in HEAD\n",
+        );
+        assert!(matches!(error, WhoKnowsError::NotTracked { .. }));
+
+        let error = WhoKnowsError::git_failed(
+            "blame",
+            PathBuf::from("main.rs"),
+            b"fatal: bad revision 'v9'\n",
+        );
+        assert_eq!(
+            error.to_string(),
+            "Error running git blame for main.rs: fatal: bad revision 'v9'"
+        );
+    }
+
+    #[test]
+    fn test_parse_rest() {
+        let txt = "first\nsecond line\nthird\n";
+        let error = WhoKnowsError::parse_rest(txt, &txt[13..]);
+        assert_eq!(
+            error.to_string(),
+            "Failure to parse git output at line 2, near \"line\""
+        );
+        let error = WhoKnowsError::parse_rest(txt, &txt[18..]);
+        assert!(matches!(error, WhoKnowsError::Parse { line: 3, .. }));
+    }
+
+    #[test]
+    fn test_spawn() {
+        let error = WhoKnowsError::spawn(io::Error::from(io::ErrorKind::NotFound));
+        assert!(matches!(error, WhoKnowsError::GitNotFound(_)));
+        let error = WhoKnowsError::spawn(io::Error::from(io::ErrorKind::PermissionDenied));
+        assert!(matches!(error, WhoKnowsError::Io(_)));
+    }
+}
@@ -0,0 +1,79 @@
+//! Repositories, hunks and files for the tests of both the library and the
+//! binary, which each compile this file, so items are named through the
+//! `identity` and `ownership` paths both crate roots provide.
+
+use crate::identity::Identities;
+use crate::ownership::{BasicHunk, TrackedFile};
+
+use chrono::{FixedOffset, TimeZone};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use tempfile::TempDir;
+
+/// Run git in `dir` with a fixed committer identity and without signing
+/// commits, returning its output and failing the test if it fails.
+pub fn git(dir: &Path, args: &[&str]) -> String {
+    let output = Command::new("git")
+        .current_dir(dir)
+        .args(["-c", "user.name=Test", "-c", "user.email=test@example.com"])
+        .args(["-c", "commit.gpgsign=false"])
+        .args(args)
+        .output()
+        .unwrap();
+    assert!(
+        output.status.success(),
+        "git {} failed: {}",
+        args.join(" "),
+        String::from_utf8_lossy(&output.stderr).trim()
+    );
+    String::from_utf8_lossy(&output.stdout).to_string()
+}
+
+/// Create a repository containing `file` committed by `author`.
+pub fn repo_with_file(file: &str, contents: &str, author: &str) -> TempDir {
+    let dir = TempDir::new().unwrap();
+    git(dir.path(), &["init", "-q"]);
+    let path = dir.path().join(file);
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent).unwrap();
+    }
+    fs::write(path, contents).unwrap();
+    git(dir.path(), &["add", file]);
+    git(
+        dir.path(),
+        &["commit", "-q", "-m", "Add file", "--author", author],
+    );
+    dir
+}
+
+/// Hunk of `num_lines` lines of commit `hash`, written by `email` under the
+/// same name, at the start of 2023 in UTC.
+///
+/// Other fields can be set with struct update syntax, as in
+/// `BasicHunk { time, ..hunk("1", "jane", 2) }`.
+pub fn hunk(hash: &str, email: &str, num_lines: usize) -> BasicHunk {
+    BasicHunk {
+        hash: hash.to_string(),
+        author: email.to_string(),
+        mail: email.to_string(),
+        time: FixedOffset::east(0).ymd(2023, 1, 1).and_hms(0, 0, 0),
+        num_lines,
+        summary: None,
+        committer: None,
+    }
+}
+
+/// File at `path` with a commit of its lines for each owner in `owners`,
+/// given by email. Hashes count from 1, as the all-zero hash stands for lines
+/// not committed yet.
+pub fn tracked_file(path: &str, owners: &[(&str, usize)]) -> TrackedFile {
+    let mut file = TrackedFile::new(path.to_string(), PathBuf::from(path));
+    for (i, (email, lines)) in owners.iter().enumerate() {
+        file.add_hunk(
+            &hunk(&format!("{:040x}", i + 1), email, *lines),
+            &Identities::default(),
+        );
+    }
+    file
+}
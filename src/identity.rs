@@ -0,0 +1,417 @@
+use crate::error::Warning;
+
+use anyhow::Result;
+use dashmap::DashMap;
+use regex::Regex;
+
+use std::collections::HashMap;
+use std::io::Write;
+use std::process::{Command, Stdio};
+use std::str::FromStr;
+use std::sync::Mutex;
+
+/// Rule crediting every author whose email matches `pattern` to a synthetic
+/// owner named `label`.
+#[derive(Clone)]
+pub struct AuthorMerge {
+    pattern: Regex,
+    label: String,
+}
+
+impl FromStr for AuthorMerge {
+    type Err = anyhow::Error;
+
+    /// Parse a rule of the form `PATTERN=>Label`.
+    fn from_str(s: &str) -> Result<AuthorMerge> {
+        let split = s
+            .rfind("=>")
+            .ok_or_else(|| anyhow::Error::msg("Expected a merge of the form PATTERN=>Label"))?;
+        let label = s[split + 2..].trim();
+        if label.is_empty() {
+            return Err(anyhow::Error::msg("Merge label must not be empty"));
+        }
+        Ok(AuthorMerge {
+            pattern: Regex::new(&s[..split])?,
+            label: label.to_string(),
+        })
+    }
+}
+
+impl AuthorMerge {
+    /// Name and email of the owner the matched authors are credited to.
+    pub fn label(&self) -> &str {
+        &self.label
+    }
+}
+
+/// Name and email of a mailmap entry, either of which may be missing.
+type MailmapIdentity = (Option<String>, Option<String>);
+
+/// Entries of a `.mailmap` file, mapping the identities recorded on commits to
+/// the canonical ones.
+#[derive(Clone, Debug, Default)]
+pub struct Mailmap {
+    /// Canonical name and email, either of which may be left as recorded, by
+    /// the lowercased email and, for entries giving one, name on the commit.
+    entries: HashMap<(String, Option<String>), MailmapIdentity>,
+}
+
+impl Mailmap {
+    /// Parse the contents of a `.mailmap` file, skipping comments and lines
+    /// that are not entries as git does.
+    ///
+    /// Entries have one of the forms `Proper Name <commit@email>`,
+    /// `<proper@email> <commit@email>`,
+    /// `Proper Name <proper@email> <commit@email>` or
+    /// `Proper Name <proper@email> Commit Name <commit@email>`.
+    pub fn parse(contents: &str) -> Mailmap {
+        let mut entries = HashMap::new();
+        for line in contents.lines().filter(|line| !line.starts_with('#')) {
+            let (proper_name, proper_email, rest) = match split_mailmap_identity(line) {
+                Some(identity) => identity,
+                None => continue,
+            };
+            match split_mailmap_identity(rest) {
+                Some((commit_name, commit_email, _)) => entries.insert(
+                    (
+                        commit_email.to_lowercase(),
+                        commit_name.map(|name| name.to_lowercase()),
+                    ),
+                    (
+                        proper_name.map(str::to_string),
+                        Some(proper_email.to_string()),
+                    ),
+                ),
+                None => entries.insert(
+                    (proper_email.to_lowercase(), None),
+                    (proper_name.map(str::to_string), None),
+                ),
+            };
+        }
+        Mailmap { entries }
+    }
+
+    /// Canonical name and email of the identity recorded on a commit.
+    pub fn map(&self, name: String, email: String) -> (String, String) {
+        let key = email.to_lowercase();
+        let entry = self
+            .entries
+            .get(&(key.clone(), Some(name.to_lowercase())))
+            .or_else(|| self.entries.get(&(key, None)));
+        match entry {
+            Some((proper_name, proper_email)) => (
+                proper_name.clone().unwrap_or(name),
+                proper_email.clone().unwrap_or(email),
+            ),
+            None => (name, email),
+        }
+    }
+}
+
+/// Split the first `Name <email>` of a mailmap line, where the name may be
+/// missing, from the rest of the line.
+fn split_mailmap_identity(s: &str) -> Option<(Option<&str>, &str, &str)> {
+    let open = s.find('<')?;
+    let close = open + s[open..].find('>')?;
+    let name = s[..open].trim();
+    let name = if name.is_empty() { None } else { Some(name) };
+    Some((name, s[open + 1..close].trim(), &s[close + 1..]))
+}
+
+/// Which identities recorded on a commit are credited with its lines.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum Attribute {
+    #[default]
+    Author,
+    /// Both the author and, when different, the committer.
+    Both,
+    /// The committer, or the author of lines whose committer is not known,
+    /// such as those not committed yet.
+    Committer,
+}
+
+impl FromStr for Attribute {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Attribute> {
+        match s {
+            "author" => Ok(Attribute::Author),
+            "both" => Ok(Attribute::Both),
+            "committer" => Ok(Attribute::Committer),
+            _ => Err(anyhow::Error::msg(
+                "Attribute must be one of author, both or committer",
+            )),
+        }
+    }
+}
+
+/// Rules mapping the identity recorded on a commit to the owner credited
+/// with its lines.
+#[derive(Default)]
+pub struct Identities {
+    mailmap: Mailmap,
+    merges: Vec<AuthorMerge>,
+    attribute: Attribute,
+    /// Shell command printing the canonical identity for the email on its
+    /// standard input.
+    resolve_cmd: Option<String>,
+    /// Identity printed by `resolve_cmd` for each email, if any.
+    resolved: DashMap<String, Option<(Option<String>, String)>>,
+    /// Failures of `resolve_cmd` not yet taken by `take_warnings`.
+    warnings: Mutex<Vec<Warning>>,
+}
+
+impl Identities {
+    pub fn new(merges: Vec<AuthorMerge>) -> Identities {
+        Identities {
+            merges,
+            ..Identities::default()
+        }
+    }
+
+    /// Resolve the authors not matched by a merge rule with a shell command.
+    ///
+    /// The command is given the email on its standard input and prints either
+    /// `Name <email>` or an identifier to credit instead of the email. It is run
+    /// once per email, and the email is kept when it fails or prints nothing.
+    pub fn resolve_with_command(&mut self, command: String) {
+        self.resolve_cmd = Some(command);
+    }
+
+    /// Map the identities recorded on commits to canonical ones before any
+    /// other rule is applied.
+    pub fn use_mailmap(&mut self, mailmap: Mailmap) {
+        self.mailmap = mailmap;
+    }
+
+    /// Credit the lines of each commit to the given identities.
+    pub fn credit(&mut self, attribute: Attribute) {
+        self.attribute = attribute;
+    }
+
+    pub fn attribute(&self) -> Attribute {
+        self.attribute
+    }
+
+    /// Problems met resolving identities since the last call, such as
+    /// failures of the resolve command, for the caller to report.
+    pub fn take_warnings(&self) -> Vec<Warning> {
+        std::mem::take(&mut *self.warnings.lock().unwrap())
+    }
+
+    fn warn(&self, warning: Warning) {
+        self.warnings.lock().unwrap().push(warning);
+    }
+
+    /// Name and email of the owner credited for the given author.
+    pub fn resolve(&self, name: String, email: String) -> (String, String) {
+        let (name, email) = self.mailmap.map(name, email);
+        if let Some(merge) = self.merges.iter().find(|m| m.pattern.is_match(&email)) {
+            return (merge.label.clone(), merge.label.clone());
+        }
+        let command = match &self.resolve_cmd {
+            Some(command) => command,
+            None => return (name, email),
+        };
+        let resolved = self
+            .resolved
+            .entry(email.clone())
+            .or_insert_with(|| {
+                run_resolve_cmd(command, &email).unwrap_or_else(|reason| {
+                    self.warn(Warning::ResolveFailed {
+                        email: email.clone(),
+                        reason,
+                    });
+                    None
+                })
+            })
+            .clone();
+        match resolved {
+            Some((Some(resolved_name), resolved_email)) => (resolved_name, resolved_email),
+            Some((None, resolved_email)) => (name, resolved_email),
+            None => (name, email),
+        }
+    }
+}
+
+/// Run `command` with `email` on its standard input, returning the name if one
+/// was printed and the identity to credit, or why the command failed.
+fn run_resolve_cmd(command: &str, email: &str) -> Result<Option<(Option<String>, String)>, String> {
+    let output = Command::new("sh")
+        .args(["-c", command])
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()
+        .and_then(|mut child| {
+            if let Some(mut stdin) = child.stdin.take() {
+                // The command may exit without reading its input.
+                let _ = writeln!(stdin, "{}", email);
+            }
+            child.wait_with_output()
+        });
+    match output {
+        Ok(output) if output.status.success() => Ok(parse_identity(
+            String::from_utf8_lossy(&output.stdout).trim(),
+        )),
+        Ok(output) => Err(output.status.to_string()),
+        Err(e) => Err(format!("unable to run it: {}", e)),
+    }
+}
+
+/// Check that `email` has the shape of an address: a single `@` between a
+/// local part and a domain, without whitespace or stray brackets.
+///
+/// This is deliberately lenient, only catching clearly broken addresses.
+pub fn looks_like_email(email: &str) -> bool {
+    let mut parts = email.split('@');
+    let (local, domain) = match (parts.next(), parts.next(), parts.next()) {
+        (Some(local), Some(domain), None) => (local, domain),
+        _ => return false,
+    };
+    !local.is_empty()
+        && !domain.is_empty()
+        && !domain.starts_with('.')
+        && !domain.ends_with('.')
+        && !email
+            .chars()
+            .any(|c| c.is_whitespace() || matches!(c, '<' | '>' | ',' | ';'))
+}
+
+/// Parse an identity printed by a resolve command.
+fn parse_identity(identity: &str) -> Option<(Option<String>, String)> {
+    if identity.is_empty() {
+        return None;
+    }
+    if let (Some(open), true) = (identity.rfind('<'), identity.ends_with('>')) {
+        let name = identity[..open].trim();
+        let email = identity[open + 1..identity.len() - 1].trim();
+        if !name.is_empty() && !email.is_empty() {
+            return Some((Some(name.to_string()), email.to_string()));
+        }
+    }
+    Some((None, identity.to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_author_merge() {
+        let merge: AuthorMerge = r".*-bot@ci\.example\.com=>CI".parse().unwrap();
+        assert_eq!(merge.pattern.as_str(), r".*-bot@ci\.example\.com");
+        assert_eq!(merge.label, "CI");
+        assert!("no-separator".parse::<AuthorMerge>().is_err());
+        assert!("pattern=>".parse::<AuthorMerge>().is_err());
+        assert!("(unclosed=>Label".parse::<AuthorMerge>().is_err());
+    }
+
+    #[test]
+    fn test_mailmap() {
+        let mailmap = Mailmap::parse(
+            "# Comment <ignored@example.com>\n\
+             Jane Doe <jane@example.com>\n\
+             <joe@example.com> <joe@old.example.com>\n\
+             Ann Lee <ann@example.com> <ann@old.example.com>\n\
+             Bot <bot@example.com> Ann Lee <ci@example.com>\n\
+             not an entry\n",
+        );
+        let map = |name: &str, email: &str| mailmap.map(name.to_string(), email.to_string());
+        assert_eq!(
+            map("jane", "Jane@Example.com"),
+            ("Jane Doe".to_string(), "Jane@Example.com".to_string())
+        );
+        assert_eq!(
+            map("Joe", "joe@old.example.com"),
+            ("Joe".to_string(), "joe@example.com".to_string())
+        );
+        assert_eq!(
+            map("A. Lee", "ann@old.example.com"),
+            ("Ann Lee".to_string(), "ann@example.com".to_string())
+        );
+        // Entries with a commit name only match that name.
+        assert_eq!(
+            map("ann lee", "ci@example.com"),
+            ("Bot".to_string(), "bot@example.com".to_string())
+        );
+        assert_eq!(
+            map("Other", "ci@example.com"),
+            ("Other".to_string(), "ci@example.com".to_string())
+        );
+        assert_eq!(
+            map("Ignored", "ignored@example.com"),
+            ("Ignored".to_string(), "ignored@example.com".to_string())
+        );
+    }
+
+    #[test]
+    fn test_looks_like_email() {
+        assert!(looks_like_email("jane@example.com"));
+        assert!(looks_like_email("jane+git@localhost"));
+        assert!(!looks_like_email("jane"));
+        assert!(!looks_like_email("jane@"));
+        assert!(!looks_like_email("@example.com"));
+        assert!(!looks_like_email("jane@@example.com"));
+        assert!(!looks_like_email("jane@example.com>"));
+        assert!(!looks_like_email("jane doe@example.com"));
+        assert!(!looks_like_email("jane@example."));
+    }
+
+    #[test]
+    fn test_resolve() {
+        let identities = Identities::new(vec![
+            r"-bot@ci\.example\.com$=>CI".parse().unwrap(),
+            r"@users\.noreply\.github\.com$=>GitHub".parse().unwrap(),
+        ]);
+        assert_eq!(
+            identities.resolve("Build Bot".into(), "build-bot@ci.example.com".into()),
+            ("CI".to_string(), "CI".to_string())
+        );
+        assert_eq!(
+            identities.resolve("Jane".into(), "jane@example.com".into()),
+            ("Jane".to_string(), "jane@example.com".to_string())
+        );
+    }
+
+    #[test]
+    fn test_parse_identity() {
+        assert_eq!(
+            parse_identity("Jane Doe <jane@example.com>"),
+            Some((Some("Jane Doe".to_string()), "jane@example.com".to_string()))
+        );
+        assert_eq!(parse_identity("jdoe"), Some((None, "jdoe".to_string())));
+        assert_eq!(parse_identity(""), None);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_resolve_with_command() {
+        let mut identities = Identities::new(vec![r"-bot@=>CI".parse().unwrap()]);
+        identities.resolve_with_command(
+            r#"read email; case "$email" in jane@*) echo "Jane Doe <jane@corp.example.com>";; joe@*) exit 1;; esac"#
+                .to_string(),
+        );
+        assert_eq!(
+            identities.resolve("jane".into(), "jane@home.example.com".into()),
+            ("Jane Doe".to_string(), "jane@corp.example.com".to_string())
+        );
+        assert_eq!(
+            identities.resolve("Joe".into(), "joe@example.com".into()),
+            ("Joe".to_string(), "joe@example.com".to_string())
+        );
+        assert_eq!(
+            identities.resolve("Build".into(), "build-bot@example.com".into()),
+            ("CI".to_string(), "CI".to_string())
+        );
+        assert_eq!(identities.resolved.len(), 2);
+
+        // The failure for joe is returned once, rather than printed.
+        let warnings = identities.take_warnings();
+        assert_eq!(warnings.len(), 1);
+        assert!(matches!(
+            &warnings[0],
+            Warning::ResolveFailed { email, .. } if email == "joe@example.com"
+        ));
+        assert!(identities.take_warnings().is_empty());
+    }
+}
@@ -0,0 +1,220 @@
+use crate::number::NumberFormat;
+use git_whoknows::{Owner, TrackedFile};
+
+use anyhow::Result;
+use crossterm::event::{self, Event, KeyCode};
+use crossterm::execute;
+use crossterm::terminal::{
+    disable_raw_mode, enable_raw_mode, EnterAlternateScreen, LeaveAlternateScreen,
+};
+use tui::backend::{Backend, CrosstermBackend};
+use tui::layout::{Constraint, Direction, Layout};
+use tui::style::{Modifier, Style};
+use tui::widgets::{Block, Borders, List, ListItem, ListState, Paragraph};
+use tui::{Frame, Terminal};
+
+use std::io;
+
+/// Browse the analyzed files and their owners in the terminal.
+pub fn run(files: &[TrackedFile], numbers: &NumberFormat) -> Result<()> {
+    enable_raw_mode()?;
+    let mut stdout = io::stdout();
+    execute!(stdout, EnterAlternateScreen)?;
+    let mut terminal = Terminal::new(CrosstermBackend::new(stdout))?;
+
+    let result = Browser::new(files, numbers).run(&mut terminal);
+
+    disable_raw_mode()?;
+    execute!(terminal.backend_mut(), LeaveAlternateScreen)?;
+    terminal.show_cursor()?;
+    result
+}
+
+/// Key the owners of the selected file are sorted by.
+#[derive(Clone, Copy)]
+enum OwnerSort {
+    Lines,
+    Commits,
+    Name,
+}
+
+impl OwnerSort {
+    fn next(self) -> OwnerSort {
+        match self {
+            OwnerSort::Lines => OwnerSort::Commits,
+            OwnerSort::Commits => OwnerSort::Name,
+            OwnerSort::Name => OwnerSort::Lines,
+        }
+    }
+
+    fn name(self) -> &'static str {
+        match self {
+            OwnerSort::Lines => "lines",
+            OwnerSort::Commits => "commits",
+            OwnerSort::Name => "name",
+        }
+    }
+}
+
+struct Browser<'a> {
+    files: Vec<&'a TrackedFile>,
+    numbers: &'a NumberFormat,
+    selected: ListState,
+    sort: OwnerSort,
+    filter: String,
+    editing_filter: bool,
+}
+
+impl<'a> Browser<'a> {
+    fn new(files: &'a [TrackedFile], numbers: &'a NumberFormat) -> Browser<'a> {
+        let mut files: Vec<&TrackedFile> = files.iter().collect();
+        files.sort_by(|a, b| a.path.cmp(&b.path));
+
+        let mut selected = ListState::default();
+        if !files.is_empty() {
+            selected.select(Some(0));
+        }
+
+        Browser {
+            files,
+            numbers,
+            selected,
+            sort: OwnerSort::Lines,
+            filter: String::new(),
+            editing_filter: false,
+        }
+    }
+
+    fn run<B: Backend>(&mut self, terminal: &mut Terminal<B>) -> Result<()> {
+        loop {
+            terminal.draw(|f| self.draw(f))?;
+
+            let key = match event::read()? {
+                Event::Key(key) => key.code,
+                _ => continue,
+            };
+
+            if self.editing_filter {
+                match key {
+                    KeyCode::Enter => self.editing_filter = false,
+                    KeyCode::Esc => {
+                        self.filter.clear();
+                        self.editing_filter = false;
+                    }
+                    KeyCode::Backspace => {
+                        self.filter.pop();
+                    }
+                    KeyCode::Char(c) => self.filter.push(c),
+                    _ => {}
+                }
+                continue;
+            }
+
+            match key {
+                KeyCode::Char('q') | KeyCode::Esc => return Ok(()),
+                KeyCode::Down | KeyCode::Char('j') => self.move_selection(1),
+                KeyCode::Up | KeyCode::Char('k') => self.move_selection(-1),
+                KeyCode::Char('s') => self.sort = self.sort.next(),
+                KeyCode::Char('/') => self.editing_filter = true,
+                _ => {}
+            }
+        }
+    }
+
+    fn move_selection(&mut self, offset: isize) {
+        if self.files.is_empty() {
+            return;
+        }
+        let current = self.selected.selected().unwrap_or(0) as isize;
+        let last = self.files.len() as isize - 1;
+        let next = (current + offset).max(0).min(last);
+        self.selected.select(Some(next as usize));
+    }
+
+    /// Owners of the selected file that match the filter, in sorted order.
+    fn owners(&self) -> Vec<&'a Owner> {
+        let file = match self.selected.selected() {
+            Some(index) => self.files[index],
+            None => return Vec::new(),
+        };
+
+        let filter = self.filter.to_lowercase();
+        let mut owners: Vec<&Owner> = file
+            .owners
+            .values()
+            .filter(|o| {
+                o.name.to_lowercase().contains(&filter) || o.email.to_lowercase().contains(&filter)
+            })
+            .collect();
+        match self.sort {
+            OwnerSort::Lines => {
+                owners.sort_by_key(|o| o.lines());
+                owners.reverse();
+            }
+            OwnerSort::Commits => {
+                owners.sort_by_key(|o| o.commits.len());
+                owners.reverse();
+            }
+            OwnerSort::Name => owners.sort_by(|a, b| a.name.cmp(&b.name)),
+        }
+        owners
+    }
+
+    fn draw<B: Backend>(&mut self, f: &mut Frame<B>) {
+        let rows = Layout::default()
+            .direction(Direction::Vertical)
+            .constraints([Constraint::Min(1), Constraint::Length(1)].as_ref())
+            .split(f.size());
+        let panes = Layout::default()
+            .direction(Direction::Horizontal)
+            .constraints([Constraint::Percentage(40), Constraint::Percentage(60)].as_ref())
+            .split(rows[0]);
+
+        let owners: Vec<ListItem> = match self.selected.selected() {
+            Some(index) => {
+                let file = self.files[index];
+                self.owners()
+                    .iter()
+                    .map(|o| {
+                        ListItem::new(format!(
+                            "{:>6} {} <{}>: Lines: {} Count: {}",
+                            self.numbers.percent(file.owner_percentage(o)),
+                            o.name,
+                            o.email,
+                            self.numbers.count(o.lines()),
+                            self.numbers.count(o.commits.len())
+                        ))
+                    })
+                    .collect()
+            }
+            None => Vec::new(),
+        };
+        let owners = List::new(owners).block(
+            Block::default()
+                .borders(Borders::ALL)
+                .title(format!("Owners by {}", self.sort.name())),
+        );
+
+        let files: Vec<ListItem> = self
+            .files
+            .iter()
+            .map(|t| ListItem::new(t.path.as_str()))
+            .collect();
+        let files = List::new(files)
+            .block(Block::default().borders(Borders::ALL).title("Files"))
+            .highlight_style(Style::default().add_modifier(Modifier::REVERSED));
+
+        let status = if self.editing_filter {
+            format!("filter: {}_", self.filter)
+        } else {
+            format!(
+                "q quit  j/k move  s sort  / filter  [filter: {}]",
+                self.filter
+            )
+        };
+
+        f.render_stateful_widget(files, panes[0], &mut self.selected);
+        f.render_widget(owners, panes[1]);
+        f.render_widget(Paragraph::new(status), rows[1]);
+    }
+}
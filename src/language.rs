@@ -0,0 +1,304 @@
+use anyhow::Result;
+
+use std::path::Path;
+use std::str::FromStr;
+
+/// Prefixes marking a line as a comment in each language known to
+/// `--skip-comments`.
+///
+/// Only lines starting with one of the prefixes are recognized, so the body
+/// of a block comment is still counted.
+const COMMENT_PREFIXES: &[(&str, &[&str])] = &[
+    ("c", &["//", "/*"]),
+    ("cpp", &["//", "/*"]),
+    ("csharp", &["//", "/*"]),
+    ("go", &["//", "/*"]),
+    ("haskell", &["--", "{-"]),
+    ("java", &["//", "/*"]),
+    ("javascript", &["//", "/*"]),
+    ("lisp", &[";"]),
+    ("lua", &["--"]),
+    ("perl", &["#"]),
+    ("python", &["#"]),
+    ("ruby", &["#"]),
+    ("rust", &["//", "/*"]),
+    ("shell", &["#"]),
+    ("sql", &["--"]),
+    ("toml", &["#"]),
+    ("typescript", &["//", "/*"]),
+    ("yaml", &["#"]),
+];
+
+/// Language of files with each extension, used by `--by-language`.
+const EXTENSIONS: &[(&str, &str)] = &[
+    ("bash", "shell"),
+    ("c", "c"),
+    ("cc", "cpp"),
+    ("cpp", "cpp"),
+    ("cs", "csharp"),
+    ("cxx", "cpp"),
+    ("el", "lisp"),
+    ("go", "go"),
+    ("h", "c"),
+    ("hpp", "cpp"),
+    ("hs", "haskell"),
+    ("java", "java"),
+    ("js", "javascript"),
+    ("jsx", "javascript"),
+    ("lisp", "lisp"),
+    ("lua", "lua"),
+    ("md", "markdown"),
+    ("pl", "perl"),
+    ("pm", "perl"),
+    ("py", "python"),
+    ("rb", "ruby"),
+    ("rs", "rust"),
+    ("sh", "shell"),
+    ("sql", "sql"),
+    ("toml", "toml"),
+    ("ts", "typescript"),
+    ("tsx", "typescript"),
+    ("yaml", "yaml"),
+    ("yml", "yaml"),
+];
+
+/// Language of files without a known extension.
+pub const OTHER_LANGUAGE: &str = "other";
+
+/// Override of the language of files with an extension.
+#[derive(Clone)]
+pub struct ExtensionLanguage {
+    extension: String,
+    language: String,
+}
+
+impl FromStr for ExtensionLanguage {
+    type Err = anyhow::Error;
+
+    /// Parse an override of the form `ext=language`.
+    fn from_str(s: &str) -> Result<ExtensionLanguage> {
+        let split = s
+            .find('=')
+            .ok_or_else(|| anyhow::Error::msg("Expected a language of the form ext=language"))?;
+        let extension = s[..split].trim().trim_start_matches('.');
+        let language = s[split + 1..].trim();
+        if extension.is_empty() || language.is_empty() {
+            return Err(anyhow::Error::msg(
+                "Extension and language must not be empty",
+            ));
+        }
+        Ok(ExtensionLanguage {
+            extension: extension.to_lowercase(),
+            language: language.to_string(),
+        })
+    }
+}
+
+/// Comment prefixes of files with an extension, for `--comment-prefixes`.
+#[derive(Clone)]
+pub struct ExtensionComments {
+    extension: String,
+    prefixes: Vec<String>,
+}
+
+impl FromStr for ExtensionComments {
+    type Err = anyhow::Error;
+
+    /// Parse prefixes of the form `ext=prefix,prefix`.
+    fn from_str(s: &str) -> Result<ExtensionComments> {
+        let split = s.find('=').ok_or_else(|| {
+            anyhow::Error::msg("Expected comment prefixes of the form ext=prefix,prefix")
+        })?;
+        let extension = s[..split].trim().trim_start_matches('.');
+        let prefixes: Vec<String> = s[split + 1..]
+            .split(',')
+            .map(str::trim)
+            .filter(|prefix| !prefix.is_empty())
+            .map(str::to_string)
+            .collect();
+        if extension.is_empty() || prefixes.is_empty() {
+            return Err(anyhow::Error::msg(
+                "Extension and comment prefixes must not be empty",
+            ));
+        }
+        Ok(ExtensionComments {
+            extension: extension.to_lowercase(),
+            prefixes,
+        })
+    }
+}
+
+/// Detects the language of files from their extension.
+#[derive(Default)]
+pub struct Languages {
+    overrides: Vec<ExtensionLanguage>,
+}
+
+impl Languages {
+    pub fn new(overrides: Vec<ExtensionLanguage>) -> Languages {
+        Languages { overrides }
+    }
+
+    /// Language of the file at `path`, or `OTHER_LANGUAGE` when the extension
+    /// is not known.
+    pub fn detect(&self, path: &Path) -> &str {
+        let extension = match path.extension().and_then(|e| e.to_str()) {
+            Some(extension) => extension.to_lowercase(),
+            None => return OTHER_LANGUAGE,
+        };
+        // Later overrides take precedence over earlier ones.
+        if let Some(o) = self
+            .overrides
+            .iter()
+            .rev()
+            .find(|o| o.extension == extension)
+        {
+            return &o.language;
+        }
+        EXTENSIONS
+            .iter()
+            .find(|(e, _)| *e == extension)
+            .map_or(OTHER_LANGUAGE, |(_, language)| language)
+    }
+}
+
+/// Selects which lines of a file count towards ownership.
+#[derive(Clone, Default)]
+pub struct LineFilter {
+    skip_blank: bool,
+    comment_prefixes: Vec<String>,
+    /// Extra comment prefixes of files with each extension.
+    extension_prefixes: Vec<ExtensionComments>,
+}
+
+impl LineFilter {
+    /// Create a filter skipping blank lines if `skip_blank` is set, and the
+    /// comments of `language` if one is given.
+    pub fn new(skip_blank: bool, language: Option<&str>) -> Result<LineFilter> {
+        let comment_prefixes = match language {
+            Some(language) => COMMENT_PREFIXES
+                .iter()
+                .find(|(name, _)| name.eq_ignore_ascii_case(language))
+                .map(|(_, prefixes)| prefixes.iter().map(|p| p.to_string()).collect())
+                .ok_or_else(|| {
+                    let known: Vec<&str> = COMMENT_PREFIXES.iter().map(|(name, _)| *name).collect();
+                    anyhow::Error::msg(format!(
+                        "Unknown language {}, expected one of: {}",
+                        language,
+                        known.join(", ")
+                    ))
+                })?,
+            None => Vec::new(),
+        };
+        Ok(LineFilter {
+            skip_blank,
+            comment_prefixes,
+            extension_prefixes: Vec::new(),
+        })
+    }
+
+    /// Also skip lines starting with the comment prefixes given for the
+    /// extension of a file.
+    pub fn with_extension_prefixes(mut self, prefixes: Vec<ExtensionComments>) -> LineFilter {
+        self.extension_prefixes = prefixes;
+        self
+    }
+
+    /// Filter for the lines of the file at `path`, adding the comment prefixes
+    /// given for its extension.
+    pub fn for_file(&self, path: &Path) -> LineFilter {
+        let mut filter = self.clone();
+        if let Some(extension) = path.extension().and_then(|e| e.to_str()) {
+            let extension = extension.to_lowercase();
+            self.extension_prefixes
+                .iter()
+                .filter(|e| e.extension == extension)
+                .for_each(|e| filter.comment_prefixes.extend(e.prefixes.iter().cloned()));
+        }
+        filter
+    }
+
+    /// Check whether a line with the given content is counted.
+    pub fn counts(&self, line: &str) -> bool {
+        let line = line.trim();
+        if self.skip_blank && line.is_empty() {
+            return false;
+        }
+        !self
+            .comment_prefixes
+            .iter()
+            .any(|prefix| line.starts_with(prefix.as_str()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_default_counts_everything() {
+        let filter = LineFilter::default();
+        assert!(filter.counts(""));
+        assert!(filter.counts("// comment"));
+    }
+
+    #[test]
+    fn test_skip_blank_and_comments() {
+        let filter = LineFilter::new(true, Some("Rust")).unwrap();
+        assert!(!filter.counts(""));
+        assert!(!filter.counts(" \t"));
+        assert!(!filter.counts("    // comment"));
+        assert!(!filter.counts("/// doc comment"));
+        assert!(filter.counts("let x = 1; // trailing comment"));
+        assert!(filter.counts("}"));
+    }
+
+    #[test]
+    fn test_extension_prefixes() {
+        let filter = LineFilter::new(false, None)
+            .unwrap()
+            .with_extension_prefixes(vec![
+                ".nix=#".parse().unwrap(),
+                "ASM=;, //".parse().unwrap(),
+            ]);
+        let nix = filter.for_file(Path::new("default.nix"));
+        assert!(!nix.counts("  # comment"));
+        assert!(nix.counts("// not a comment"));
+        let asm = filter.for_file(Path::new("boot.s.asm"));
+        assert!(!asm.counts("; comment"));
+        assert!(!asm.counts("// comment"));
+        assert!(filter
+            .for_file(Path::new("main.rs"))
+            .counts("# not a comment"));
+        assert!(filter
+            .for_file(Path::new("Makefile"))
+            .counts("# not a comment"));
+
+        assert!("nix".parse::<ExtensionComments>().is_err());
+        assert!("nix=,".parse::<ExtensionComments>().is_err());
+    }
+
+    #[test]
+    fn test_unknown_language() {
+        assert!(LineFilter::new(false, Some("klingon")).is_err());
+    }
+
+    #[test]
+    fn test_detect_language() {
+        let languages = Languages::default();
+        assert_eq!(languages.detect(Path::new("src/main.rs")), "rust");
+        assert_eq!(languages.detect(Path::new("setup.PY")), "python");
+        assert_eq!(languages.detect(Path::new("Makefile")), OTHER_LANGUAGE);
+        assert_eq!(languages.detect(Path::new("data.xyz")), OTHER_LANGUAGE);
+    }
+
+    #[test]
+    fn test_override_language() {
+        let languages =
+            Languages::new(vec!["h=cpp".parse().unwrap(), ".xyz=data".parse().unwrap()]);
+        assert_eq!(languages.detect(Path::new("include/lib.h")), "cpp");
+        assert_eq!(languages.detect(Path::new("data.xyz")), "data");
+        assert!("rs".parse::<ExtensionLanguage>().is_err());
+        assert!("rs=".parse::<ExtensionLanguage>().is_err());
+    }
+}
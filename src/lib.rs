@@ -0,0 +1,21 @@
+//! The ownership engine of git-whoknows: parsers for the output of git, and
+//! the analysis crediting the lines of each file to their owners, exposed as a
+//! library so other programs, benchmarks and integration tests can call them
+//! directly.
+
+#[macro_use]
+extern crate nom;
+
+pub mod analyze;
+pub mod blame;
+pub mod error;
+#[cfg(test)]
+mod fixture;
+pub mod identity;
+pub mod language;
+pub mod numstat;
+pub mod origin;
+pub mod ownership;
+
+pub use analyze::{analyze, Analyzer, Method, Options};
+pub use ownership::{Hunk, Owner, TrackedFile};
@@ -1,263 +1,904 @@
-#[macro_use]
-extern crate nom;
-
-mod blame;
+mod anonymize;
+mod codeowners;
+mod csv;
+#[cfg(test)]
+#[path = "fixture.rs"]
+mod fixture;
+#[cfg(feature = "interactive")]
+mod interactive;
+mod number;
+mod progress;
+mod report;
+mod sarif;
+mod self_test;
 
 use anyhow::Result;
+use chrono::{DateTime, FixedOffset, Local};
+use csv::Granularity;
 use dashmap::DashMap;
-use git2::{BlameHunk, Commit, Oid, Repository};
+use git2::{Oid, Repository};
+use git_whoknows::analyze::{
+    repo_relative_path, split_existing, Analyzer, BlameSettings, Method, Options, PathStyle,
+};
+use git_whoknows::blame;
+use git_whoknows::error::WhoKnowsError;
+use git_whoknows::identity::{self, Attribute, AuthorMerge, Identities, Mailmap};
+use git_whoknows::language::{ExtensionComments, ExtensionLanguage, Languages, LineFilter};
+#[cfg(test)]
+use git_whoknows::ownership;
+use git_whoknows::ownership::{concentration, percentage, DateWindow, Owner, Period, TrackedFile};
+use number::NumberFormat;
 use rayon::prelude::*;
-use regex::Regex;
-use std::collections::HashMap;
-use std::fmt;
+use std::collections::hash_map::RandomState;
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+use std::io::{self, BufRead, IsTerminal, Read, Write};
 use std::path::{Path, PathBuf};
 use std::process::Command;
+use std::str::FromStr;
+use std::time::{Duration, Instant};
 use structopt::clap::AppSettings;
 use structopt::StructOpt;
+use unicode_segmentation::UnicodeSegmentation;
 
-struct TrackedFile {
-    path: String,
-    owners: HashMap<String, Owner>,
+/// Format the results are printed in.
+#[derive(Clone, Copy, PartialEq)]
+enum Format {
+    Text,
+    Json,
+    Bincode,
+    Sarif,
+    Csv,
 }
 
-impl TrackedFile {
-    fn new(path: String) -> TrackedFile {
-        TrackedFile {
-            path,
-            owners: HashMap::new(),
+impl FromStr for Format {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Format> {
+        match s {
+            "text" => Ok(Format::Text),
+            "json" => Ok(Format::Json),
+            "bincode" => Ok(Format::Bincode),
+            "sarif" => Ok(Format::Sarif),
+            "csv" => Ok(Format::Csv),
+            _ => Err(anyhow::Error::msg(
+                "Format must be one of text, json, bincode, sarif or csv",
+            )),
         }
     }
+}
 
-    fn add_hunk(&mut self, hunk: &impl Hunk) {
-        let owner = Owner::new(hunk);
-        self.owners
-            .entry(hunk.email())
-            .or_insert(owner)
-            .add_hunk(hunk);
+/// Format a concentration for display after a heading.
+fn concentration_suffix(concentration: Option<f64>) -> String {
+    match concentration {
+        Some(concentration) => format!(" (concentration: {:.2})", concentration),
+        None => String::new(),
     }
 }
 
-struct Owner {
-    name: String,
-    email: String,
-    commits: HashMap<String, usize>,
+/// Range of lines of each file to blame, from `start` to `end` inclusive.
+#[derive(Clone, Copy, Debug, PartialEq)]
+struct LineRange {
+    start: usize,
+    end: usize,
 }
 
-impl Owner {
-    fn new(hunk: &impl Hunk) -> Owner {
-        Owner {
-            name: hunk.author(),
-            email: hunk.email(),
-            commits: HashMap::new(),
+impl FromStr for LineRange {
+    type Err = anyhow::Error;
+
+    /// Parse a range of the form `start,end`, counting lines from 1.
+    fn from_str(s: &str) -> Result<LineRange> {
+        let error = || {
+            anyhow::Error::msg("Line range must be of the form START,END with 1 <= START <= END")
+        };
+        let (start, end) = s.split_once(',').ok_or_else(error)?;
+        let (start, end): (usize, usize) = (
+            start.trim().parse().map_err(|_| error())?,
+            end.trim().parse().map_err(|_| error())?,
+        );
+        if start == 0 || start > end {
+            return Err(error());
         }
+        Ok(LineRange { start, end })
     }
+}
+
+/// Author times of the oldest and newest commits owning lines.
+fn date_range<'a>(
+    owners: impl Iterator<Item = &'a Owner>,
+) -> Option<(DateTime<FixedOffset>, DateTime<FixedOffset>)> {
+    owners
+        .flat_map(|o| o.dates.values())
+        .fold(None, |range, date| match range {
+            None => Some((*date, *date)),
+            Some((oldest, newest)) => Some((oldest.min(*date), newest.max(*date))),
+        })
+}
 
-    fn add_hunk(&mut self, hunk: &impl Hunk) {
-        *self.commits.entry(hunk.sha1()).or_insert(0) += hunk.lines();
+fn date_range_suffix(range: Option<(DateTime<FixedOffset>, DateTime<FixedOffset>)>) -> String {
+    match range {
+        Some((oldest, newest)) => format!(
+            " (oldest: {}, newest: {})",
+            oldest.format("%Y-%m"),
+            newest.format("%Y-%m")
+        ),
+        None => String::new(),
     }
+}
 
-    fn lines(&self) -> usize {
-        self.commits.values().sum::<usize>()
+/// Shorten `s` to at most `width` characters, ending with an ellipsis when
+/// cut. Characters are counted as graphemes so none are split. Nothing, not
+/// even the ellipsis, fits in a width of 0.
+fn truncate(s: &str, width: usize) -> String {
+    if s.graphemes(true).nth(width).is_none() {
+        return s.to_string();
+    }
+    if width == 0 {
+        return String::new();
     }
+    let mut truncated: String = s.graphemes(true).take(width.saturating_sub(1)).collect();
+    truncated.push('…');
+    truncated
+}
 
-    fn merge(&mut self, other: &Owner) {
-        other
-            .commits
-            .iter()
-            .for_each(|(hash, lines)| *self.commits.entry(hash.to_string()).or_insert(0) += lines);
+/// Style of the dates in the text output.
+#[derive(Clone, Copy)]
+enum DateStyle {
+    /// Time before now, such as "3 years ago".
+    Relative,
+    Iso,
+    /// The day only.
+    Short,
+    /// Seconds since the Unix epoch.
+    Unix,
+}
+
+impl DateStyle {
+    fn format(self, date: DateTime<FixedOffset>, now: DateTime<FixedOffset>) -> String {
+        match self {
+            DateStyle::Relative => relative_date(now.signed_duration_since(date)),
+            DateStyle::Iso => date.to_rfc3339(),
+            DateStyle::Short => date.format("%Y-%m-%d").to_string(),
+            DateStyle::Unix => date.timestamp().to_string(),
+        }
     }
 }
 
-impl fmt::Display for Owner {
-    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(
-            f,
-            "{} <{}>: Lines: {} Count: {}",
-            self.name,
-            self.email,
-            self.lines(),
-            self.commits.len()
-        )
+impl FromStr for DateStyle {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<DateStyle> {
+        match s {
+            "relative" => Ok(DateStyle::Relative),
+            "iso" => Ok(DateStyle::Iso),
+            "short" => Ok(DateStyle::Short),
+            "unix" => Ok(DateStyle::Unix),
+            _ => Err(anyhow::Error::msg(
+                "Date must be one of relative, iso, short or unix",
+            )),
+        }
     }
 }
 
-/// Definition of a hunk with no dependencies.
-struct BasicHunk {
-    hash: String,
-    author: String,
-    mail: String,
-    num_lines: usize,
+/// Time zone dates are shown in, instead of the zone each commit was made in.
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Zone {
+    Local,
+    Utc,
+    Offset(FixedOffset),
 }
 
-impl Hunk for BasicHunk {
-    fn sha1(&self) -> String {
-        self.hash.clone()
-    }
-    fn author(&self) -> String {
-        self.author.clone()
-    }
-    fn email(&self) -> String {
-        self.mail.clone()
+impl Zone {
+    fn convert(self, date: DateTime<FixedOffset>) -> DateTime<FixedOffset> {
+        match self {
+            Zone::Local => date.with_timezone(&Local).into(),
+            Zone::Utc => date.with_timezone(&FixedOffset::east(0)),
+            Zone::Offset(offset) => date.with_timezone(&offset),
+        }
     }
-    fn lines(&self) -> usize {
-        self.num_lines
+}
+
+impl FromStr for Zone {
+    type Err = anyhow::Error;
+
+    /// Parse `LOCAL`, `UTC` or an offset from UTC such as `+0530`.
+    fn from_str(s: &str) -> Result<Zone> {
+        let error =
+            || anyhow::Error::msg("Time zone must be LOCAL, UTC or an offset such as +0530");
+        match s.to_uppercase().as_str() {
+            "LOCAL" => return Ok(Zone::Local),
+            "UTC" => return Ok(Zone::Utc),
+            _ => {}
+        }
+        blame::parse_tz(s).map(Zone::Offset).ok_or_else(error)
     }
 }
 
-struct RawHunk<'rh> {
-    commit: Commit<'rh>,
-    _lines: usize,
+/// Describe how long ago something happened, in the largest whole unit.
+fn relative_date(age: chrono::Duration) -> String {
+    let ago = |count: i64, unit: &str| {
+        if count == 1 {
+            format!("1 {} ago", unit)
+        } else {
+            format!("{} {}s ago", count, unit)
+        }
+    };
+    let days = age.num_days();
+    if age.num_minutes() < 1 {
+        "just now".to_string()
+    } else if age.num_hours() < 1 {
+        ago(age.num_minutes(), "minute")
+    } else if days < 1 {
+        ago(age.num_hours(), "hour")
+    } else if days == 1 {
+        "yesterday".to_string()
+    } else if days < 14 {
+        ago(days, "day")
+    } else if days < 60 {
+        ago(days / 7, "week")
+    } else if days < 365 {
+        ago(days / 30, "month")
+    } else {
+        ago(days / 365, "year")
+    }
 }
 
-trait Hunk {
-    fn sha1(&self) -> String;
-    fn author(&self) -> String;
-    fn email(&self) -> String;
-    fn lines(&self) -> usize;
+/// Identifier owners are shown by in the text output.
+#[derive(Clone, Copy)]
+enum DisplayName {
+    Name,
+    Email,
+    /// The name, or the local part of the email when there is no name.
+    NameOrEmail,
 }
 
-impl Hunk for &RawHunk<'_> {
-    fn sha1(&self) -> String {
-        self.commit.id().to_string()
-    }
-    fn author(&self) -> String {
-        String::from_utf8_lossy(self.commit.author().name_bytes()).to_string()
+impl DisplayName {
+    /// Identifier of `owner`, falling back to the other field when the
+    /// preferred one is empty.
+    fn of(self, owner: &Owner) -> String {
+        let name = owner.name.trim();
+        let email = owner.email.trim();
+        let local_part = || email.split('@').next().unwrap_or(email);
+        let shown = match self {
+            DisplayName::Name if name.is_empty() => email,
+            DisplayName::Name => name,
+            DisplayName::Email if email.is_empty() => name,
+            DisplayName::Email => email,
+            DisplayName::NameOrEmail if name.is_empty() => local_part(),
+            DisplayName::NameOrEmail => name,
+        };
+        shown.to_string()
     }
-    fn email(&self) -> String {
-        String::from_utf8_lossy(self.commit.author().email_bytes()).to_string()
+}
+
+impl FromStr for DisplayName {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<DisplayName> {
+        match s {
+            "name" => Ok(DisplayName::Name),
+            "email" => Ok(DisplayName::Email),
+            "name-or-email" => Ok(DisplayName::NameOrEmail),
+            _ => Err(anyhow::Error::msg(
+                "Display must be one of name, email or name-or-email",
+            )),
+        }
     }
-    fn lines(&self) -> usize {
-        self._lines
+}
+
+/// Order owners are listed in, highest first.
+#[derive(Clone, Copy)]
+enum Sort {
+    Lines,
+    Ratio,
+    /// Lines weighted by their age, with the given half-life in days.
+    Weighted(f64),
+}
+
+impl FromStr for Sort {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Sort> {
+        match s {
+            "lines" => Ok(Sort::Lines),
+            "ratio" => Ok(Sort::Ratio),
+            _ => Err(anyhow::Error::msg("Sort must be one of lines or ratio")),
+        }
     }
 }
 
-impl Hunk for BlameHunk<'_> {
-    fn sha1(&self) -> String {
-        self.final_commit_id().to_string()
+fn sort_owners<O: std::borrow::Borrow<Owner>>(owners: &mut [O], sort: Sort) {
+    let now = Local::now().into();
+    owners.sort_by(|a, b| {
+        let (a, b) = (a.borrow(), b.borrow());
+        match sort {
+            Sort::Lines => b.lines().cmp(&a.lines()),
+            Sort::Ratio => b.ratio().total_cmp(&a.ratio()),
+            Sort::Weighted(half_life) => b
+                .weighted_lines(half_life, now)
+                .total_cmp(&a.weighted_lines(half_life, now)),
+        }
+        .then_with(|| a.email.cmp(&b.email))
+    });
+}
+
+/// Order files are listed in.
+#[derive(Clone, Copy)]
+enum FileSort {
+    /// By path, alphabetically.
+    Path,
+    /// Most owners first.
+    Owners,
+    /// Most lines first.
+    Lines,
+    /// Lowest bus factor first, so the riskiest files lead.
+    BusFactor,
+}
+
+impl FromStr for FileSort {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<FileSort> {
+        match s {
+            "path" => Ok(FileSort::Path),
+            "owners" => Ok(FileSort::Owners),
+            "lines" => Ok(FileSort::Lines),
+            "bus-factor" => Ok(FileSort::BusFactor),
+            _ => Err(anyhow::Error::msg(
+                "File sort must be one of path, owners, lines or bus-factor",
+            )),
+        }
     }
-    fn author(&self) -> String {
-        String::from_utf8_lossy(self.final_signature().name_bytes()).to_string()
+}
+
+/// Sort `files`, keeping the order they were given in between equal files,
+/// with the bus factor taken at `bus_factor_threshold` percent of the lines.
+fn sort_files(files: &mut [TrackedFile], sort: FileSort, bus_factor_threshold: f64) {
+    match sort {
+        FileSort::Path => files.sort_by(|a, b| a.path.cmp(&b.path)),
+        FileSort::Owners => files.sort_by_key(|file| std::cmp::Reverse(file.owners.len())),
+        FileSort::Lines => files.sort_by_key(|file| std::cmp::Reverse(file.lines())),
+        FileSort::BusFactor => {
+            files.sort_by_key(|file| file.bus_factor_above(bus_factor_threshold))
+        }
     }
-    fn email(&self) -> String {
-        String::from_utf8_lossy(self.final_signature().email_bytes()).to_string()
+}
+
+/// Length commit hashes are abbreviated to in the output.
+#[derive(Clone, Copy)]
+enum Abbrev {
+    Length(usize),
+    /// The shortest length leaving the hashes in the output unambiguous.
+    Auto,
+}
+
+/// Shortest abbreviation git accepts.
+const MIN_ABBREV: usize = 4;
+
+impl Abbrev {
+    /// Length to abbreviate `hashes` to.
+    fn length<'a>(self, hashes: impl Iterator<Item = &'a str>) -> usize {
+        match self {
+            Abbrev::Length(length) => length,
+            Abbrev::Auto => unambiguous_length(hashes),
+        }
     }
-    fn lines(&self) -> usize {
-        self.lines_in_hunk()
+}
+
+impl FromStr for Abbrev {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Abbrev> {
+        match s {
+            "auto" => Ok(Abbrev::Auto),
+            _ => match s.parse::<usize>() {
+                Ok(length) if length >= MIN_ABBREV => Ok(Abbrev::Length(length)),
+                _ => Err(anyhow::Error::msg(format!(
+                    "Abbreviation must be auto or a length of at least {}",
+                    MIN_ABBREV
+                ))),
+            },
+        }
     }
 }
 
-fn run_external_blame<'rh>(repo: &'rh Repository, path: &PathBuf) -> Result<Vec<RawHunk<'rh>>> {
-    let mut hunks: Vec<RawHunk> = Vec::new();
+/// Length commit hashes are abbreviated to without `--abbrev`, from
+/// `core.abbrev` or 7.
+fn default_abbrev() -> usize {
+    Repository::discover(".")
+        .and_then(|repo| repo.config())
+        .or_else(|_| git2::Config::open_default())
+        .and_then(|config| config.get_i32("core.abbrev"))
+        .ok()
+        .filter(|length| *length >= MIN_ABBREV as i32)
+        .map_or(7, |length| length as usize)
+}
 
-    let output = Command::new("git")
-        .arg("-C")
-        .arg(format!("{}", path.parent().unwrap().display().to_string()))
-        .arg("blame")
-        .arg("--line-porcelain")
-        .arg("--")
-        .arg(format!("{}", path.file_name().unwrap().to_str().unwrap()))
-        .output()?;
+/// Shortest length, no less than `MIN_ABBREV`, at which no two of `hashes`
+/// share a prefix.
+fn unambiguous_length<'a>(hashes: impl Iterator<Item = &'a str>) -> usize {
+    let mut hashes: Vec<&str> = hashes.collect();
+    hashes.sort_unstable();
+    hashes.dedup();
+    hashes
+        .windows(2)
+        .map(|pair| {
+            let common = pair[0]
+                .bytes()
+                .zip(pair[1].bytes())
+                .take_while(|(a, b)| a == b)
+                .count();
+            common + 1
+        })
+        .fold(MIN_ABBREV, usize::max)
+}
 
-    if !output.status.success() {
-        println!("Error with git-blame for {}", path.display());
-        return Err(anyhow::Error::msg("Error running git blame"));
+/// Content of the blob `oid` with the path of the file it is blamed as.
+///
+/// The path is `path` when given, otherwise the first path the blob has in the
+/// tree of HEAD. The repository is the one containing `path`, or the current
+/// directory.
+fn resolve_blob(oid: &str, path: Option<&Path>) -> Result<(String, PathBuf)> {
+    let dir = path.map_or_else(|| PathBuf::from("."), |path| split_existing(path).0);
+    let repo = Repository::discover(dir)?;
+    let blob = repo
+        .revparse_single(oid)
+        .map_err(|_| anyhow::Error::msg(format!("{} is not an object in the repository", oid)))?
+        .into_blob()
+        .map_err(|object| {
+            let kind = object.kind().map_or("unknown", |kind| kind.str());
+            anyhow::Error::msg(format!("{} is a {}, not a blob", oid, kind))
+        })?;
+    let contents = String::from_utf8_lossy(blob.content()).to_string();
+
+    if let Some(path) = path {
+        return Ok((contents, path.to_path_buf()));
+    }
+    let workdir = repo
+        .workdir()
+        .ok_or_else(|| anyhow::Error::msg("--blob needs a repository with a working tree"))?;
+    let tree = repo.head()?.peel_to_tree()?;
+    let mut found = None;
+    tree.walk(git2::TreeWalkMode::PreOrder, |parent, entry| {
+        if entry.id() == blob.id() {
+            found = Some(Path::new(parent).join(entry.name().unwrap_or_default()));
+            git2::TreeWalkResult::Abort
+        } else {
+            git2::TreeWalkResult::Ok
+        }
+    })
+    .ok();
+    match found {
+        Some(relative) => Ok((contents, workdir.join(relative))),
+        None => Err(anyhow::Error::msg(format!(
+            "blob {} is not in the tree of HEAD, give the file it belongs to with --path",
+            oid
+        ))),
     }
+}
 
-    let pattern = Regex::new(
-        r"(?x)
-          ^([0-9a-zA-Z]{40})\s+ # 40 character SHA-1
-          [0-9]+\s+ # Original line number
-          [0-9]+\s+ # Final line number
-          ([0-9]+) # Line count",
-    )?;
+/// Hash of the common ancestor of revisions `a` and `b` in the repository
+/// containing `dir`.
+fn merge_base(dir: &Path, a: &str, b: &str) -> Result<String> {
+    let repo = Repository::discover(dir)?;
+    let resolve =
+        |rev: &str| -> Result<Oid> { Ok(repo.revparse_single(rev)?.peel_to_commit()?.id()) };
+    let base = repo
+        .merge_base(resolve(a)?, resolve(b)?)
+        .map_err(|e| match e.code() {
+            git2::ErrorCode::NotFound => {
+                anyhow::Error::msg(format!("{} and {} have no common ancestor", a, b))
+            }
+            _ => e.into(),
+        })?;
+    Ok(base.to_string())
+}
 
-    String::from_utf8_lossy(&output.stdout)
-        .lines()
-        .filter_map(|line| pattern.captures(line))
-        .map(|cap| RawHunk {
-            commit: repo
-                .find_object(Oid::from_str(&cap[1].to_string()).unwrap(), None)
-                .unwrap()
-                .into_commit()
-                .unwrap(),
-            _lines: cap[2].to_string().parse::<usize>().unwrap(),
-        })
-        .for_each(|hunk| hunks.push(hunk));
+/// Hash of the ancestor `depth` first parents before `rev` in the repository
+/// containing `dir`, if the history is that deep.
+fn nth_ancestor(dir: &Path, rev: &str, depth: usize) -> Result<Option<String>> {
+    let repo = Repository::discover(dir)?;
+    let mut commit = repo.revparse_single(rev)?.peel_to_commit()?;
+    for _ in 0..depth {
+        commit = match commit.parents().next() {
+            Some(parent) => parent,
+            None => return Ok(None),
+        };
+    }
+    Ok(Some(commit.id().to_string()))
+}
 
-    Ok(hunks)
+/// Check that a file missing from the working tree exists in the tree of
+/// `rev`.
+fn check_exists_at_rev(path: &Path, rev: &str) -> Result<()> {
+    let repo = Repository::discover(split_existing(path).0)?;
+    let relative = repo_relative_path(&repo, path)?;
+    let tree = repo.revparse_single(rev)?.peel_to_tree()?;
+    tree.get_path(&relative).map_err(|_| {
+        anyhow::Error::msg(format!("{} does not exist at {}", relative.display(), rev))
+    })?;
+    Ok(())
 }
 
-fn analyze_file_nom(path: &Path) -> Result<TrackedFile> {
-    let txt = blame::generate_blame(&path.canonicalize().unwrap())?;
-    let lines = blame::parse_blame(&txt);
+/// Resolve the symlinks among `paths`, reporting each path that cannot be
+/// analyzed, such as a missing file or a dangling symlink, along with the
+/// number of those.
+///
+/// With `rev`, files missing from the working tree are kept if they exist at
+/// that revision and skipped with a warning otherwise.
+fn resolve_files(paths: &[PathBuf], rev: Option<&str>) -> (Vec<PathBuf>, usize) {
+    let mut failed = 0;
+    let resolved = paths
+        .iter()
+        .filter_map(|path| match rev {
+            Some(rev) if !path.exists() => match check_exists_at_rev(path, rev) {
+                Ok(()) => Some(path.to_path_buf()),
+                Err(e) => {
+                    eprintln!("warning: skipping {}: {}", path.display(), e);
+                    None
+                }
+            },
+            _ => match resolve_symlink(path) {
+                Ok(path) => Some(path),
+                Err(e) => {
+                    eprintln!("error: unable to analyze {}: {}", path.display(), e);
+                    failed += 1;
+                    None
+                }
+            },
+        })
+        .collect();
+    (resolved, failed)
+}
 
-    let commits: HashMap<&str, (&str, &str)> = lines
+/// Split the analyzed files from those that failed, reporting each failure,
+/// along with the number of failures.
+fn split_failures(
+    paths: &[PathBuf],
+    results: Vec<Result<TrackedFile, WhoKnowsError>>,
+) -> (Vec<TrackedFile>, usize) {
+    let mut failed = 0;
+    let tracked_files = paths
         .iter()
-        .filter_map(|line| {
-            if let Some(extra) = &line.header.extra {
-                Some((line.header.hash, (extra.author, extra.author_mail)))
-            } else {
+        .zip(results)
+        .filter_map(|(path, result)| match result {
+            Ok(tracked_file) => Some(tracked_file),
+            Err(e) => {
+                eprintln!("error: unable to analyze {}: {}", path.display(), e);
+                failed += 1;
                 None
             }
         })
         .collect();
+    (tracked_files, failed)
+}
+
+/// Remove later occurrences of files given more than once, comparing the
+/// canonical paths, noting each one removed unless `quiet`.
+fn dedup_files(paths: Vec<PathBuf>, quiet: bool) -> Vec<PathBuf> {
+    let mut seen = HashSet::new();
+    paths
+        .into_iter()
+        .filter(|path| {
+            let first = seen.insert(path.canonicalize().unwrap_or_else(|_| path.to_path_buf()));
+            if !first && !quiet {
+                eprintln!("note: ignoring duplicate {}", path.display());
+            }
+            first
+        })
+        .collect()
+}
+
+/// Keep the `count` largest files, by size in bytes, in their original order,
+/// returning them with the number of files left out.
+///
+/// Files that cannot be read, such as those only existing at a revision, sort
+/// as empty.
+fn largest_files(paths: Vec<PathBuf>, count: usize) -> (Vec<PathBuf>, usize) {
+    let mut by_size: Vec<(u64, usize)> = paths
+        .iter()
+        .enumerate()
+        .map(|(i, path)| (std::fs::metadata(path).map_or(0, |m| m.len()), i))
+        .collect();
+    by_size.sort_by(|a, b| b.0.cmp(&a.0).then(a.1.cmp(&b.1)));
+    let kept: HashSet<usize> = by_size.iter().take(count).map(|(_, i)| *i).collect();
+    let skipped = paths.len() - kept.len();
+    let paths = paths
+        .into_iter()
+        .enumerate()
+        .filter(|(i, _)| kept.contains(i))
+        .map(|(_, path)| path)
+        .collect();
+    (paths, skipped)
+}
+
+/// Files tracked by git under `dir`, relative to `dir`.
+fn tracked_files_under(dir: &Path) -> Result<Vec<PathBuf>, WhoKnowsError> {
+    let output = Command::new("git")
+        .current_dir(dir)
+        .args(["ls-files", "-z"])
+        .output()
+        .map_err(WhoKnowsError::spawn)?;
+    if !output.status.success() {
+        return Err(WhoKnowsError::git_failed(
+            "ls-files",
+            dir.to_path_buf(),
+            &output.stderr,
+        ));
+    }
+    Ok(String::from_utf8_lossy(&output.stdout)
+        .split('\0')
+        .filter(|path| !path.is_empty())
+        .map(PathBuf::from)
+        .collect())
+}
+
+/// Replace each directory in `paths` with the files tracked by git under it,
+/// keeping other paths as they are.
+///
+/// Untracked files, including ignored build output, are skipped so they do not
+/// fail to blame. Submodules are listed by git as a single path and are left
+/// out.
+fn expand_dirs(paths: &[PathBuf]) -> Result<Vec<PathBuf>, WhoKnowsError> {
+    let mut expanded = Vec::new();
+    for path in paths {
+        if !path.is_dir() {
+            expanded.push(path.to_path_buf());
+            continue;
+        }
+        let files = tracked_files_under(path)?
+            .into_iter()
+            .map(|file| {
+                if path == Path::new(".") {
+                    file
+                } else {
+                    path.join(file)
+                }
+            })
+            .filter(|file| !file.is_dir());
+        expanded.extend(files);
+    }
+    Ok(expanded)
+}
 
-    let mut tracked_file = TrackedFile::new(path.display().to_string());
+/// Files to analyze from `paths`, with the paths read from `input` as by
+/// `paths_from_input` and directories expanded as by `expand_dirs`.
+///
+/// Fails when there are none, such as when stdin is empty, rather than
+/// analyzing nothing and seeming to succeed.
+fn listed_files(paths: &[PathBuf], input: impl BufRead, null: bool) -> Result<Vec<PathBuf>> {
+    let mut files = paths.to_vec();
+    if files.is_empty() || files.iter().any(|path| path == Path::new("-")) {
+        files = paths_from_input(paths, input, null)?;
+    }
+    let files = expand_dirs(&files)?;
+    if files.is_empty() {
+        return Err(anyhow::Error::msg(
+            "No files to analyze, give . to analyze the files tracked under the current directory",
+        ));
+    }
+    Ok(files)
+}
 
-    lines
+/// Replace each `-` in `paths`, or all of them when there are none, with the
+/// paths read from `input`, such as the output of `git ls-files` piped to
+/// stdin. Paths are separated by newlines, or by NUL bytes if `null`, as with
+/// `git ls-files -z`. Empty paths are skipped.
+fn paths_from_input(
+    paths: &[PathBuf],
+    input: impl BufRead,
+    null: bool,
+) -> io::Result<Vec<PathBuf>> {
+    let mut listed = Vec::new();
+    for entry in input.split(if null { b'\0' } else { b'\n' }) {
+        let entry =
+            String::from_utf8(entry?).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+        let entry = if null {
+            entry.as_str()
+        } else {
+            entry.trim_end_matches('\r')
+        };
+        if !entry.is_empty() {
+            listed.push(PathBuf::from(entry));
+        }
+    }
+    if paths.is_empty() {
+        return Ok(listed);
+    }
+    // Input can only be read once, so a repeated `-` adds nothing.
+    let mut listed = Some(listed);
+    Ok(paths
         .iter()
-        .filter_map(|line| {
-            if let Some(num_lines_in_group) = line.header.num_lines_in_group {
-                let commit = commits
-                    .get(line.header.hash)
-                    .expect("Commit information must be known for hunk.");
-                Some(BasicHunk {
-                    hash: line.header.hash.to_string(),
-                    author: commit.0.to_string(),
-                    mail: commit
-                        .1
-                        .trim_start_matches("<")
-                        .trim_end_matches(">")
-                        .to_string(),
-                    num_lines: num_lines_in_group,
-                })
+        .flat_map(|path| {
+            if path == Path::new("-") {
+                listed.take().unwrap_or_default()
             } else {
-                None
+                vec![path.to_path_buf()]
             }
         })
-        .for_each(|hunk| {
-            tracked_file.add_hunk(&hunk);
-        });
+        .collect())
+}
 
-    Ok(tracked_file)
+/// Check that each of `revs` is an abbreviation of a commit hash, returning
+/// them in lower case to match the hashes git prints.
+fn ignored_revs(revs: &[String]) -> Result<Vec<String>> {
+    revs.iter()
+        .map(|rev| {
+            if rev.len() < MIN_ABBREV || !rev.chars().all(|c| c.is_ascii_hexdigit()) {
+                return Err(anyhow::Error::msg(format!(
+                    "Ignored revision {} is not a commit hash of at least {} characters",
+                    rev, MIN_ABBREV
+                )));
+            }
+            Ok(rev.to_lowercase())
+        })
+        .collect()
+}
+
+/// Full hashes of the commits among `revs` in the repository containing `dir`,
+/// whose changed lines blame credits to the commits before them.
+///
+/// Revisions not naming a commit there are left out, as `git blame
+/// --ignore-rev` fails on them.
+fn ignore_rev_hashes(dir: &Path, revs: &[String]) -> Vec<String> {
+    let repo = match Repository::discover(dir) {
+        Ok(repo) => repo,
+        Err(_) => return Vec::new(),
+    };
+    revs.iter()
+        .filter_map(|rev| repo.revparse_single(rev).ok()?.peel_to_commit().ok())
+        .map(|commit| commit.id().to_string())
+        .collect()
 }
 
-fn analyze_file(file: &PathBuf) -> Result<TrackedFile> {
-    let repo = Repository::discover(file)?;
+/// Check whether `path` has changes that are not committed, staged or not.
+fn has_uncommitted_changes(path: &Path) -> bool {
+    let check = || -> Result<bool> {
+        let repo = Repository::discover(split_existing(path).0)?;
+        let relative = repo_relative_path(&repo, path)?;
+        let status = repo.status_file(&relative)?;
+        Ok(!(status.is_empty() || status.contains(git2::Status::IGNORED)))
+    };
+    check().unwrap_or(false)
+}
 
-    // Construct the path relative to the Git repository.
-    let repo_base_path: PathBuf = repo.path().iter().take_while(|x| *x != ".git").collect();
-    let arg_path = file.canonicalize()?;
-    let path = if arg_path.starts_with(&repo_base_path) {
-        arg_path.strip_prefix(&repo_base_path)?.to_path_buf()
-    } else {
-        arg_path
+/// Path of the submodule containing `path`, relative to the root of its
+/// superproject, when `path` is in a submodule.
+fn submodule_of(path: &Path) -> Option<PathBuf> {
+    let repo = Repository::discover(split_existing(path).0).ok()?;
+    let workdir = repo.workdir()?.canonicalize().ok()?;
+    let superproject = Repository::discover(workdir.parent()?).ok()?;
+    let super_workdir = superproject.workdir()?.canonicalize().ok()?;
+    let relative = workdir.strip_prefix(&super_workdir).ok()?;
+    let submodules = superproject.submodules().ok()?;
+    submodules
+        .iter()
+        .find(|submodule| submodule.path() == relative)
+        .map(|_| relative.to_path_buf())
+}
+
+/// Resolve a symlinked file argument to the file it points to.
+///
+/// The target of a symlink is analyzed rather than the link itself, since the
+/// blame of a link only covers the path it points to. A note is printed for
+/// each resolved link, and a warning when the link and its target belong to
+/// different repositories.
+fn resolve_symlink(path: &Path) -> Result<PathBuf> {
+    if !path.symlink_metadata()?.file_type().is_symlink() {
+        return Ok(path.to_path_buf());
+    }
+
+    let target = path.canonicalize()?;
+    eprintln!(
+        "note: {} is a symlink, analyzing {}",
+        path.display(),
+        target.display()
+    );
+
+    let link_dir = match path.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir,
+        _ => Path::new("."),
     };
+    let link_repo = Repository::discover(link_dir).map(|r| r.path().to_path_buf());
+    let target_repo = Repository::discover(&target).map(|r| r.path().to_path_buf());
+    if let (Ok(link_repo), Ok(target_repo)) = (link_repo, target_repo) {
+        if link_repo != target_repo {
+            eprintln!(
+                "warning: symlink {} points into a different repository ({})",
+                path.display(),
+                target_repo.display()
+            );
+        }
+    }
 
-    let mut tracker = TrackedFile::new(path.display().to_string());
+    Ok(target)
+}
+
+/// Source of the lines credited to each owner.
+#[derive(Clone, Copy, PartialEq)]
+enum Backend {
+    /// The lines of the file as it is, from git blame.
+    Blame,
+    /// The lines added over the history of the file, from git log.
+    Numstat,
+}
 
-    let blame = run_external_blame(&repo, &file)?;
+impl FromStr for Backend {
+    type Err = anyhow::Error;
 
-    for hunk in blame.iter() {
-        tracker.add_hunk(&hunk);
+    fn from_str(s: &str) -> Result<Backend> {
+        match s {
+            "blame" => Ok(Backend::Blame),
+            "numstat" => Ok(Backend::Numstat),
+            _ => Err(anyhow::Error::msg(
+                "Backend must be one of blame or numstat",
+            )),
+        }
     }
+}
+
+/// Command line: the options of `Args`, or a subcommand choosing what is
+/// printed followed by its own options.
+///
+/// A subcommand is only taken from the first argument, so a file named like
+/// one is analyzed when it comes after any other argument.
+#[derive(StructOpt)]
+#[structopt(
+    name = "git-whoknows",
+    global_settings = &[AppSettings::ColoredHelp],
+    settings = &[AppSettings::ArgsNegateSubcommands]
+)]
+struct Cli {
+    #[structopt(flatten)]
+    args: Args,
+
+    #[structopt(subcommand)]
+    command: Option<Subcommand>,
+}
+
+/// What is printed about the analyzed files. Without a subcommand the owners of
+/// each file are printed, as with `files`.
+#[derive(StructOpt)]
+enum Subcommand {
+    /// Print the owners of each file
+    Files(Args),
+    /// Print only the owners of all of the files together
+    Summary(Args),
+    /// Suggest reviewers for a change to the files: their owners together, other than
+    /// the user.email of the repository, up to --top of them
+    Reviewers(Args),
+    /// List the files a single owner has more than --bus-factor-threshold percent of
+    /// the lines of, with that owner
+    AtRisk(Args),
+}
 
-    Ok(tracker)
+/// Names of the subcommands, as given on the command line.
+const SUBCOMMANDS: &[&str] = &["files", "summary", "reviewers", "at-risk"];
+
+/// Output chosen with a subcommand.
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum View {
+    Files,
+    Summary,
+    Reviewers,
+    AtRisk,
+}
+
+impl Cli {
+    /// The options and the output they apply to, the options being those
+    /// given after the subcommand when there is one.
+    fn into_parts(self) -> (Args, View) {
+        match self.command {
+            None => (self.args, View::Files),
+            Some(Subcommand::Files(args)) => (args, View::Files),
+            Some(Subcommand::Summary(args)) => (args, View::Summary),
+            Some(Subcommand::Reviewers(args)) => (args, View::Reviewers),
+            Some(Subcommand::AtRisk(args)) => (args, View::AtRisk),
+        }
+    }
 }
 
 #[derive(StructOpt)]
 #[allow(non_snake_case)]
-#[structopt(global_settings = &[AppSettings::ColoredHelp])]
 struct Args {
     #[structopt(name = "filter-email", long)]
     email: Option<Vec<String>>,
@@ -265,6 +906,49 @@ struct Args {
     #[structopt(name = "filter-name", long)]
     name: Option<Vec<String>>,
 
+    /// Read --filter-email patterns from a file, one per line. Blank lines and lines
+    /// starting with # are ignored
+    #[structopt(name = "filter-email-file", long, parse(from_os_str))]
+    email_file: Option<PathBuf>,
+
+    /// Read --filter-name patterns from a file, one per line. Blank lines and lines
+    /// starting with # are ignored
+    #[structopt(name = "filter-name-file", long, parse(from_os_str))]
+    name_file: Option<PathBuf>,
+
+    /// Only show owners whose email is at one of these domains, `*.` matches subdomains
+    #[structopt(name = "filter-email-domain", long)]
+    email_domain: Option<Vec<String>>,
+
+    /// Hide owners whose email is at one of these domains, `*.` matches subdomains
+    #[structopt(name = "exclude-email-domain", long)]
+    exclude_email_domain: Option<Vec<String>>,
+
+    /// Credit authors with an email matching PATTERN to a single owner named Label,
+    /// given as 'PATTERN=>Label'. Can be specified multiple times
+    #[structopt(name = "merge-authors", long, number_of_values = 1)]
+    merge_authors: Vec<AuthorMerge>,
+
+    /// Shell command printing the canonical identity of the email given on its standard
+    /// input, either as 'Name <email>' or an identifier. Run once per email, the email
+    /// is kept when the command fails
+    #[structopt(long)]
+    resolve_cmd: Option<String>,
+
+    /// Keep the identities recorded on commits rather than mapping them through the
+    /// .mailmap file of the repository
+    #[structopt(long)]
+    no_mailmap: bool,
+
+    /// Print the owners of the files of each language, detected from their extension
+    #[structopt(long)]
+    by_language: bool,
+
+    /// Treat files with the extension as the language for --by-language, given as
+    /// 'ext=language'. Can be specified multiple times
+    #[structopt(name = "language", long, number_of_values = 1)]
+    languages: Vec<ExtensionLanguage>,
+
     #[structopt(name = "summary", long)]
     /// Print out summary of owners
     summary: bool,
@@ -273,82 +957,2269 @@ struct Args {
     #[structopt(long)]
     regex: bool,
 
-    #[structopt(name = "files", parse(from_os_str))]
-    file_list: Vec<PathBuf>,
-}
+    /// Run git blame on each file rather than blaming with libgit2. Git is run anyway
+    /// for files with uncommitted changes and for the options libgit2 cannot honour:
+    /// arguments after --, -w, --detect-moves, --detect-copies, --ignore-rev or a
+    /// .git-blame-ignore-revs file, --contents-stdin and --blob
+    #[structopt(long)]
+    external_git: bool,
 
-fn main() -> Result<()> {
-    let args = Args::from_args();
+    /// Do not count blank lines towards ownership. Not supported with --regex
+    #[structopt(long, conflicts_with = "regex")]
+    code_only: bool,
 
-    let tracked_files: Vec<TrackedFile> = args
-        .file_list
-        .par_iter()
-        .filter_map(|path| {
-            if args.regex {
-                analyze_file(path).ok()
-            } else {
-                analyze_file_nom(path).ok()
-            }
-        })
-        .collect();
+    /// Do not count comment lines of the given language towards ownership, such as
+    /// rust or python. Not supported with --regex
+    #[structopt(long, conflicts_with = "regex")]
+    skip_comments: Option<String>,
 
-    for file in &tracked_files {
-        let mut owners: Vec<&Owner> = file
-            .owners
-            .values()
-            .filter(|s| match &args.email {
-                Some(email) => email.iter().any(|e| s.email.contains(e)),
-                None => true,
-            })
-            .filter(|s| match &args.name {
-                Some(name) => name.iter().any(|n| s.email.contains(n)),
-                None => true,
-            })
-            .collect();
+    /// Do not count lines starting with the given comment prefixes in files with an
+    /// extension, given as 'ext=prefix,prefix'. Can be specified multiple times. Not
+    /// supported with --regex
+    #[structopt(long, number_of_values = 1, conflicts_with = "regex")]
+    comment_prefixes: Vec<ExtensionComments>,
 
-        if !owners.is_empty() {
-            println!("File: {}", file.path);
-            owners.sort_by_key(|a| a.lines());
-            owners.reverse();
-            owners.iter().for_each(|x| println!(" {}", x));
-        }
-    }
-
-    if args.summary {
-        let summary: DashMap<String, Owner> = DashMap::new();
-        &tracked_files.par_iter().for_each(|t| {
-            t.owners.par_iter().for_each(|(e, o)| {
-                summary
-                    .entry(e.to_string())
-                    .or_insert(Owner {
-                        name: o.name.to_string(),
-                        email: o.email.to_string(),
-                        commits: HashMap::new(),
-                    })
-                    .merge(o)
-            })
-        });
+    /// Only parse the line counts from blame and look up the authors of each commit
+    /// separately, which is faster for files with many commits
+    #[structopt(long)]
+    no_extra: bool,
 
-        let mut owners: Vec<Owner> = summary
-            .into_iter()
-            .map(|(_, value)| value)
-            .filter(|s| match &args.email {
-                Some(email) => email.iter().any(|e| s.email.contains(e)),
-                None => true,
-            })
-            .filter(|s| match &args.name {
-                Some(name) => name.iter().any(|n| s.email.contains(n)),
-                None => true,
-            })
-            .collect();
+    /// Where the lines credited to owners come from. blame credits the lines the file
+    /// has now, numstat every line added over its history from git log
+    #[structopt(long, default_value = "blame", possible_values = &["blame", "numstat"])]
+    backend: Backend,
 
-        println!("Summary");
-        if !owners.is_empty() {
-            owners.sort_by_key(|a| a.lines());
-            owners.reverse();
-            owners.iter().for_each(|x| println!(" {}", x));
-        }
-    }
+    /// With --backend numstat, credit each commit with the lines it added less those
+    /// it deleted
+    #[structopt(long)]
+    net: bool,
 
-    Ok(())
+    /// Credit each line to the author who first wrote it, following the lines each
+    /// commit replaced back through the history, rather than who last changed it.
+    /// Much slower, as each line is blamed again for every commit that changed it
+    #[structopt(long, conflicts_with_all = &["regex", "no-extra"])]
+    original_author: bool,
+
+    /// Ignore whitespace when blaming, so lines only reindented or reformatted stay
+    /// with the author of their content
+    #[structopt(short = "w", long)]
+    ignore_whitespace: bool,
+
+    /// Credit lines moved within a file to the author who wrote them rather than who
+    /// moved them, as git blame -M
+    #[structopt(long)]
+    detect_moves: bool,
+
+    /// Credit lines moved or copied from other files changed in the same commit to the
+    /// author who wrote them, as git blame -C
+    #[structopt(long)]
+    detect_copies: bool,
+
+    /// Blame the files as committed at HEAD, ignoring changes in the working tree
+    #[structopt(long)]
+    head: bool,
+
+    /// Blame the files as they were at a revision, such as a tag. Files deleted since
+    /// are looked up in the tree of the revision
+    #[structopt(long, conflicts_with = "head")]
+    rev: Option<String>,
+
+    /// Blame the files as they were at the common ancestor of two revisions, such as a
+    /// release branch and a feature branch
+    #[structopt(
+        long,
+        number_of_values = 2,
+        value_names = &["a", "b"],
+        conflicts_with_all = &["head", "rev"]
+    )]
+    merge_base: Vec<String>,
+
+    /// Warn about owners whose email does not look like an address
+    #[structopt(long)]
+    warn_bad_emails: bool,
+
+    /// Do not warn about files with uncommitted changes or note duplicate files
+    #[structopt(short, long)]
+    quiet: bool,
+
+    /// Blame the content read from stdin in place of the working tree content of the
+    /// file given by --path, such as an unsaved editor buffer
+    #[structopt(long, requires = "path", conflicts_with = "files")]
+    contents_stdin: bool,
+
+    /// Blame the content of a blob, given by its full or abbreviated object id, against
+    /// the history of the file given by --path, or of the path it has at HEAD
+    #[structopt(long, conflicts_with_all = &["contents-stdin", "files"])]
+    blob: Option<String>,
+
+    /// File whose history the content read with --contents-stdin or --blob is blamed
+    /// against
+    #[structopt(long, parse(from_os_str))]
+    path: Option<PathBuf>,
+
+    /// Do not count the lines of a commit, given by its full or abbreviated hash. May be
+    /// repeated
+    #[structopt(long, number_of_values = 1)]
+    ignore_rev: Vec<String>,
+
+    /// Do not ignore the commits listed in the .git-blame-ignore-revs file at the root of
+    /// the repository
+    #[structopt(long)]
+    no_ignore_revs_file: bool,
+
+    /// Only blame the lines from START to END of each file, counting from 1
+    #[structopt(short = "L", value_name = "START,END")]
+    line_range: Option<LineRange>,
+
+    /// Only count the lines of commits authored on or after this date, such as
+    /// 2023-01-31, or time, such as 2023-01-31T12:00:00+01:00
+    #[structopt(long)]
+    since: Option<String>,
+
+    /// Only count the lines of commits authored on or before this date, such as
+    /// 2023-01-31, or before this time, such as 2023-01-31T12:00:00+01:00
+    #[structopt(long)]
+    until: Option<String>,
+
+    /// Browse the analyzed files and their owners in a terminal UI
+    #[cfg(feature = "interactive")]
+    #[structopt(long)]
+    tui: bool,
+
+    /// Only analyze the files where the owner with this email has lines
+    #[structopt(long)]
+    owned_by: Option<String>,
+
+    /// Only analyze the files where the owner with this email has no lines
+    #[structopt(long)]
+    not_owned_by: Option<String>,
+
+    /// Percentage of a file's lines an owner needs for --owned-by and --not-owned-by to
+    /// count them as an owner
+    #[structopt(long, default_value = "0")]
+    min_percent: f64,
+
+    /// Exit with an error when a file has a bus factor below this, the smallest number
+    /// of owners who together own more than half of its lines
+    #[structopt(long)]
+    fail_if_bus_factor_below: Option<usize>,
+
+    /// Print the bus factor of each file, the smallest number of owners who together own
+    /// more than half of its lines
+    #[structopt(long)]
+    bus_factor: bool,
+
+    /// Percentage of a file's lines the owners counted by its bus factor must own more
+    /// than, for --bus-factor, --fail-if-bus-factor-below, --sort-files bus-factor and
+    /// --format sarif
+    #[structopt(long, value_name = "PCT", default_value = "50")]
+    bus_factor_threshold: f64,
+
+    /// Exit with an error when a file has a single owner
+    #[structopt(long)]
+    fail_if_single_owner: bool,
+
+    /// Print a single line for each file naming the owner of more than half of its
+    /// lines and their share
+    #[structopt(long)]
+    primary_owner: bool,
+
+    /// Print a CODEOWNERS file built from the analyzed files, one entry per directory
+    #[structopt(long)]
+    codeowners: bool,
+
+    /// Maximum number of owners listed for each CODEOWNERS entry
+    #[structopt(long, default_value = "3")]
+    codeowners_top: usize,
+
+    /// Minimum percentage of a directory's lines an owner needs to be listed in CODEOWNERS
+    #[structopt(long, default_value = "0")]
+    codeowners_min_percent: f64,
+
+    /// Give each file its own CODEOWNERS entry rather than rolling files up by directory
+    #[structopt(long, requires = "codeowners")]
+    codeowners_per_file: bool,
+
+    /// File mapping emails to the handles listed in CODEOWNERS, one `email @handle` pair
+    /// per line. Owners without a handle are listed by email
+    #[structopt(long, parse(from_os_str), requires = "codeowners")]
+    email_map: Option<PathBuf>,
+
+    /// Output format, one of text, json, bincode, sarif or csv. The json and bincode
+    /// outputs have the same fields, the bincode output starting with the schema version
+    /// as a little-endian u32. The sarif output has a result for each
+    /// file where a single owner wrote more than half of the lines
+    #[structopt(
+        long,
+        default_value = "text",
+        possible_values = &["text", "json", "bincode", "sarif", "csv"]
+    )]
+    format: Format,
+
+    /// What each row of the csv output describes, the owners of each file or the
+    /// commits of each file with their author, date and summary
+    #[structopt(long, possible_values = &["owner", "commit"])]
+    granularity: Option<Granularity>,
+
+    /// Print how concentrated the ownership of each file and the summary is, from 0 for
+    /// lines split evenly between owners to 1 for a single owner
+    #[structopt(long)]
+    concentration: bool,
+
+    /// Print the months of the oldest and newest commits owning lines of each file, and
+    /// of all files in the summary
+    #[structopt(long)]
+    date_range: bool,
+
+    /// Print the number of commits each owner made per period
+    #[structopt(long)]
+    activity: bool,
+
+    /// Period used to group commits for --activity, either month or year
+    #[structopt(long, default_value = "month", possible_values = &["month", "year"])]
+    activity_period: Period,
+
+    /// Print the average number of lines per commit of each owner and when they last
+    /// touched the lines they own
+    #[structopt(short, long)]
+    verbose: bool,
+
+    /// Style of the dates printed by --verbose, one of relative, iso, short or unix
+    #[structopt(long, default_value = "relative", possible_values = &["relative", "iso", "short", "unix"])]
+    date: DateStyle,
+
+    /// Time zone of the dates printed by --verbose and --date-range, LOCAL, UTC or an
+    /// offset such as +0530, instead of the zone each commit was made in
+    #[structopt(long)]
+    tz: Option<Zone>,
+
+    /// Show owners by only their name, email, or name falling back to the local part of
+    /// the email, instead of both name and email
+    #[structopt(long, possible_values = &["name", "email", "name-or-email"])]
+    display: Option<DisplayName>,
+
+    /// Shorten names and emails longer than this many characters in the text output,
+    /// filters still match the full email
+    #[structopt(long)]
+    max_width: Option<usize>,
+
+    /// Order owners are listed in, either lines or ratio of lines per commit
+    #[structopt(long, default_value = "lines", possible_values = &["lines", "ratio"])]
+    sort: Sort,
+
+    /// Weight each line by how recently it was written, halving its weight for every
+    /// DAYS days since, and order owners by their weighted lines
+    #[structopt(long, value_name = "DAYS", conflicts_with = "sort")]
+    half_life: Option<f64>,
+
+    /// Only list the first N owners of each file, after filtering and ordering them
+    #[structopt(long)]
+    top: Option<usize>,
+
+    /// Order files are listed in, by path, most owners, most lines or lowest bus factor
+    /// first, instead of the order they were given in
+    #[structopt(long, possible_values = &["path", "owners", "lines", "bus-factor"])]
+    sort_files: Option<FileSort>,
+
+    /// Only list the first N files, after ordering them with --sort-files. The summary
+    /// still covers all of the files
+    #[structopt(long)]
+    top_files: Option<usize>,
+
+    /// Separator between groups of thousands in counts, such as ','
+    #[structopt(long)]
+    thousands_sep: Option<String>,
+
+    /// Number of decimal places shown for percentages
+    #[structopt(long, default_value = "1")]
+    percent_precision: usize,
+
+    /// Who is credited with the lines of each commit, its author, both its author and
+    /// committer, or its committer. With both, committers are listed apart from authors,
+    /// marked as such, and the lines of the file can add up to more than its length
+    #[structopt(long, default_value = "author", possible_values = &["author", "both", "committer"])]
+    attribute: Attribute,
+
+    /// Credit the lines of each commit to its committer rather than its author, the same
+    /// as --attribute committer
+    #[structopt(long)]
+    use_committer: bool,
+
+    /// Only analyze the N largest of the files given, by size in bytes
+    #[structopt(long)]
+    largest: Option<usize>,
+
+    /// Print each owner with the names and emails recorded on the commits credited to
+    /// them, before any merging or resolving, to stderr
+    #[structopt(long)]
+    explain_merges: bool,
+
+    /// Only blame the last N commits before the blamed revision, for speed on deep
+    /// histories. Lines older than that are not attributed to their authors but
+    /// counted together under a Boundary owner
+    #[structopt(long)]
+    max_depth: Option<usize>,
+
+    /// Check that files can be analyzed in this environment by analyzing a small
+    /// repository created in a temporary directory
+    #[structopt(long, hidden = true)]
+    self_test: bool,
+
+    /// Name of the owner of the lines that are not committed yet
+    #[structopt(long, default_value = "Not Committed Yet")]
+    uncommitted_label: String,
+
+    /// Do not count the lines that are not committed yet
+    #[structopt(long, conflicts_with = "uncommitted-label")]
+    skip_uncommitted: bool,
+
+    /// Name of the owner of the lines older than --max-depth
+    #[structopt(long, default_value = "Boundary")]
+    boundary_label: String,
+
+    /// How the paths of files are shown, as given, absolute, relative to the root of
+    /// their repository or relative to the current directory
+    #[structopt(
+        long,
+        default_value = "given",
+        possible_values = &["given", "absolute", "repo-relative", "cwd-relative"]
+    )]
+    path_style: PathStyle,
+
+    /// Show paths with / separators, also on Windows
+    #[structopt(long)]
+    posix_paths: bool,
+
+    /// Replace the names and emails of owners with pseudonyms such as Contributor A,
+    /// the same for each person throughout the output. Commit hashes are replaced too,
+    /// and dates are rounded to the day in UTC
+    #[structopt(long)]
+    anonymize: bool,
+
+    /// Write the name and email each pseudonym stands for to this file, tab separated
+    #[structopt(long, parse(from_os_str), requires = "anonymize")]
+    anonymize_key: Option<PathBuf>,
+
+    /// List empty files as such, rather than leaving them out
+    #[structopt(long)]
+    show_empty: bool,
+
+    /// Show the lines each owner has from each of their commits, also included in the
+    /// bincode output
+    #[structopt(long)]
+    by_commit: bool,
+
+    /// Length to abbreviate commit hashes to, or auto for the shortest length leaving
+    /// the hashes shown unambiguous. Defaults to core.abbrev, or 7
+    #[structopt(long)]
+    abbrev: Option<Abbrev>,
+
+    /// Print the time taken to analyze the slowest files to stderr
+    #[structopt(long)]
+    timings: bool,
+
+    /// Do not show how many of the files have been analyzed, which is otherwise shown
+    /// on stderr when it is a terminal
+    #[structopt(long)]
+    no_progress: bool,
+
+    /// Files to analyze, - reads newline separated paths from stdin as does giving none
+    /// when stdin is piped. Defaults to the files tracked under the current directory
+    /// when none are given and stdin is a terminal
+    #[structopt(name = "files", parse(from_os_str))]
+    file_list: Vec<PathBuf>,
+
+    /// Separate the paths read from stdin by NUL bytes rather than newlines, as printed
+    /// by git ls-files -z
+    #[structopt(short = "z", long)]
+    null: bool,
+
+    /// Extra arguments passed through to git blame, given after --
+    #[structopt(name = "blame-args", last = true)]
+    blame_args: Vec<String>,
+}
+
+impl Args {
+    /// How `owner` is shown in the text output, by default their name and email.
+    fn owner_label(&self, owner: &Owner) -> String {
+        match self.display {
+            Some(display) => self.shorten(&display.of(owner)),
+            None => format!(
+                "{} <{}>",
+                self.shorten(&owner.name),
+                self.shorten(&owner.email)
+            ),
+        }
+    }
+
+    /// Name `owner` is given in the machine formats with a name field, the
+    /// identifier chosen with `--display` or else their name.
+    fn display_name(&self, owner: &Owner) -> String {
+        match self.display {
+            Some(display) => display.of(owner),
+            None => owner.name.clone(),
+        }
+    }
+
+    /// Truncate a name or email to `--max-width`, if given.
+    fn shorten(&self, s: &str) -> String {
+        match self.max_width {
+            Some(width) => truncate(s, width),
+            None => s.to_string(),
+        }
+    }
+
+    /// `date` in the zone given by `--tz`, if any.
+    fn in_zone(&self, date: DateTime<FixedOffset>) -> DateTime<FixedOffset> {
+        match self.tz {
+            Some(zone) => zone.convert(date),
+            None => date,
+        }
+    }
+
+    /// Oldest and newest dates of `owners` in the zone given by `--tz`.
+    fn date_range<'a>(
+        &self,
+        owners: impl Iterator<Item = &'a Owner>,
+    ) -> Option<(DateTime<FixedOffset>, DateTime<FixedOffset>)> {
+        date_range(owners).map(|(oldest, newest)| (self.in_zone(oldest), self.in_zone(newest)))
+    }
+
+    fn number_format(&self) -> NumberFormat {
+        NumberFormat::new(self.thousands_sep.clone(), self.percent_precision)
+    }
+
+    /// Add the patterns of the filter files to the inline filters.
+    fn load_filter_files(&mut self) -> Result<()> {
+        if let Some(path) = &self.email_file {
+            add_patterns(&mut self.email, read_patterns(path)?);
+        }
+        if let Some(path) = &self.name_file {
+            add_patterns(&mut self.name, read_patterns(path)?);
+        }
+        Ok(())
+    }
+
+    /// Replace the owner filters, which match real names and emails, by the
+    /// emails the owners they match in `files` are given by `pseudonyms`, so
+    /// they still apply once the files are anonymized.
+    fn anonymize_filters(
+        &mut self,
+        files: &[TrackedFile],
+        pseudonyms: &HashMap<String, anonymize::Pseudonym>,
+    ) {
+        if self.email.is_none()
+            && self.name.is_none()
+            && self.email_domain.is_none()
+            && self.exclude_email_domain.is_none()
+        {
+            return;
+        }
+        let emails: BTreeSet<String> = files
+            .iter()
+            .flat_map(|file| file.owners.values())
+            .filter(|owner| self.matches(owner))
+            .map(|owner| anonymize::email(pseudonyms, &owner.email))
+            .collect();
+        self.email = Some(emails.into_iter().collect());
+        self.name = None;
+        self.email_domain = None;
+        self.exclude_email_domain = None;
+    }
+
+    /// Check whether an owner passes the owner filters given on the command line.
+    fn matches(&self, owner: &Owner) -> bool {
+        let email = match &self.email {
+            Some(email) => email.iter().any(|e| owner.email.contains(e)),
+            None => true,
+        };
+        let name = match &self.name {
+            Some(name) => name.iter().any(|n| owner.name.contains(n)),
+            None => true,
+        };
+        let domain = match &self.email_domain {
+            Some(domains) => domains.iter().any(|d| email_in_domain(&owner.email, d)),
+            None => true,
+        };
+        let excluded = match &self.exclude_email_domain {
+            Some(domains) => domains.iter().any(|d| email_in_domain(&owner.email, d)),
+            None => false,
+        };
+        email && name && domain && !excluded
+    }
+}
+
+/// Path of the file `name` at the root of the working tree containing `dir`,
+/// if there is one.
+fn repo_root_file(dir: &Path, name: &str) -> Option<PathBuf> {
+    let repo = Repository::discover(dir).ok()?;
+    let path = repo.workdir()?.join(name);
+    if path.is_file() {
+        Some(path)
+    } else {
+        None
+    }
+}
+
+/// Read the `.mailmap` file at the root of the working tree containing `dir`,
+/// if there is one.
+fn read_mailmap(dir: &Path) -> Result<Option<Mailmap>> {
+    match repo_root_file(dir, ".mailmap") {
+        Some(path) => Ok(Some(Mailmap::parse(&std::fs::read_to_string(path)?))),
+        None => Ok(None),
+    }
+}
+
+/// Read the hashes listed in the `.git-blame-ignore-revs` file at the root of
+/// the working tree containing `dir`, one per line with `#` starting a
+/// comment, as `git blame --ignore-revs-file` does.
+fn read_ignore_revs_file(dir: &Path) -> Result<Vec<String>> {
+    let path = match repo_root_file(dir, ".git-blame-ignore-revs") {
+        Some(path) => path,
+        None => return Ok(Vec::new()),
+    };
+    Ok(read_patterns(&path)?
+        .iter()
+        .filter_map(|line| line.split('#').next())
+        .map(str::trim)
+        .filter(|rev| !rev.is_empty())
+        .map(str::to_string)
+        .collect())
+}
+
+/// Read the newline separated patterns in the file at `path`, skipping blank
+/// lines and comments starting with `#`.
+fn read_patterns(path: &Path) -> Result<Vec<String>> {
+    let contents = std::fs::read_to_string(path).map_err(|e| {
+        anyhow::Error::msg(format!(
+            "Error reading patterns from {}: {}",
+            path.display(),
+            e
+        ))
+    })?;
+    Ok(contents
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .map(str::to_string)
+        .collect())
+}
+
+/// Add `patterns` to a filter, leaving it unset when there are none so an
+/// empty file does not hide every owner.
+fn add_patterns(filter: &mut Option<Vec<String>>, patterns: Vec<String>) {
+    if !patterns.is_empty() {
+        filter.get_or_insert_with(Vec::new).extend(patterns);
+    }
+}
+
+/// Check whether the host part of `email` matches `domain`, ignoring case.
+///
+/// A domain of the form `*.example.com` also matches any subdomain of
+/// `example.com`.
+fn email_in_domain(email: &str, domain: &str) -> bool {
+    let host = match email.rfind('@') {
+        Some(at) => email[at + 1..].to_lowercase(),
+        None => return false,
+    };
+    let domain = domain.trim_start_matches('@').to_lowercase();
+    match domain.strip_prefix("*.") {
+        Some(parent) => host == parent || host.ends_with(&format!(".{}", parent)),
+        None => host == domain,
+    }
+}
+
+/// Print a line for `owner`, who has `share` percent of the lines listed with
+/// them, with commit hashes abbreviated to `abbrev` characters.
+fn print_owner(args: &Args, owner: &Owner, share: f64, abbrev: usize) {
+    let numbers = args.number_format();
+    let verbose = if args.verbose {
+        let last = owner.last_touched().map_or(String::new(), |date| {
+            format!(
+                " Last: {}",
+                args.date.format(args.in_zone(date), Local::now().into())
+            )
+        });
+        format!(" Ratio: {:.1}{}", owner.ratio(), last)
+    } else {
+        String::new()
+    };
+    let files = if owner.files.is_empty() {
+        String::new()
+    } else {
+        format!(" Files: {}", numbers.count(owner.files.len()))
+    };
+    let weight = match args.half_life {
+        Some(half_life) => format!(
+            " Weight: {:.1}",
+            owner.weighted_lines(half_life, Local::now().into())
+        ),
+        None => String::new(),
+    };
+    println!(
+        " {}: Lines: {} ({}) Count: {}{}{}{}",
+        args.owner_label(owner),
+        numbers.count(owner.lines()),
+        numbers.percent(share),
+        numbers.count(owner.commits.len()),
+        files,
+        weight,
+        verbose
+    );
+    if args.by_commit {
+        let mut commits: Vec<(&String, &usize)> = owner.commits.iter().collect();
+        commits.sort_by(|a, b| b.1.cmp(a.1).then(a.0.cmp(b.0)));
+        commits.iter().for_each(|(hash, lines)| {
+            let length = abbrev.min(hash.len());
+            println!("   {}: {}", &hash[..length], numbers.count(**lines))
+        });
+    }
+    if args.activity {
+        let activity: Vec<String> = owner
+            .activity(args.activity_period)
+            .iter()
+            .map(|(period, commits)| format!("{}: {}", period, numbers.count(*commits)))
+            .collect();
+        println!("   Activity: {}", activity.join(", "));
+    }
+}
+
+/// Number of files listed by `--timings`.
+const SLOWEST_FILES: usize = 10;
+
+fn print_timings(timings: DashMap<PathBuf, Duration>, total: Duration) {
+    let mut timings: Vec<(PathBuf, Duration)> = timings.into_iter().collect();
+    timings.sort_by_key(|(_, duration)| *duration);
+    timings.reverse();
+
+    eprintln!("Analyzed {} files in {:.3?}", timings.len(), total);
+    timings
+        .iter()
+        .take(SLOWEST_FILES)
+        .for_each(|(path, duration)| eprintln!(" {:>10.3?} {}", duration, path.display()));
+}
+
+/// Merge the owners of all `files`, recording the files each owns lines in.
+fn merge_owners<'a>(files: impl IntoParallelIterator<Item = &'a TrackedFile>) -> Vec<Owner> {
+    let merged: DashMap<String, Owner> = DashMap::new();
+    files.into_par_iter().for_each(|t| {
+        t.owners.par_iter().for_each(|(e, o)| {
+            let mut owner = merged
+                .entry(e.to_string())
+                .or_insert_with(|| Owner::new(o.name.to_string(), o.email.to_string()));
+            owner.merge(o);
+            owner.files.insert(t.path.to_string());
+        })
+    });
+    merged.into_iter().map(|(_, value)| value).collect()
+}
+
+/// Merge the owners of all `files` and print those matching the filters under
+/// `heading`.
+fn print_merged_owners<'a>(
+    args: &Args,
+    heading: &str,
+    files: impl IntoParallelIterator<Item = &'a TrackedFile>,
+    abbrev: usize,
+) {
+    let mut owners = merge_owners(files);
+    let total = owners
+        .iter()
+        .fold(0, |total: usize, owner| total.saturating_add(owner.lines()));
+
+    let mut heading = heading.to_string();
+    if args.concentration {
+        heading.push_str(&concentration_suffix(concentration(owners.iter())));
+    }
+    if args.date_range {
+        heading.push_str(&date_range_suffix(args.date_range(owners.iter())));
+    }
+    println!("{}", heading);
+    owners.retain(|s| args.matches(s));
+    if !owners.is_empty() {
+        sort_owners(&mut owners, args.sort);
+        owners
+            .iter()
+            .for_each(|x| print_owner(args, x, percentage(x.lines(), total), abbrev));
+    }
+}
+
+/// Owners of `file` passing the owner filters, in the order chosen by `args`
+/// and limited by --top.
+fn listed_owners<'a>(args: &Args, file: &'a TrackedFile) -> Vec<&'a Owner> {
+    let mut owners: Vec<&Owner> = file.owners.values().filter(|o| args.matches(o)).collect();
+    sort_owners(&mut owners, args.sort);
+    if let Some(top) = args.top {
+        owners.truncate(top);
+    }
+    owners
+}
+
+/// Print the analyzed files as chosen by `view` and in the format chosen by
+/// `args`, with commit hashes abbreviated to `abbrev` characters.
+fn print_results(
+    args: &Args,
+    view: View,
+    tracked_files: &[TrackedFile],
+    rev: Option<&str>,
+    abbrev: usize,
+) -> Result<()> {
+    // --top-files limits the files listed in every format, but not those
+    // summarized.
+    let shown = &tracked_files[..args
+        .top_files
+        .map_or(tracked_files.len(), |count| count.min(tracked_files.len()))];
+
+    #[cfg(feature = "interactive")]
+    {
+        if args.tui {
+            return interactive::run(shown, &args.number_format());
+        }
+    }
+
+    if args.codeowners {
+        let handles = match &args.email_map {
+            Some(path) => codeowners::parse_email_map(&read_patterns(path)?)?,
+            None => HashMap::new(),
+        };
+        print!(
+            "{}",
+            codeowners::generate(
+                shown,
+                args.codeowners_top,
+                args.codeowners_min_percent,
+                |o| args.matches(o),
+                args.codeowners_per_file,
+                &handles
+            )
+        );
+        return Ok(());
+    }
+
+    if args.format == Format::Json || args.format == Format::Bincode {
+        let report = report::Report::new(shown, rev, args.by_commit, |o| args.matches(o), args.top);
+        if args.format == Format::Json {
+            println!("{}", report.to_json()?);
+        } else {
+            io::stdout().write_all(&report.to_bincode()?)?;
+        }
+        return Ok(());
+    }
+
+    if args.format == Format::Csv {
+        let granularity = args.granularity.unwrap_or(Granularity::Owner);
+        print!(
+            "{}",
+            csv::generate(
+                shown,
+                granularity,
+                |o| args.matches(o),
+                args.top,
+                |o| args.display_name(o)
+            )
+        );
+        return Ok(());
+    }
+
+    if args.format == Format::Sarif {
+        println!(
+            "{}",
+            sarif::Log::new(
+                shown,
+                args.bus_factor_threshold,
+                |o| args.matches(o),
+                &args.number_format()
+            )
+            .to_json()?
+        );
+        return Ok(());
+    }
+
+    let showing_note = || {
+        if shown.len() < tracked_files.len() {
+            println!("(showing {} of {} files)", shown.len(), tracked_files.len());
+        }
+    };
+
+    if args.primary_owner {
+        let numbers = args.number_format();
+        shown.iter().for_each(|file| match file.primary_owner() {
+            Some(owner) => println!(
+                "{}: {} ({})",
+                file.path,
+                args.shorten(&args.display.unwrap_or(DisplayName::Name).of(owner)),
+                numbers.percent(file.owner_percentage(owner))
+            ),
+            None => println!("{}: (no majority)", file.path),
+        });
+        showing_note();
+        return Ok(());
+    }
+
+    if view == View::Reviewers {
+        let owners = merge_owners(tracked_files);
+        let total = owners
+            .iter()
+            .fold(0, |total: usize, owner| total.saturating_add(owner.lines()));
+        println!("Reviewers");
+        reviewers(args, &owners, user_email().as_deref())
+            .iter()
+            .for_each(|x| print_owner(args, x, percentage(x.lines(), total), abbrev));
+        return Ok(());
+    }
+
+    if view == View::AtRisk {
+        let numbers = args.number_format();
+        at_risk(args, shown).iter().for_each(|(file, owner)| {
+            println!(
+                "{}: {} ({})",
+                file.path,
+                args.owner_label(owner),
+                numbers.percent(file.owner_percentage(owner))
+            )
+        });
+        showing_note();
+        return Ok(());
+    }
+
+    for file in shown.iter().filter(|_| view == View::Files) {
+        let owners = listed_owners(args, file);
+
+        if file.empty && args.show_empty {
+            println!("File: {} (empty)", file.path);
+        } else if !owners.is_empty() {
+            let mut heading = format!("File: {}", file.path);
+            if args.concentration {
+                heading.push_str(&concentration_suffix(file.concentration()));
+            }
+            if args.bus_factor {
+                heading.push_str(&format!(
+                    " (bus factor: {})",
+                    file.bus_factor_above(args.bus_factor_threshold)
+                ));
+            }
+            if args.date_range {
+                heading.push_str(&date_range_suffix(args.date_range(file.owners.values())));
+            }
+            println!("{}", heading);
+            owners
+                .iter()
+                .for_each(|x| print_owner(args, x, file.owner_percentage(x), abbrev));
+        }
+    }
+    if view == View::Files {
+        showing_note();
+    }
+
+    if args.by_language {
+        let languages = Languages::new(args.languages.clone());
+        let mut by_language: BTreeMap<&str, Vec<&TrackedFile>> = BTreeMap::new();
+        tracked_files.iter().for_each(|t| {
+            by_language
+                .entry(languages.detect(&t.repo_path))
+                .or_default()
+                .push(t)
+        });
+        by_language.into_iter().for_each(|(language, files)| {
+            print_merged_owners(args, &format!("Language: {}", language), files, abbrev)
+        });
+    }
+
+    if args.summary || view == View::Summary {
+        print_merged_owners(args, "Summary", tracked_files, abbrev);
+    }
+
+    Ok(())
+}
+
+/// Number of reviewers suggested when --top is not given.
+const REVIEWERS: usize = 3;
+
+/// Owners among `owners` to suggest as reviewers, those matching the filters
+/// other than `user_email` and the owners standing in for lines, up to --top
+/// of them.
+fn reviewers<'a>(args: &Args, owners: &'a [Owner], user_email: Option<&str>) -> Vec<&'a Owner> {
+    let mut reviewers: Vec<&Owner> = owners
+        .iter()
+        .filter(|o| args.matches(o) && !o.is_synthetic())
+        .filter(|o| user_email.is_none_or(|email| !o.email.eq_ignore_ascii_case(email)))
+        .collect();
+    sort_owners(&mut reviewers, args.sort);
+    reviewers.truncate(args.top.unwrap_or(REVIEWERS));
+    reviewers
+}
+
+/// Email git commits are made with in the current repository, if set.
+fn user_email() -> Option<String> {
+    Repository::discover(".")
+        .and_then(|repo| repo.config())
+        .or_else(|_| git2::Config::open_default())
+        .and_then(|config| config.get_string("user.email"))
+        .ok()
+}
+
+/// Files of `files` a single owner has more than --bus-factor-threshold
+/// percent of the lines of, with that owner.
+fn at_risk<'a>(args: &Args, files: &'a [TrackedFile]) -> Vec<(&'a TrackedFile, &'a Owner)> {
+    files
+        .iter()
+        .filter(|file| file.bus_factor_above(args.bus_factor_threshold) == 1)
+        .filter_map(|file| {
+            let owner = file
+                .owners
+                .values()
+                .max_by(|a, b| a.lines().cmp(&b.lines()).then(b.email.cmp(&a.email)))?;
+            Some((file, owner))
+        })
+        .collect()
+}
+
+/// Warn about each owner whose email does not look like an address.
+fn warn_bad_emails(args: &Args, files: &[TrackedFile]) {
+    bad_email_warnings(args, files)
+        .iter()
+        .for_each(|warning| eprintln!("warning: {}", warning));
+}
+
+/// Owners whose email does not look like an address, once each and ordered
+/// by email, other than the labels of --merge-authors and lines not
+/// committed yet.
+fn bad_email_warnings(args: &Args, files: &[TrackedFile]) -> Vec<String> {
+    let bad: BTreeMap<&str, &str> = files
+        .iter()
+        .flat_map(|file| file.owners.values())
+        .filter(|owner| !owner.is_synthetic())
+        .filter(|owner| !args.merge_authors.iter().any(|m| m.label() == owner.email))
+        .filter(|owner| !identity::looks_like_email(&owner.email))
+        .map(|owner| (owner.email.as_str(), owner.name.as_str()))
+        .collect();
+    bad.iter()
+        .map(|(email, name)| format!("{} <{}> has a malformed email", name, email))
+        .collect()
+}
+
+/// Describe the names and emails recorded on commits that were credited to
+/// each owner of `files`, ordered by the owner's email.
+fn explain_merges(args: &Args, files: &[TrackedFile]) -> String {
+    let mut owners = merge_owners(files);
+    owners.sort_by(|a, b| a.email.cmp(&b.email));
+    let mut explanation = String::from("Identities\n");
+    for owner in owners {
+        explanation.push_str(&format!(" {}\n", args.owner_label(&owner)));
+        owner
+            .aliases
+            .iter()
+            .for_each(|(name, email)| explanation.push_str(&format!("   {} <{}>\n", name, email)));
+    }
+    explanation
+}
+
+/// Reasons files break the conditions given with the --fail-if options.
+fn policy_failures(args: &Args, files: &[TrackedFile]) -> Vec<String> {
+    let mut failures = Vec::new();
+    for file in files.iter().filter(|file| file.lines() > 0) {
+        if let Some(minimum) = args.fail_if_bus_factor_below {
+            let bus_factor = file.bus_factor_above(args.bus_factor_threshold);
+            if bus_factor < minimum {
+                failures.push(format!(
+                    "{} has a bus factor of {}, below {}",
+                    file.path, bus_factor, minimum
+                ));
+            }
+        }
+        if args.fail_if_single_owner && file.owners.len() == 1 {
+            failures.push(format!("{} has a single owner", file.path));
+        }
+    }
+    failures
+}
+
+fn main() -> Result<()> {
+    let (mut args, view) = Cli::from_args().into_parts();
+    if args.self_test {
+        return self_test::run();
+    }
+    if let Some(name) = args
+        .file_list
+        .iter()
+        .filter_map(|path| path.to_str())
+        .find(|path| SUBCOMMANDS.contains(path) && !Path::new(path).exists())
+    {
+        return Err(anyhow::Error::msg(format!(
+            "{} is not a file, subcommands are given before any option, as in git-whoknows {} [options] [files]",
+            name, name
+        )));
+    }
+    args.load_filter_files()?;
+    if let Some(half_life) = args.half_life {
+        if !(half_life > 0.0 && half_life.is_finite()) {
+            return Err(anyhow::Error::msg(
+                "--half-life must be a positive number of days",
+            ));
+        }
+        args.sort = Sort::Weighted(half_life);
+    }
+    if !(0.0..100.0).contains(&args.bus_factor_threshold) {
+        return Err(anyhow::Error::msg(
+            "--bus-factor-threshold must be a percentage from 0 up to, but not including, 100",
+        ));
+    }
+    if args.path.is_some() && !args.contents_stdin && args.blob.is_none() {
+        return Err(anyhow::Error::msg(
+            "--path only applies to --contents-stdin and --blob",
+        ));
+    }
+    let blob = match &args.blob {
+        Some(oid) => Some(resolve_blob(oid, args.path.as_deref())?),
+        None => None,
+    };
+    let contents_given = args.contents_stdin || blob.is_some();
+    if let Some((_, path)) = &blob {
+        args.file_list = vec![path.to_path_buf()];
+    } else if let Some(path) = &args.path {
+        args.file_list = vec![path.to_path_buf()];
+    } else if args.file_list.is_empty() && io::stdin().is_terminal() {
+        args.file_list = tracked_files_under(Path::new("."))?;
+    } else {
+        args.file_list = listed_files(&args.file_list, io::stdin().lock(), args.null)?;
+    }
+    let filter = LineFilter::new(args.code_only, args.skip_comments.as_deref())?
+        .with_extension_prefixes(args.comment_prefixes.clone());
+    blame::check_blame_args(&args.blame_args)?;
+    let window = DateWindow::new(args.since.as_deref(), args.until.as_deref())?;
+    let mut blame_args = args.blame_args.clone();
+    // Revisions are resolved in the repository of the first file.
+    let repo_dir = args
+        .file_list
+        .first()
+        .map_or_else(|| PathBuf::from("."), |path| split_existing(path).0);
+    let rev = if args.head {
+        Some("HEAD".to_string())
+    } else if let [a, b] = args.merge_base.as_slice() {
+        Some(merge_base(&repo_dir, a, b)?)
+    } else {
+        args.rev.clone()
+    };
+    let mut ignore_rev = args.ignore_rev.clone();
+    if !args.no_ignore_revs_file {
+        ignore_rev.extend(read_ignore_revs_file(&repo_dir)?);
+    }
+    let ignored_revs = ignored_revs(&ignore_rev)?;
+    // Settings libgit2 can be asked for are taken out of the passed arguments.
+    let first_parent = blame_args.iter().any(|arg| arg == "--first-parent");
+    blame_args.retain(|arg| arg != "--first-parent");
+    let settings = BlameSettings {
+        rev: rev.clone(),
+        after: None,
+        lines: args.line_range.map(|range| (range.start, range.end)),
+        ignore_whitespace: args.ignore_whitespace,
+        detect_moves: args.detect_moves,
+        detect_copies: args.detect_copies,
+        first_parent,
+        ignore_revs: if args.backend == Backend::Blame {
+            ignore_rev_hashes(&repo_dir, &ignored_revs)
+        } else {
+            Vec::new()
+        },
+    };
+    if args.use_committer && args.attribute != Attribute::Author {
+        return Err(anyhow::Error::msg(
+            "--use-committer cannot be combined with --attribute",
+        ));
+    }
+    if view != View::Files && (args.format != Format::Text || args.codeowners || args.primary_owner)
+    {
+        return Err(anyhow::Error::msg(
+            "--format, --codeowners and --primary-owner only apply to the files subcommand",
+        ));
+    }
+    #[cfg(feature = "interactive")]
+    {
+        if view != View::Files && args.tui {
+            return Err(anyhow::Error::msg(
+                "--tui only applies to the files subcommand",
+            ));
+        }
+    }
+    if args.granularity.is_some() && args.format != Format::Csv {
+        return Err(anyhow::Error::msg(
+            "--granularity only applies to --format csv",
+        ));
+    }
+    if args.backend == Backend::Numstat
+        && (args.regex
+            || args.no_extra
+            || args.original_author
+            || args.max_depth.is_some()
+            || args.line_range.is_some()
+            || args.detect_moves
+            || args.detect_copies
+            || contents_given)
+    {
+        return Err(anyhow::Error::msg(
+            "--regex, --no-extra, --original-author, --max-depth, -L, --detect-moves, --detect-copies, --contents-stdin and --blob only apply to --backend blame",
+        ));
+    }
+    let method = if args.backend == Backend::Numstat {
+        Method::Numstat { net: args.net }
+    } else if args.regex {
+        Method::Regex
+    } else if args.no_extra {
+        Method::NoExtra
+    } else if args.original_author {
+        Method::Original
+    } else if args.external_git {
+        Method::Nom
+    } else {
+        Method::Libgit2
+    };
+    let mut identities = Identities::new(args.merge_authors.clone());
+    if !args.no_mailmap {
+        if let Some(mailmap) = read_mailmap(&repo_dir)? {
+            identities.use_mailmap(mailmap);
+        }
+    }
+    if let Some(command) = &args.resolve_cmd {
+        identities.resolve_with_command(command.to_string());
+    }
+    identities.credit(if args.use_committer {
+        Attribute::Committer
+    } else {
+        args.attribute
+    });
+    let mut analyzer = Analyzer::with_options(Options {
+        method,
+        identities,
+        filter,
+        blame_args,
+        settings,
+        contents: None,
+        ignored_revs,
+        window,
+        skip_uncommitted: args.skip_uncommitted,
+    });
+    if args.timings {
+        analyzer.record_timings();
+    }
+    analyzer.show_paths(args.path_style, args.posix_paths);
+    if let Some(depth) = args.max_depth {
+        // With a shallower history there is nothing to leave out.
+        if let Some(boundary) = nth_ancestor(&repo_dir, rev.as_deref().unwrap_or("HEAD"), depth)? {
+            analyzer.blame_after(boundary);
+        }
+    }
+    if let Some((contents, _)) = blob {
+        analyzer.blame_contents(contents);
+    } else if args.contents_stdin {
+        let mut contents = String::new();
+        io::stdin().read_to_string(&mut contents)?;
+        analyzer.blame_contents(contents);
+    }
+    let git_only = analyzer.git_only();
+    if method == Method::Libgit2 && !git_only.is_empty() && !args.quiet {
+        eprintln!(
+            "warning: libgit2 cannot blame with {}, running git blame instead",
+            git_only.join(", ")
+        );
+    }
+
+    let start = Instant::now();
+
+    let (file_list, unresolved) = resolve_files(&args.file_list, rev.as_deref());
+    let mut file_list = dedup_files(file_list, args.quiet);
+    if let Some(count) = args.largest {
+        let (largest, skipped) = largest_files(file_list, count);
+        if skipped > 0 && !args.quiet {
+            eprintln!(
+                "note: skipped {} smaller files, analyzing the {} largest",
+                skipped,
+                largest.len()
+            );
+        }
+        file_list = largest;
+    }
+
+    if rev.is_none() && !contents_given && !args.quiet {
+        file_list
+            .iter()
+            .filter(|path| has_uncommitted_changes(path))
+            .for_each(|path| {
+                eprintln!(
+                    "warning: {} has uncommitted changes; results include working-tree state",
+                    path.display()
+                )
+            });
+    }
+
+    let submodules: BTreeSet<PathBuf> = file_list.iter().filter_map(|p| submodule_of(p)).collect();
+    submodules.iter().for_each(|submodule| {
+        eprintln!(
+            "note: {} is a submodule, paths of files in it are relative to its root",
+            submodule.display()
+        )
+    });
+
+    let results = if !args.no_progress && file_list.len() > 1 && io::stderr().is_terminal() {
+        let progress = progress::Progress::new(file_list.len());
+        let results = analyzer.analyze_par_with(&file_list, |_| progress.tick());
+        progress.finish();
+        results
+    } else {
+        analyzer.analyze_par(&file_list)
+    };
+    let (mut tracked_files, failed) = split_failures(&file_list, results);
+    tracked_files
+        .iter()
+        .flat_map(|file| file.warnings.iter())
+        .chain(analyzer.take_warnings().iter())
+        .for_each(|warning| eprintln!("warning: {}", warning));
+    if let Some(email) = &args.owned_by {
+        tracked_files.retain(|file| file.owned_by(email, args.min_percent));
+    }
+    if let Some(email) = &args.not_owned_by {
+        tracked_files.retain(|file| !file.owned_by(email, args.min_percent));
+    }
+    tracked_files
+        .iter_mut()
+        .for_each(|file| file.label_synthetic(&args.uncommitted_label, &args.boundary_label));
+    if args.anonymize {
+        // A salt picked for this run only, so pseudonyms can only be traced
+        // back to people through the --anonymize-key file.
+        let salt = RandomState::new();
+        let pseudonyms = anonymize::pseudonyms(&tracked_files, &salt);
+        if let Some(path) = &args.anonymize_key {
+            std::fs::write(path, anonymize::key(&pseudonyms))?;
+        }
+        args.anonymize_filters(&tracked_files, &pseudonyms);
+        tracked_files
+            .iter_mut()
+            .for_each(|file| anonymize::apply(file, &pseudonyms, &salt));
+    }
+    if let Some(sort) = args.sort_files {
+        sort_files(&mut tracked_files, sort, args.bus_factor_threshold);
+    }
+
+    if args.warn_bad_emails {
+        warn_bad_emails(&args, &tracked_files);
+    }
+    if args.explain_merges {
+        eprint!("{}", explain_merges(&args, &tracked_files));
+    }
+
+    let hashes = tracked_files
+        .iter()
+        .flat_map(|t| t.owners.values())
+        .flat_map(|o| o.commits.keys())
+        .map(String::as_str);
+    let abbrev = match args.abbrev {
+        Some(abbrev) => abbrev.length(hashes),
+        None => default_abbrev(),
+    };
+
+    if let Some(timings) = analyzer.into_timings() {
+        print_timings(timings, start.elapsed());
+    }
+
+    print_results(&args, view, &tracked_files, rev.as_deref(), abbrev)?;
+
+    let failures = policy_failures(&args, &tracked_files);
+    failures
+        .iter()
+        .for_each(|failure| eprintln!("fail: {}", failure));
+    if failed + unresolved > 0 {
+        return Err(anyhow::Error::msg(format!(
+            "{} of {} files could not be analyzed",
+            failed + unresolved,
+            file_list.len() + unresolved
+        )));
+    }
+    if !failures.is_empty() {
+        return Err(anyhow::Error::msg("Files failed the --fail-if checks"));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixture::{git, hunk, repo_with_file, tracked_file};
+    use chrono::TimeZone;
+    use git_whoknows::analyze::{analyze_file_nom, CommitCache};
+    use git_whoknows::ownership::{BasicHunk, NOT_COMMITTED_EMAIL};
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_read_patterns() {
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join("bots.txt");
+        std::fs::write(
+            &path,
+            "# CI accounts\nbot@ci.example.com\n\n  deploy@example.com  \n",
+        )
+        .unwrap();
+        let patterns = read_patterns(&path).unwrap();
+        assert_eq!(patterns, vec!["bot@ci.example.com", "deploy@example.com"]);
+
+        let mut filter = Some(vec!["jane@example.com".to_string()]);
+        add_patterns(&mut filter, patterns);
+        assert_eq!(filter.unwrap().len(), 3);
+
+        let mut filter = None;
+        add_patterns(&mut filter, Vec::new());
+        assert!(filter.is_none());
+        assert!(read_patterns(&dir.path().join("missing.txt")).is_err());
+    }
+
+    #[test]
+    fn test_filter_name() {
+        let repo = repo_with_file("a.txt", "one\n", "Jane Doe <jd@example.com>");
+        let analyzer = Analyzer::new(
+            Method::Nom,
+            Identities::default(),
+            LineFilter::default(),
+            Vec::new(),
+        );
+        let file = analyzer.analyze(&repo.path().join("a.txt")).unwrap();
+        let owner = &file.owners["jd@example.com"];
+
+        // Names are matched on the name, not the email.
+        assert!(Args::from_iter(&["git-whoknows", "--filter-name", "Doe"]).matches(owner));
+        assert!(!Args::from_iter(&["git-whoknows", "--filter-name", "jd@"]).matches(owner));
+    }
+
+    #[test]
+    fn test_email_in_domain() {
+        assert!(email_in_domain("jane@Example.COM", "example.com"));
+        assert!(email_in_domain("jane@example.com", "@example.com"));
+        assert!(!email_in_domain("jane@notexample.com.evil", "example.com"));
+        assert!(!email_in_domain("jane@eu.example.com", "example.com"));
+        assert!(email_in_domain("jane@eu.example.com", "*.example.com"));
+        assert!(email_in_domain("jane@example.com", "*.example.com"));
+        assert!(!email_in_domain("jane@badexample.com", "*.example.com"));
+        assert!(!email_in_domain("not.committed.yet", "example.com"));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_symlink_is_resolved_to_target() {
+        let repo = repo_with_file("target.txt", "one\ntwo\n", "Jane <jane@example.com>");
+        std::os::unix::fs::symlink("target.txt", repo.path().join("link.txt")).unwrap();
+        git(repo.path(), &["add", "link.txt"]);
+        git(repo.path(), &["commit", "-q", "-m", "Add link"]);
+
+        let link = repo.path().join("link.txt");
+        let target = resolve_symlink(&link).unwrap();
+        assert_eq!(
+            target,
+            repo.path().join("target.txt").canonicalize().unwrap()
+        );
+
+        let tracked_file = analyze_file_nom(
+            &Repository::discover(repo.path()).unwrap(),
+            &target,
+            &CommitCache::new(),
+            &Identities::default(),
+            &LineFilter::default(),
+            &[],
+            None,
+        )
+        .unwrap();
+        assert_eq!(tracked_file.repo_path, PathBuf::from("target.txt"));
+        assert_eq!(tracked_file.owners["jane@example.com"].lines(), 2);
+    }
+
+    #[test]
+    fn test_missing_file_fails() {
+        let repo = repo_with_file("file.txt", "one\n", "Jane <jane@example.com>");
+        let paths = vec![
+            repo.path().join("file.txt"),
+            repo.path().join("missing.txt"),
+        ];
+        let (resolved, failed) = resolve_files(&paths, None);
+        assert_eq!(resolved, vec![repo.path().join("file.txt")]);
+        assert_eq!(failed, 1);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_dangling_symlink_fails() {
+        let repo = repo_with_file("file.txt", "one\n", "Jane <jane@example.com>");
+        std::os::unix::fs::symlink("gone.txt", repo.path().join("link.txt")).unwrap();
+        git(repo.path(), &["add", "link.txt"]);
+        git(repo.path(), &["commit", "-q", "-m", "Add link"]);
+
+        let (resolved, failed) = resolve_files(&[repo.path().join("link.txt")], None);
+        assert!(resolved.is_empty());
+        assert_eq!(failed, 1);
+    }
+
+    #[test]
+    fn test_nested_submodule() {
+        let inner = repo_with_file("src/lib.rs", "one\ntwo\n", "Jane <jane@example.com>");
+        let middle = repo_with_file("README", "middle\n", "Joe <joe@example.com>");
+        let outer = repo_with_file("README", "outer\n", "Joe <joe@example.com>");
+        let add_submodule = |repo: &Path, url: &Path, path: &str| {
+            git(
+                repo,
+                &[
+                    "-c",
+                    "protocol.file.allow=always",
+                    "submodule",
+                    "add",
+                    "-q",
+                    url.to_str().unwrap(),
+                    path,
+                ],
+            );
+            git(repo, &["commit", "-qm", "Add submodule"]);
+        };
+        add_submodule(middle.path(), inner.path(), "vendor/inner");
+        git(
+            outer.path(),
+            &[
+                "-c",
+                "protocol.file.allow=always",
+                "clone",
+                "-q",
+                "--recurse-submodules",
+                middle.path().to_str().unwrap(),
+                "middle",
+            ],
+        );
+
+        let path = outer.path().join("middle/vendor/inner/src/lib.rs");
+        assert_eq!(submodule_of(&path), Some(PathBuf::from("vendor/inner")));
+        assert_eq!(submodule_of(&outer.path().join("README")), None);
+
+        for method in [Method::Nom, Method::NoExtra, Method::Regex] {
+            let analyzer = Analyzer::new(
+                method,
+                Identities::default(),
+                LineFilter::default(),
+                Vec::new(),
+            );
+            let tracked_file = analyzer.analyze(&path).unwrap();
+            assert_eq!(tracked_file.repo_path, PathBuf::from("src/lib.rs"));
+            assert_eq!(tracked_file.owners["jane@example.com"].lines(), 2);
+        }
+    }
+
+    #[test]
+    fn test_read_mailmap() {
+        let repo = repo_with_file("file.txt", "one\n", "Jane <jane@old.example.com>");
+        assert!(read_mailmap(repo.path()).unwrap().is_none());
+        std::fs::write(
+            repo.path().join(".mailmap"),
+            "Jane Doe <jane@example.com> <jane@old.example.com>\n",
+        )
+        .unwrap();
+        let mut identities = Identities::default();
+        identities.use_mailmap(read_mailmap(repo.path()).unwrap().unwrap());
+        assert_eq!(
+            identities.resolve("Jane".to_string(), "jane@old.example.com".to_string()),
+            ("Jane Doe".to_string(), "jane@example.com".to_string())
+        );
+    }
+
+    #[test]
+    fn test_nth_ancestor() {
+        let repo = repo_with_file("file.txt", "one\n", "Jane <jane@example.com>");
+        let first = git(repo.path(), &["rev-parse", "HEAD"]).trim().to_string();
+        git(
+            repo.path(),
+            &["commit", "-q", "--allow-empty", "-m", "Empty"],
+        );
+
+        assert_eq!(nth_ancestor(repo.path(), "HEAD", 1).unwrap(), Some(first));
+        assert_eq!(nth_ancestor(repo.path(), "HEAD", 2).unwrap(), None);
+    }
+
+    #[test]
+    fn test_check_exists_at_rev() {
+        let repo = repo_with_file("old/removed.txt", "one\ntwo\n", "Jane <jane@example.com>");
+        git(repo.path(), &["tag", "v1.0"]);
+        git(repo.path(), &["rm", "-qr", "old"]);
+        git(repo.path(), &["commit", "-qm", "Remove"]);
+
+        let path = repo.path().join("old/removed.txt");
+        check_exists_at_rev(&path, "v1.0").unwrap();
+        assert!(check_exists_at_rev(&path, "HEAD").is_err());
+    }
+
+    #[test]
+    fn test_ignored_revs() {
+        assert_eq!(
+            ignored_revs(&["ABCDEF12".to_string()]).unwrap(),
+            vec!["abcdef12".to_string()]
+        );
+        assert!(ignored_revs(&["abc".to_string()]).is_err());
+        assert!(ignored_revs(&["HEAD~1".to_string()]).is_err());
+    }
+
+    #[test]
+    fn test_read_ignore_revs_file() {
+        let repo = repo_with_file("file.txt", "one\n", "Jane <jane@example.com>");
+        let head = git(repo.path(), &["rev-parse", "HEAD"]).trim().to_string();
+        assert!(read_ignore_revs_file(repo.path()).unwrap().is_empty());
+        let contents = format!("# Bulk changes\n{} # Reformat\n", &head[..10]);
+        std::fs::write(repo.path().join(".git-blame-ignore-revs"), contents).unwrap();
+        let revs = ignored_revs(&read_ignore_revs_file(repo.path()).unwrap()).unwrap();
+        assert_eq!(revs, vec![head[..10].to_string()]);
+
+        let unknown = "0123456789".to_string();
+        let hashes = ignore_rev_hashes(repo.path(), &[revs[0].clone(), unknown]);
+        assert_eq!(hashes, vec![head]);
+    }
+
+    #[test]
+    fn test_line_range() {
+        assert_eq!(
+            " 5, 9".parse::<LineRange>().unwrap(),
+            LineRange { start: 5, end: 9 }
+        );
+        for invalid in &["0,3", "4,3", "3", "a,b", "-1,3", "3,"] {
+            assert!(invalid.parse::<LineRange>().is_err(), "{}", invalid);
+        }
+    }
+
+    #[test]
+    fn test_ignore_whitespace_flag() {
+        assert!(Args::from_iter(&["git-whoknows", "-w"]).ignore_whitespace);
+        assert!(!Args::from_iter(&["git-whoknows"]).ignore_whitespace);
+    }
+
+    #[test]
+    fn test_has_uncommitted_changes() {
+        let repo = repo_with_file("file.txt", "one\n", "Jane <jane@example.com>");
+        let path = repo.path().join("file.txt");
+        assert!(!has_uncommitted_changes(&path));
+        std::fs::write(&path, "one\ntwo\n").unwrap();
+        assert!(has_uncommitted_changes(&path));
+        git(repo.path(), &["add", "file.txt"]);
+        assert!(has_uncommitted_changes(&path));
+    }
+
+    #[test]
+    fn test_resolve_blob() {
+        let repo = repo_with_file("src/main.rs", "one\n", "Jane <jane@example.com>");
+        let rev_parse = |rev: &str| {
+            let output = Command::new("git")
+                .current_dir(repo.path())
+                .args(["rev-parse", rev])
+                .output()
+                .unwrap();
+            String::from_utf8(output.stdout).unwrap().trim().to_string()
+        };
+        let blob = rev_parse("HEAD:src/main.rs");
+        let path = repo.path().join("src/main.rs");
+
+        let (contents, found) = resolve_blob(&blob[..10], Some(&path)).unwrap();
+        assert_eq!(contents, "one\n");
+        assert_eq!(found, path);
+
+        std::fs::write(repo.path().join("other.rs"), "two\n").unwrap();
+        git(repo.path(), &["add", "other.rs"]);
+        git(repo.path(), &["commit", "-qm", "Add other"]);
+        let other = rev_parse("HEAD:other.rs");
+        let (contents, found) = resolve_blob(&other, Some(&path)).unwrap();
+        assert_eq!(contents, "two\n");
+        assert_eq!(found, path);
+
+        let tree = rev_parse("HEAD^{tree}");
+        let error = resolve_blob(&tree, Some(&path)).unwrap_err();
+        assert!(error.to_string().contains("is a tree, not a blob"));
+        assert!(resolve_blob("0123456789abcdef", Some(&path)).is_err());
+    }
+
+    #[test]
+    fn test_merge_base() {
+        let repo = repo_with_file("file.txt", "one\n", "Jane <jane@example.com>");
+        let head = |repo: &Path| {
+            let output = Command::new("git")
+                .current_dir(repo)
+                .args(["rev-parse", "HEAD"])
+                .output()
+                .unwrap();
+            String::from_utf8(output.stdout).unwrap().trim().to_string()
+        };
+        let base = head(repo.path());
+        git(repo.path(), &["checkout", "-qb", "feature"]);
+        git(
+            repo.path(),
+            &["commit", "-q", "--allow-empty", "-m", "Feature"],
+        );
+        git(repo.path(), &["checkout", "-q", "-"]);
+        git(
+            repo.path(),
+            &["commit", "-q", "--allow-empty", "-m", "Main"],
+        );
+        assert_eq!(merge_base(repo.path(), "HEAD", "feature").unwrap(), base);
+
+        git(repo.path(), &["checkout", "-q", "--orphan", "unrelated"]);
+        git(repo.path(), &["commit", "-q", "-m", "Unrelated"]);
+        let error = merge_base(repo.path(), "unrelated", "feature").unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "unrelated and feature have no common ancestor"
+        );
+        assert!(merge_base(repo.path(), "HEAD", "missing").is_err());
+    }
+
+    #[test]
+    fn test_dedup_files() {
+        let repo = repo_with_file("src/main.rs", "fn main() {}\n", "Jane <jane@example.com>");
+        let path = repo.path().join("src/main.rs");
+        let files = dedup_files(
+            vec![
+                path.clone(),
+                repo.path().join("src/../src/main.rs"),
+                repo.path().join("missing.rs"),
+                path.clone(),
+                repo.path().join("missing.rs"),
+            ],
+            true,
+        );
+        assert_eq!(files, vec![path, repo.path().join("missing.rs")]);
+    }
+
+    #[test]
+    fn test_largest_files() {
+        let dir = TempDir::new().unwrap();
+        let paths: Vec<PathBuf> = [("a", 3), ("b", 10), ("c", 1), ("d", 10)]
+            .iter()
+            .map(|(name, size)| {
+                let path = dir.path().join(name);
+                std::fs::write(&path, "x".repeat(*size)).unwrap();
+                path
+            })
+            .collect();
+        let missing = dir.path().join("missing");
+
+        let mut all = paths.clone();
+        all.push(missing);
+        let (largest, skipped) = largest_files(all, 3);
+        assert_eq!(
+            largest,
+            vec![paths[0].clone(), paths[1].clone(), paths[3].clone()]
+        );
+        assert_eq!(skipped, 2);
+
+        let (largest, skipped) = largest_files(paths.clone(), 10);
+        assert_eq!(largest, paths);
+        assert_eq!(skipped, 0);
+    }
+
+    #[test]
+    fn test_tracked_files_under() {
+        let repo = repo_with_file("src/main.rs", "fn main() {}\n", "Jane <jane@example.com>");
+        std::fs::write(repo.path().join("README.md"), "readme\n").unwrap();
+        git(repo.path(), &["add", "README.md"]);
+        std::fs::write(repo.path().join("src/untracked.rs"), "\n").unwrap();
+
+        let mut files = tracked_files_under(repo.path()).unwrap();
+        files.sort();
+        assert_eq!(
+            files,
+            vec![PathBuf::from("README.md"), PathBuf::from("src/main.rs")]
+        );
+        assert_eq!(
+            tracked_files_under(&repo.path().join("src")).unwrap(),
+            vec![PathBuf::from("main.rs")]
+        );
+    }
+
+    #[test]
+    fn test_expand_dirs() {
+        let repo = repo_with_file("src/main.rs", "fn main() {}\n", "Jane <jane@example.com>");
+        std::fs::write(repo.path().join("src/lib.rs"), "\n").unwrap();
+        git(repo.path(), &["add", "src/lib.rs"]);
+        std::fs::write(repo.path().join("src/untracked.rs"), "\n").unwrap();
+        std::fs::write(repo.path().join("README.md"), "readme\n").unwrap();
+        // Ignored files are skipped unless they were added anyway.
+        std::fs::write(repo.path().join(".gitignore"), "*.o\n").unwrap();
+        std::fs::write(repo.path().join("src/main.o"), "\n").unwrap();
+        std::fs::write(repo.path().join("src/vendored.o"), "\n").unwrap();
+        git(repo.path(), &["add", "-f", "src/vendored.o"]);
+
+        let readme = repo.path().join("README.md");
+        assert_eq!(
+            expand_dirs(&[repo.path().join("src"), readme.clone()]).unwrap(),
+            vec![
+                repo.path().join("src/lib.rs"),
+                repo.path().join("src/main.rs"),
+                repo.path().join("src/vendored.o"),
+                readme
+            ]
+        );
+    }
+
+    #[test]
+    fn test_listed_files() {
+        let repo = repo_with_file("src/main.rs", "fn main() {}\n", "Jane <jane@example.com>");
+        let main = repo.path().join("src/main.rs");
+        let input = format!("{}\n", main.display());
+        assert_eq!(
+            listed_files(&[], input.as_bytes(), false).unwrap(),
+            vec![main.clone()]
+        );
+        assert_eq!(
+            listed_files(&[repo.path().join("src")], "".as_bytes(), false).unwrap(),
+            vec![main]
+        );
+
+        // Empty stdin, as under cron or with </dev/null, lists nothing to
+        // analyze.
+        assert!(listed_files(&[], "".as_bytes(), false).is_err());
+        assert!(listed_files(&[PathBuf::from("-")], "\n".as_bytes(), false).is_err());
+    }
+
+    #[test]
+    fn test_paths_from_input() {
+        let input = "src/main.rs\r\n\nsrc/lib.rs\n".as_bytes();
+        assert_eq!(
+            paths_from_input(&[], input, false).unwrap(),
+            vec![PathBuf::from("src/main.rs"), PathBuf::from("src/lib.rs")]
+        );
+        let input = "my file.rs\0new\nline.rs\0".as_bytes();
+        assert_eq!(
+            paths_from_input(&[], input, true).unwrap(),
+            vec![PathBuf::from("my file.rs"), PathBuf::from("new\nline.rs")]
+        );
+
+        let paths = [
+            PathBuf::from("README.md"),
+            PathBuf::from("-"),
+            PathBuf::from("-"),
+        ];
+        assert_eq!(
+            paths_from_input(&paths, "src/main.rs\n".as_bytes(), false).unwrap(),
+            vec![PathBuf::from("README.md"), PathBuf::from("src/main.rs")]
+        );
+    }
+
+    #[test]
+    fn test_merge_owners_counts_files() {
+        let files = vec![
+            tracked_file("a.rs", &[("specialist", 90), ("generalist", 1)]),
+            tracked_file("b.rs", &[("generalist", 1)]),
+            tracked_file("c.rs", &[("generalist", 1)]),
+        ];
+
+        let owners = merge_owners(&files);
+        let owner = |email: &str| owners.iter().find(|o| o.email == email).unwrap();
+        assert_eq!(owner("specialist").files.len(), 1);
+        assert_eq!(owner("generalist").files.len(), 3);
+        assert_eq!(owner("generalist").lines(), 3);
+    }
+
+    #[test]
+    fn test_subcommands() {
+        let parse = |argv: &[&str]| Cli::from_iter(argv).into_parts();
+
+        let (args, view) = parse(&["git-whoknows", "--rev", "v1", "a.rs", "b.rs"]);
+        assert_eq!(
+            args.file_list,
+            vec![PathBuf::from("a.rs"), PathBuf::from("b.rs")]
+        );
+        assert_eq!(args.rev.as_deref(), Some("v1"));
+        assert_eq!(view, View::Files);
+
+        let (args, view) = parse(&["git-whoknows", "files", "--rev", "v1", "a.rs"]);
+        assert_eq!(args.file_list, vec![PathBuf::from("a.rs")]);
+        assert_eq!(args.rev.as_deref(), Some("v1"));
+        assert_eq!(view, View::Files);
+
+        let (args, view) = parse(&["git-whoknows", "summary", "--code-only", "a.rs", "b.rs"]);
+        assert_eq!(args.file_list.len(), 2);
+        assert!(args.code_only);
+        assert_eq!(view, View::Summary);
+
+        let (args, view) = parse(&["git-whoknows", "reviewers", "--top", "2", "a.rs"]);
+        assert_eq!(args.top, Some(2));
+        assert_eq!(view, View::Reviewers);
+        assert_eq!(parse(&["git-whoknows", "at-risk"]).1, View::AtRisk);
+
+        // Only the first argument is taken as a subcommand, so files named
+        // like one can be given after any other.
+        let (args, view) = parse(&["git-whoknows", "a.rs", "summary"]);
+        assert_eq!(
+            args.file_list,
+            vec![PathBuf::from("a.rs"), PathBuf::from("summary")]
+        );
+        assert_eq!(view, View::Files);
+        let (args, view) = parse(&["git-whoknows", "files", "summary"]);
+        assert_eq!(args.file_list, vec![PathBuf::from("summary")]);
+        assert_eq!(view, View::Files);
+    }
+
+    #[test]
+    fn test_reviewers() {
+        let owners = merge_owners(&vec![
+            tracked_file("a.rs", &[("jane@example.com", 5), ("joe@example.com", 3)]),
+            tracked_file(
+                "b.rs",
+                &[
+                    ("ann@example.com", 4),
+                    ("joe@example.com", 3),
+                    (NOT_COMMITTED_EMAIL, 9),
+                ],
+            ),
+        ]);
+        let emails = |argv: &[&str], user_email| -> Vec<String> {
+            reviewers(&Args::from_iter(argv), &owners, user_email)
+                .iter()
+                .map(|o| o.email.to_string())
+                .collect()
+        };
+
+        assert_eq!(
+            emails(&["git-whoknows"], None),
+            ["joe@example.com", "jane@example.com", "ann@example.com"]
+        );
+        // The user is not suggested to review their own change.
+        assert_eq!(
+            emails(&["git-whoknows", "--top", "2"], Some("Joe@example.com")),
+            ["jane@example.com", "ann@example.com"]
+        );
+    }
+
+    #[test]
+    fn test_at_risk() {
+        let files = vec![
+            tracked_file("single.rs", &[("a", 3)]),
+            tracked_file("shared.rs", &[("a", 2), ("b", 2), ("c", 1)]),
+            tracked_file("mostly.rs", &[("a", 1), ("b", 3)]),
+            tracked_file("empty.rs", &[]),
+        ];
+        let risky = |argv: &[&str]| -> Vec<(String, String)> {
+            at_risk(&Args::from_iter(argv), &files)
+                .iter()
+                .map(|(file, owner)| (file.path.to_string(), owner.email.to_string()))
+                .collect()
+        };
+
+        assert_eq!(
+            risky(&["git-whoknows"]),
+            [
+                ("single.rs".to_string(), "a".to_string()),
+                ("mostly.rs".to_string(), "b".to_string())
+            ]
+        );
+        assert_eq!(
+            risky(&["git-whoknows", "--bus-factor-threshold", "80"]),
+            [("single.rs".to_string(), "a".to_string())]
+        );
+    }
+
+    #[test]
+    fn test_explain_merges() {
+        let identities = Identities::new(vec![r"-bot@ci\.example\.com$=>CI".parse().unwrap()]);
+        let hunk = |author: &str, mail: &str| BasicHunk {
+            author: author.to_string(),
+            ..hunk("1", mail, 1)
+        };
+        let mut a = tracked_file("a", &[]);
+        a.add_hunk(&hunk("Build Bot", "build-bot@ci.example.com"), &identities);
+        a.add_hunk(&hunk("Jane", "jane@example.com"), &identities);
+        let mut b = tracked_file("b", &[]);
+        b.add_hunk(
+            &hunk("Deploy Bot", "deploy-bot@ci.example.com"),
+            &identities,
+        );
+        b.add_hunk(&hunk("Jane", "jane@example.com"), &identities);
+        let files = [a, b];
+
+        assert_eq!(
+            explain_merges(&Args::from_iter(&["git-whoknows"]), &files),
+            "Identities\n \
+             CI <CI>\n   \
+             Build Bot <build-bot@ci.example.com>\n   \
+             Deploy Bot <deploy-bot@ci.example.com>\n \
+             Jane <jane@example.com>\n   \
+             Jane <jane@example.com>\n"
+        );
+        // Owners are shown as chosen with --display, the identities recorded
+        // on their commits as they were.
+        assert_eq!(
+            explain_merges(
+                &Args::from_iter(&["git-whoknows", "--display", "email"]),
+                &files
+            ),
+            "Identities\n \
+             CI\n   \
+             Build Bot <build-bot@ci.example.com>\n   \
+             Deploy Bot <deploy-bot@ci.example.com>\n \
+             jane@example.com\n   \
+             Jane <jane@example.com>\n"
+        );
+    }
+
+    #[test]
+    fn test_sort_files() {
+        let mut files = vec![
+            tracked_file("b", &[("a", 5), ("b", 5)]),
+            tracked_file("c", &[("a", 20)]),
+            tracked_file("a", &[("a", 2), ("b", 2), ("c", 2)]),
+        ];
+        let order = |files: &[TrackedFile]| -> Vec<String> {
+            files.iter().map(|f| f.path.to_string()).collect()
+        };
+
+        sort_files(&mut files, FileSort::Path, 50.0);
+        assert_eq!(order(&files), ["a", "b", "c"]);
+        sort_files(&mut files, FileSort::Owners, 50.0);
+        assert_eq!(order(&files), ["a", "b", "c"]);
+        sort_files(&mut files, FileSort::Lines, 50.0);
+        assert_eq!(order(&files), ["c", "b", "a"]);
+        sort_files(&mut files, FileSort::BusFactor, 50.0);
+        assert_eq!(order(&files), ["c", "b", "a"]);
+    }
+
+    #[test]
+    fn test_policy_failures() {
+        let files = vec![
+            tracked_file("single.rs", &[("a", 3)]),
+            tracked_file("shared.rs", &[("a", 2), ("b", 2), ("c", 1)]),
+            tracked_file("empty.rs", &[]),
+        ];
+
+        let args = Args::from_iter(&["git-whoknows"]);
+        assert!(policy_failures(&args, &files).is_empty());
+
+        let args = Args::from_iter(&[
+            "git-whoknows",
+            "--fail-if-single-owner",
+            "--fail-if-bus-factor-below",
+            "2",
+        ]);
+        assert_eq!(
+            policy_failures(&args, &files),
+            vec![
+                "single.rs has a bus factor of 1, below 2",
+                "single.rs has a single owner"
+            ]
+        );
+    }
+
+    #[test]
+    fn test_warn_bad_emails_malformed() {
+        let files = vec![
+            tracked_file("a.rs", &[("jane", 1), ("joe@example.com", 1)]),
+            tracked_file("b.rs", &[("jane", 1)]),
+        ];
+        let args = Args::from_iter(&["git-whoknows", "--warn-bad-emails"]);
+        assert_eq!(
+            bad_email_warnings(&args, &files),
+            ["jane <jane> has a malformed email"]
+        );
+    }
+
+    #[test]
+    fn test_warn_bad_emails_valid() {
+        let files = vec![tracked_file("a.rs", &[("jane@example.com", 1)])];
+        let args = Args::from_iter(&["git-whoknows", "--warn-bad-emails"]);
+        assert!(bad_email_warnings(&args, &files).is_empty());
+    }
+
+    #[test]
+    fn test_date_range() {
+        let mut file = tracked_file("a.rs", &[]);
+        assert_eq!(date_range_suffix(date_range(file.owners.values())), "");
+        for (i, (email, year, month)) in [("a", 2019, 5), ("b", 2018, 3), ("a", 2024, 1)]
+            .iter()
+            .enumerate()
+        {
+            let hunk = BasicHunk {
+                time: FixedOffset::east(0).ymd(*year, *month, 1).and_hms(0, 0, 0),
+                ..hunk(&i.to_string(), email, 1)
+            };
+            file.add_hunk(&hunk, &Identities::default());
+        }
+        assert_eq!(
+            date_range_suffix(date_range(file.owners.values())),
+            " (oldest: 2018-03, newest: 2024-01)"
+        );
+    }
+
+    #[test]
+    fn test_date_styles() {
+        let now = FixedOffset::east(0).ymd(2023, 6, 1).and_hms(12, 0, 0);
+        let date = FixedOffset::west(7 * 3600)
+            .ymd(2020, 4, 10)
+            .and_hms(19, 9, 1);
+        assert_eq!(DateStyle::Relative.format(date, now), "3 years ago");
+        assert_eq!(
+            DateStyle::Iso.format(date, now),
+            "2020-04-10T19:09:01-07:00"
+        );
+        assert_eq!(DateStyle::Short.format(date, now), "2020-04-10");
+        assert_eq!(DateStyle::Unix.format(date, now), "1586570941");
+
+        let relative = |seconds: i64| relative_date(chrono::Duration::seconds(seconds));
+        assert_eq!(relative(30), "just now");
+        assert_eq!(relative(60), "1 minute ago");
+        assert_eq!(relative(5 * 3600), "5 hours ago");
+        assert_eq!(relative(30 * 3600), "yesterday");
+        assert_eq!(relative(3 * 86400), "3 days ago");
+        assert_eq!(relative(20 * 86400), "2 weeks ago");
+        assert_eq!(relative(100 * 86400), "3 months ago");
+        assert_eq!(relative(-10), "just now");
+    }
+
+    #[test]
+    fn test_zone() {
+        let date = FixedOffset::west(7 * 3600)
+            .ymd(2020, 4, 10)
+            .and_hms(19, 9, 1);
+        let convert = |zone: &str| zone.parse::<Zone>().unwrap().convert(date).to_rfc3339();
+        assert_eq!(convert("UTC"), "2020-04-11T02:09:01+00:00");
+        assert_eq!(convert("+0530"), "2020-04-11T07:39:01+05:30");
+        assert_eq!(convert("-0000"), "2020-04-11T02:09:01+00:00");
+        assert_eq!("local".parse::<Zone>().unwrap(), Zone::Local);
+        assert_eq!("local".parse::<Zone>().unwrap().convert(date), date);
+        for zone in &["0530", "+530", "+05:30", "+0560", "+2400", "EST"] {
+            assert!(zone.parse::<Zone>().is_err(), "{}", zone);
+        }
+    }
+
+    #[test]
+    fn test_truncate() {
+        assert_eq!(truncate("Jane", 4), "Jane");
+        assert_eq!(truncate("Jane Doe", 4), "Jan…");
+        assert_eq!(truncate("Zoë Åström", 4), "Zoë…");
+        // The e and its combining accent are a single character.
+        assert_eq!(truncate("Rene\u{301}e", 5), "Rene\u{301}e");
+        assert_eq!(truncate("Rene\u{301}e", 4), "Ren…");
+    }
+
+    #[test]
+    fn test_truncate_zero_width() {
+        assert_eq!(truncate("Jane", 0), "");
+        assert_eq!(truncate("", 0), "");
+        assert_eq!(truncate("J", 1), "J");
+    }
+
+    #[test]
+    fn test_display_name() {
+        let owner = |name: &str, email: &str| Owner::new(name.to_string(), email.to_string());
+        let jane = owner("Jane Doe", "jane@example.com");
+        let nameless = owner(" ", "joe@example.com");
+        let emailless = owner("Ann", "");
+
+        assert_eq!(DisplayName::Name.of(&jane), "Jane Doe");
+        assert_eq!(DisplayName::Name.of(&nameless), "joe@example.com");
+        assert_eq!(DisplayName::Email.of(&jane), "jane@example.com");
+        assert_eq!(DisplayName::Email.of(&emailless), "Ann");
+        assert_eq!(DisplayName::NameOrEmail.of(&jane), "Jane Doe");
+        assert_eq!(DisplayName::NameOrEmail.of(&nameless), "joe");
+    }
+
+    #[test]
+    fn test_sort_by_ratio() {
+        let mut bulk = Owner::new("Bulk".to_string(), "bulk@example.com".to_string());
+        bulk.commits.insert("a".to_string(), 100);
+        let mut small = Owner::new("Small".to_string(), "small@example.com".to_string());
+        (0..10).for_each(|i| {
+            small.commits.insert(i.to_string(), 15);
+        });
+        assert_eq!(bulk.ratio(), 100.0);
+        assert_eq!(small.ratio(), 15.0);
+        assert_eq!(Owner::new(String::new(), String::new()).ratio(), 0.0);
+
+        let mut owners = vec![&bulk, &small];
+        sort_owners(&mut owners, Sort::Lines);
+        assert_eq!(owners[0].name, "Small");
+        sort_owners(&mut owners, Sort::Ratio);
+        assert_eq!(owners[0].name, "Bulk");
+
+        // Ties are broken by email so the order is the same on every run.
+        let mut tied = Owner::new("Tied".to_string(), "a@example.com".to_string());
+        tied.commits.insert("b".to_string(), 150);
+        let mut owners = vec![&small, &tied];
+        sort_owners(&mut owners, Sort::Lines);
+        assert_eq!(owners[0].name, "Tied");
+    }
+
+    #[test]
+    fn test_listed_owners() {
+        let file = tracked_file(
+            "a.rs",
+            &[
+                ("jane@example.com", 3),
+                ("joe@example.com", 5),
+                ("ann@corp.example.com", 4),
+            ],
+        );
+        let emails = |argv: &[&str]| -> Vec<String> {
+            listed_owners(&Args::from_iter(argv), &file)
+                .iter()
+                .map(|o| o.email.to_string())
+                .collect()
+        };
+
+        assert_eq!(
+            emails(&["git-whoknows"]),
+            [
+                "joe@example.com",
+                "ann@corp.example.com",
+                "jane@example.com"
+            ]
+        );
+        assert_eq!(
+            emails(&["git-whoknows", "--top", "2"]),
+            ["joe@example.com", "ann@corp.example.com"]
+        );
+        // Owners are filtered before the top ones are taken.
+        assert_eq!(
+            emails(&[
+                "git-whoknows",
+                "--top",
+                "1",
+                "--filter-email",
+                "@example.com"
+            ]),
+            ["joe@example.com"]
+        );
+        assert_eq!(
+            emails(&["git-whoknows", "--top", "1", "--filter-email", "jane"]),
+            ["jane@example.com"]
+        );
+    }
+
+    #[test]
+    fn test_anonymize_filters() {
+        let mut files = vec![tracked_file(
+            "a.rs",
+            &[
+                ("jane@example.com", 3),
+                ("joe@example.com", 5),
+                ("ann@corp.example.com", 4),
+            ],
+        )];
+        let salt = RandomState::new();
+        let pseudonyms = anonymize::pseudonyms(&files, &salt);
+        let mut args = Args::from_iter(&[
+            "git-whoknows",
+            "--anonymize",
+            "--filter-email",
+            "jane",
+            "--filter-email",
+            "ann",
+            "--exclude-email-domain",
+            "corp.example.com",
+        ]);
+
+        args.anonymize_filters(&files, &pseudonyms);
+        anonymize::apply(&mut files[0], &pseudonyms, &salt);
+        let emails: Vec<String> = listed_owners(&args, &files[0])
+            .iter()
+            .map(|o| o.email.to_string())
+            .collect();
+        assert_eq!(emails, [pseudonyms["jane@example.com"].email.to_string()]);
+    }
+
+    #[test]
+    fn test_anonymize_by_commit() {
+        let repo = repo_with_file("a.txt", "one\ntwo\n", "Jane <jane@example.com>");
+        let head = git(repo.path(), &["rev-parse", "HEAD"]).trim().to_string();
+
+        let analyzer = Analyzer::new(
+            Method::Nom,
+            Identities::default(),
+            LineFilter::default(),
+            Vec::new(),
+        );
+        let mut files = vec![analyzer.analyze(&repo.path().join("a.txt")).unwrap()];
+        let salt = RandomState::new();
+        let pseudonyms = anonymize::pseudonyms(&files, &salt);
+        anonymize::apply(&mut files[0], &pseudonyms, &salt);
+
+        let args = Args::from_iter(&["git-whoknows", "--anonymize", "--by-commit"]);
+        let json = report::Report::new(&files, None, args.by_commit, |o| args.matches(o), args.top)
+            .to_json()
+            .unwrap();
+        let csv = csv::generate(
+            &files,
+            Granularity::Commit,
+            |o| args.matches(o),
+            args.top,
+            |o| args.display_name(o),
+        );
+        for output in [&json, &csv] {
+            assert!(!output.contains(&head[..7]), "{}", output);
+            assert!(!output.contains("jane@example.com"), "{}", output);
+        }
+        // Dates are kept to the day, without the author's time zone.
+        let owner = files[0].owners.values().next().unwrap();
+        let date = owner.last_touched().unwrap();
+        assert_eq!(date.offset().local_minus_utc(), 0);
+        assert_eq!(date.format("%H:%M:%S").to_string(), "00:00:00");
+    }
+
+    #[test]
+    fn test_weighted_lines() {
+        let now = FixedOffset::east(0).ymd(2023, 1, 31).and_hms(0, 0, 0);
+        let hunk = |hash: &str, email: &str, days_ago: i64, num_lines| BasicHunk {
+            time: now - chrono::Duration::days(days_ago),
+            ..hunk(hash, email, num_lines)
+        };
+        let mut file = tracked_file("a.rs", &[]);
+        let identities = Identities::default();
+        file.add_hunk(&hunk("1", "old@example.com", 60, 100), &identities);
+        file.add_hunk(&hunk("2", "new@example.com", 0, 20), &identities);
+        file.add_hunk(&hunk("3", "new@example.com", 30, 20), &identities);
+
+        let old = &file.owners["old@example.com"];
+        let new = &file.owners["new@example.com"];
+        assert_eq!(old.weighted_lines(30.0, now), 25.0);
+        assert_eq!(new.weighted_lines(30.0, now), 30.0);
+        assert_eq!(old.weighted_lines(f64::INFINITY, now), 100.0);
+
+        // Recent work outweighs more lines written long ago.
+        let mut owners = vec![old, new];
+        sort_owners(&mut owners, Sort::Lines);
+        assert_eq!(owners[0].email, "old@example.com");
+        let mut owners = vec![old, new];
+        sort_owners(&mut owners, Sort::Weighted(1.0));
+        assert_eq!(owners[0].email, "new@example.com");
+    }
+
+    #[test]
+    fn test_unambiguous_length() {
+        let hashes = [
+            "1234567aaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaa",
+            "123456789bbbbbbbbbbbbbbbbbbbbbbbbbbbbbbb",
+            "abcdef0000000000000000000000000000000000",
+            "abcdef0000000000000000000000000000000000",
+        ];
+        assert_eq!(unambiguous_length(hashes.iter().copied()), 8);
+        assert_eq!(unambiguous_length(hashes[2..].iter().copied()), MIN_ABBREV);
+        assert_eq!(Abbrev::Auto.length(hashes.iter().copied()), 8);
+        assert_eq!(
+            "12".parse::<Abbrev>()
+                .unwrap()
+                .length(hashes.iter().copied()),
+            12
+        );
+        assert!("3".parse::<Abbrev>().is_err());
+        assert!("short".parse::<Abbrev>().is_err());
+    }
+
+    #[test]
+    fn test_owner_percentage() {
+        let mut file = tracked_file("a.rs", &[]);
+        let owner = Owner::new("Jane".to_string(), "jane@example.com".to_string());
+        // An empty file has no lines to take a share of.
+        assert_eq!(file.owner_percentage(&owner), 0.0);
+
+        for (hash, lines) in &[("1", 1), ("2", 3)] {
+            let hunk = BasicHunk {
+                author: format!("Author {}", hash),
+                ..hunk(hash, &format!("{}@example.com", hash), *lines)
+            };
+            file.add_hunk(&hunk, &Identities::default());
+        }
+        assert_eq!(file.owner_percentage(&file.owners["1@example.com"]), 25.0);
+        assert_eq!(file.owner_percentage(&file.owners["2@example.com"]), 75.0);
+    }
 }
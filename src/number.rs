@@ -0,0 +1,72 @@
+/// Formatting of the numbers in the text output.
+///
+/// Machine readable formats always use plain numbers.
+#[derive(Clone)]
+pub struct NumberFormat {
+    /// Separator inserted between each group of three digits of counts.
+    thousands_sep: Option<String>,
+    /// Number of decimal places of percentages.
+    percent_precision: usize,
+}
+
+impl Default for NumberFormat {
+    fn default() -> NumberFormat {
+        NumberFormat {
+            thousands_sep: None,
+            percent_precision: 1,
+        }
+    }
+}
+
+impl NumberFormat {
+    pub fn new(thousands_sep: Option<String>, percent_precision: usize) -> NumberFormat {
+        NumberFormat {
+            thousands_sep,
+            percent_precision,
+        }
+    }
+
+    pub fn count(&self, count: usize) -> String {
+        let digits = count.to_string();
+        let sep = match &self.thousands_sep {
+            Some(sep) => sep,
+            None => return digits,
+        };
+
+        let groups: Vec<&str> = digits
+            .as_bytes()
+            .rchunks(3)
+            .rev()
+            .map(|group| std::str::from_utf8(group).unwrap())
+            .collect();
+        groups.join(sep)
+    }
+
+    pub fn percent(&self, percent: f64) -> String {
+        format!("{:.*}%", self.percent_precision, percent)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_count() {
+        let plain = NumberFormat::default();
+        assert_eq!(plain.count(1234567), "1234567");
+
+        let separated = NumberFormat::new(Some(",".to_string()), 1);
+        assert_eq!(separated.count(0), "0");
+        assert_eq!(separated.count(999), "999");
+        assert_eq!(separated.count(1000), "1,000");
+        assert_eq!(separated.count(1234567), "1,234,567");
+    }
+
+    #[test]
+    fn test_percent() {
+        assert_eq!(NumberFormat::default().percent(37.142857), "37.1%");
+        assert_eq!(NumberFormat::new(None, 0).percent(37.6), "38%");
+        assert_eq!(NumberFormat::new(None, 3).percent(37.142857), "37.143%");
+    }
+}
@@ -0,0 +1,126 @@
+//! History of a file from `git log --numstat`, used by `--backend numstat`.
+//!
+//! Unlike blame, which credits the lines a file has now, the log credits every
+//! line an author added over the history of the file, including lines since
+//! changed or removed.
+
+use crate::error::WhoKnowsError;
+
+use chrono::{DateTime, FixedOffset};
+
+use std::path::Path;
+use std::process::Command;
+
+/// Marks the start of each commit in the log output.
+const COMMIT_MARKER: char = '\x00';
+
+/// Lines added and deleted by a commit in a file.
+#[derive(Debug, PartialEq)]
+pub struct Change<'a> {
+    pub hash: &'a str,
+    pub author: &'a str,
+    pub email: &'a str,
+    pub time: DateTime<FixedOffset>,
+    pub committer: &'a str,
+    pub committer_email: &'a str,
+    pub added: usize,
+    pub deleted: usize,
+}
+
+/// Run git log in `dir` on `file`, relative to `dir`, following renames.
+pub fn generate_numstat(dir: &Path, file: &Path) -> Result<String, WhoKnowsError> {
+    let output = Command::new("git")
+        .current_dir(dir)
+        .args([
+            "log",
+            "--numstat",
+            "--follow",
+            "--format=%x00%H%x09%an%x09%ae%x09%aI%x09%cn%x09%ce",
+            "--",
+        ])
+        .arg(file)
+        .output()
+        .map_err(WhoKnowsError::spawn)?;
+    if !output.status.success() {
+        return Err(WhoKnowsError::git_failed(
+            "log",
+            dir.join(file),
+            &output.stderr,
+        ));
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).to_string())
+}
+
+/// Parse the output of `generate_numstat`.
+///
+/// Binary changes, which have no line counts, count as no lines.
+pub fn parse_numstat(txt: &str) -> Result<Vec<Change<'_>>, WhoKnowsError> {
+    let mut changes = Vec::new();
+    for (number, line) in txt.lines().enumerate() {
+        let error = || WhoKnowsError::parse(number + 1, line);
+
+        if let Some(header) = line.strip_prefix(COMMIT_MARKER) {
+            let mut fields = header.splitn(6, '\t');
+            let mut field = || fields.next().ok_or_else(error);
+            let (hash, author, email, time) = (field()?, field()?, field()?, field()?);
+            let (committer, committer_email) = (field()?, field()?);
+            changes.push(Change {
+                hash,
+                author,
+                email,
+                time: DateTime::parse_from_rfc3339(time).map_err(|_| error())?,
+                committer,
+                committer_email,
+                added: 0,
+                deleted: 0,
+            });
+        } else if !line.is_empty() {
+            let mut fields = line.splitn(3, '\t');
+            let mut count = || match fields.next() {
+                Some("-") => Ok(0),
+                Some(count) => count.parse::<usize>().map_err(|_| error()),
+                None => Err(error()),
+            };
+            let (added, deleted) = (count()?, count()?);
+            let change = changes.last_mut().ok_or_else(error)?;
+            change.added = change.added.saturating_add(added);
+            change.deleted = change.deleted.saturating_add(deleted);
+        }
+    }
+    Ok(changes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_numstat() {
+        let input = "\u{0}a1b2\tJane Doe\tjane@example.com\t2023-01-02T03:04:05-07:00\tJoe\tjoe@example.com\n\
+                     \n\
+                     12\t3\tsrc/main.rs\n\
+                     \u{0}c3d4\tJoe\tjoe@example.com\t2022-12-01T00:00:00+00:00\tJoe\tjoe@example.com\n\
+                     \n\
+                     -\t-\tsrc/logo.png\n\
+                     \u{0}e5f6\tJoe\tjoe@example.com\t2022-11-01T00:00:00+00:00\tJoe\tjoe@example.com\n\
+                     \n\
+                     40\t0\t{old => src}/main.rs\n";
+        let changes = parse_numstat(input).unwrap();
+        assert_eq!(changes.len(), 3);
+        assert_eq!(changes[0].hash, "a1b2");
+        assert_eq!(changes[0].author, "Jane Doe");
+        assert_eq!(changes[0].email, "jane@example.com");
+        assert_eq!(changes[0].time.to_rfc3339(), "2023-01-02T03:04:05-07:00");
+        assert_eq!(changes[0].committer_email, "joe@example.com");
+        assert_eq!((changes[0].added, changes[0].deleted), (12, 3));
+        assert_eq!((changes[1].added, changes[1].deleted), (0, 0));
+        assert_eq!((changes[2].added, changes[2].deleted), (40, 0));
+    }
+
+    #[test]
+    fn test_parse_numstat_errors() {
+        assert!(parse_numstat("12\t3\tsrc/main.rs\n").is_err());
+        assert!(parse_numstat("\u{0}a1b2\tJane\n").is_err());
+        assert!(parse_numstat("\u{0}a1b2\tJane\tj@e\t2023-01-02\n").is_err());
+    }
+}
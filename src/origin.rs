@@ -0,0 +1,158 @@
+use crate::blame;
+use crate::error::WhoKnowsError;
+use dashmap::DashMap;
+use git2::{DiffOptions, Oid, Patch, Repository};
+
+use std::path::Path;
+
+/// Commit that introduced each line, by the commit that last changed it, the
+/// path of the file in that commit and the line number in that file.
+///
+/// Shared between files, as lines moved or copied between files resolve
+/// through the same commits.
+pub type OriginCache = DashMap<(String, String, usize), String>;
+
+/// A line as blamed at one commit: the commit that last changed it, its path
+/// and line number there, and the commit and path before that change, if any.
+struct Blamed {
+    hash: String,
+    filename: String,
+    line: usize,
+    previous: Option<(String, String)>,
+}
+
+impl Blamed {
+    fn from_line(line: &blame::Line) -> Option<Blamed> {
+        let extra = line.header.extra.as_ref()?;
+        Some(Blamed {
+            hash: line.header.hash.to_string(),
+            filename: extra.filename.to_string(),
+            line: line.header.line_num_orig,
+            previous: extra
+                .previous
+                .as_ref()
+                .map(|previous| (previous.sha.to_string(), previous.filename.to_string())),
+        })
+    }
+
+    fn key(&self) -> (String, String, usize) {
+        (self.hash.clone(), self.filename.clone(), self.line)
+    }
+}
+
+/// Resolve the commit that first wrote each line of line porcelain blame output.
+///
+/// A line blamed to a commit that replaced other lines is followed to the line
+/// it replaced, paired by position within the changed hunk, and blamed again
+/// in the parent, until a commit added it without replacing anything or there
+/// is no parent. Lines that are not committed yet keep the zero hash.
+///
+/// Each line is blamed again with the options of `blame_args` that change
+/// which commit lines are credited to, such as `-w` or `-M`.
+pub fn original_commits(
+    repo: &Repository,
+    txt: &str,
+    lines: &[blame::Line],
+    cache: &OriginCache,
+    blame_args: &[String],
+) -> Result<Vec<String>, WhoKnowsError> {
+    let blame_args = blame::attribution_args(blame_args);
+    lines
+        .iter()
+        .enumerate()
+        .map(|(index, line)| match Blamed::from_line(line) {
+            Some(blamed) => resolve(repo, blamed, cache, &blame_args),
+            None => {
+                let (number, header) = blame::header_line(txt, index);
+                Err(WhoKnowsError::parse(number, header))
+            }
+        })
+        .collect()
+}
+
+fn resolve(
+    repo: &Repository,
+    mut blamed: Blamed,
+    cache: &OriginCache,
+    blame_args: &[String],
+) -> Result<String, WhoKnowsError> {
+    let mut chain = Vec::new();
+    let origin = loop {
+        if let Some(origin) = cache.get(&blamed.key()) {
+            break origin.clone();
+        }
+        chain.push(blamed.key());
+        let (previous_hash, previous_filename) = match &blamed.previous {
+            Some(previous) if !Oid::from_str(&blamed.hash)?.is_zero() => previous.clone(),
+            _ => break blamed.hash,
+        };
+        let replaced = replaced_line(
+            repo,
+            (&previous_hash, &previous_filename),
+            (&blamed.hash, &blamed.filename),
+            blamed.line,
+        )?;
+        blamed = match replaced {
+            Some(line) => blame_line(repo, &previous_hash, &previous_filename, line, blame_args)?,
+            None => break blamed.hash,
+        };
+    };
+    for key in chain {
+        cache.insert(key, origin.clone());
+    }
+    Ok(origin)
+}
+
+/// Line of the file at `old` that `line` of the file at `new` replaced, if it
+/// replaced one rather than being added.
+fn replaced_line(
+    repo: &Repository,
+    old: (&str, &str),
+    new: (&str, &str),
+    line: usize,
+) -> Result<Option<usize>, WhoKnowsError> {
+    let blob = |(hash, filename): (&str, &str)| {
+        repo.revparse_single(&format!("{}:{}", hash, filename))
+            .and_then(|object| object.peel_to_blob())
+    };
+    let (old_blob, new_blob) = (blob(old)?, blob(new)?);
+    let mut options = DiffOptions::new();
+    options.context_lines(0);
+    let patch = Patch::from_blobs(&old_blob, None, &new_blob, None, Some(&mut options))?;
+    let mut replaced = None;
+    for index in 0..patch.num_hunks() {
+        let (hunk, _) = patch.hunk(index)?;
+        let new_start = hunk.new_start() as usize;
+        if (new_start..new_start + hunk.new_lines() as usize).contains(&line) {
+            let offset = line - new_start;
+            if offset < hunk.old_lines() as usize {
+                replaced = Some(hunk.old_start() as usize + offset);
+            }
+            break;
+        }
+    }
+    Ok(replaced)
+}
+
+/// Blame a single line of `filename` as of the commit `hash`, adding
+/// `blame_args` to the command line.
+fn blame_line(
+    repo: &Repository,
+    hash: &str,
+    filename: &str,
+    line: usize,
+    blame_args: &[String],
+) -> Result<Blamed, WhoKnowsError> {
+    let dir = repo.workdir().unwrap_or_else(|| repo.path());
+    let mut args = blame_args.to_vec();
+    args.extend([
+        "-L".to_string(),
+        format!("{},{}", line, line),
+        hash.to_string(),
+    ]);
+    let txt = blame::run_blame(dir, Path::new(filename), "--line-porcelain", &args, None)?;
+    blame::parse_blame(&txt, |_| false)?
+        .first()
+        .and_then(Blamed::from_line)
+        .ok_or_else(|| WhoKnowsError::parse(1, &txt))
+}
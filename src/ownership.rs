@@ -0,0 +1,637 @@
+//! Owners of the lines of each file, and the hunks of blame output they are
+//! credited from.
+
+use crate::error::Warning;
+use crate::identity::{Attribute, Identities};
+
+use anyhow::Result;
+use chrono::{DateTime, FixedOffset, Local, TimeZone};
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+use std::fmt;
+use std::path::PathBuf;
+use std::str::FromStr;
+
+/// A file and the owners of its lines, keyed by their lowercased email.
+pub struct TrackedFile {
+    pub path: String,
+    /// Path of the file relative to the root of its repository.
+    pub repo_path: PathBuf,
+    pub owners: HashMap<String, Owner>,
+    /// Whether blame found no lines at all, as opposed to lines that were all
+    /// filtered out.
+    pub empty: bool,
+    /// Problems met blaming the file that did not stop its analysis.
+    pub warnings: Vec<Warning>,
+}
+
+impl TrackedFile {
+    pub fn new(path: String, repo_path: PathBuf) -> TrackedFile {
+        TrackedFile {
+            path,
+            repo_path,
+            owners: HashMap::new(),
+            empty: false,
+            warnings: Vec::new(),
+        }
+    }
+
+    /// Total number of lines attributed to all owners.
+    pub fn lines(&self) -> usize {
+        self.owners
+            .values()
+            .fold(0, |total, o| total.saturating_add(o.lines()))
+    }
+
+    /// Percentage of the file's lines attributed to `owner`.
+    pub fn owner_percentage(&self, owner: &Owner) -> f64 {
+        percentage(owner.lines(), self.lines())
+    }
+
+    /// Whether the owner with `email` owns at least `min_percent` of the lines,
+    /// and at least one line.
+    pub fn owned_by(&self, email: &str, min_percent: f64) -> bool {
+        self.owners.values().any(|o| {
+            o.email.eq_ignore_ascii_case(email)
+                && o.lines() > 0
+                && self.owner_percentage(o) >= min_percent
+        })
+    }
+
+    /// Owner of more than half of the file's lines, if there is one.
+    pub fn primary_owner(&self) -> Option<&Owner> {
+        let total = self.lines();
+        self.owners.values().find(|o| o.lines() > total - o.lines())
+    }
+
+    /// Smallest number of owners who together own more than half of the
+    /// file's lines.
+    pub fn bus_factor(&self) -> usize {
+        self.bus_factor_above(50.0)
+    }
+
+    /// Smallest number of owners who together own more than `threshold`
+    /// percent of the file's lines.
+    pub fn bus_factor_above(&self, threshold: f64) -> usize {
+        let mut lines: Vec<usize> = self.owners.values().map(|o| o.lines()).collect();
+        lines.sort_unstable();
+        lines.reverse();
+
+        let total = self.lines();
+        let mut owned = 0;
+        for (count, owner_lines) in lines.iter().enumerate() {
+            owned += owner_lines;
+            if percentage(owned, total) > threshold {
+                return count + 1;
+            }
+        }
+        lines.len()
+    }
+
+    /// Concentration of the file's lines among its owners, see `concentration`.
+    pub fn concentration(&self) -> Option<f64> {
+        concentration(self.owners.values())
+    }
+
+    pub fn merge(&mut self, other: &TrackedFile) {
+        other.owners.iter().for_each(|(email, owner)| {
+            self.owners
+                .entry(email.to_string())
+                .or_insert_with(|| Owner::new(owner.name.to_string(), owner.email.to_string()))
+                .merge(owner)
+        });
+    }
+
+    /// Credit the lines of `hunk` to the owners it resolves to.
+    ///
+    /// Owners are keyed by lowercased email, as emails differing only in case
+    /// are the same person, and keep the name and email of the first hunk.
+    pub fn add_hunk(&mut self, hunk: &impl Hunk, identities: &Identities) {
+        let raw = match (identities.attribute(), hunk.committer()) {
+            (Attribute::Committer, Some(committer)) => committer,
+            _ => (hunk.author(), hunk.email()),
+        };
+        let (name, email) = identities.resolve(raw.0.clone(), raw.1.clone());
+        if let (Attribute::Both, Some(raw_committer)) = (identities.attribute(), hunk.committer()) {
+            let (committer, committer_email) =
+                identities.resolve(raw_committer.0.clone(), raw_committer.1.clone());
+            if !committer_email.eq_ignore_ascii_case(&email) {
+                // Committers are kept apart from the same people as authors.
+                self.owners
+                    .entry(format!("{} (committer)", committer_email.to_lowercase()))
+                    .or_insert_with(|| {
+                        Owner::new(format!("{} (committer)", committer), committer_email)
+                    })
+                    .add_aliased_hunk(hunk, raw_committer);
+            }
+        }
+        self.owners
+            .entry(email.to_lowercase())
+            .or_insert_with(|| Owner::new(name, email))
+            .add_aliased_hunk(hunk, raw);
+    }
+
+    /// Drop the lines of commits whose hash starts with one of `revs`, along
+    /// with owners left without lines.
+    pub fn ignore_revs(&mut self, revs: &[String]) {
+        self.owners
+            .values_mut()
+            .for_each(|owner| owner.ignore_revs(revs));
+        self.owners.retain(|_, owner| !owner.commits.is_empty());
+    }
+
+    /// Drop the lines not committed yet, along with owners left without
+    /// lines.
+    pub fn skip_uncommitted(&mut self) {
+        self.owners
+            .values_mut()
+            .for_each(|owner| owner.retain_commits(|hash| !is_uncommitted(hash)));
+        self.owners.retain(|_, owner| !owner.commits.is_empty());
+    }
+
+    /// Drop the lines of commits authored outside `window`, along with owners
+    /// left without lines.
+    pub fn keep_within(&mut self, window: &DateWindow) {
+        self.owners
+            .values_mut()
+            .for_each(|owner| owner.keep_within(window));
+        self.owners.retain(|_, owner| !owner.commits.is_empty());
+    }
+
+    /// Move the lines of the boundary commit `hash`, which stands in for all
+    /// of the history before it, to a single owner.
+    pub fn bucket_boundary(&mut self, hash: &str) {
+        let mut bucket = Owner::new("Boundary".to_string(), BOUNDARY_EMAIL.to_string());
+        for owner in self.owners.values_mut() {
+            if let Some(lines) = owner.commits.remove(hash) {
+                let total = bucket.commits.entry(hash.to_string()).or_insert(0);
+                *total = total.saturating_add(lines);
+            }
+            if let Some(date) = owner.dates.remove(hash) {
+                bucket.dates.insert(hash.to_string(), date);
+            }
+            owner.summaries.remove(hash);
+        }
+        self.owners.retain(|_, owner| !owner.commits.is_empty());
+        if !bucket.commits.is_empty() {
+            self.owners.insert(BOUNDARY_EMAIL.to_string(), bucket);
+        }
+    }
+
+    /// Name the owners standing in for lines not committed yet and lines
+    /// older than the boundary.
+    pub fn label_synthetic(&mut self, uncommitted: &str, boundary: &str) {
+        for owner in self.owners.values_mut() {
+            match owner.email.as_str() {
+                NOT_COMMITTED_EMAIL => owner.name = uncommitted.to_string(),
+                BOUNDARY_EMAIL => owner.name = boundary.to_string(),
+                _ => {}
+            }
+        }
+    }
+}
+
+/// Whether `hash` is the all-zero hash git blame gives lines that are not
+/// committed yet.
+pub fn is_uncommitted(hash: &str) -> bool {
+    !hash.is_empty() && hash.bytes().all(|b| b == b'0')
+}
+
+/// Email of the owner credited with the lines older than `--max-depth`.
+pub const BOUNDARY_EMAIL: &str = "boundary";
+
+/// Email git blame gives the lines that are not committed yet.
+pub const NOT_COMMITTED_EMAIL: &str = "not.committed.yet";
+
+/// Someone credited with lines of a file, or of several merged files.
+pub struct Owner {
+    pub name: String,
+    pub email: String,
+    pub commits: HashMap<String, usize>,
+    /// Author time of each commit in `commits`.
+    pub dates: HashMap<String, DateTime<FixedOffset>>,
+    /// Summary of each commit in `commits`, when known.
+    pub summaries: HashMap<String, String>,
+    /// Names and emails recorded on the commits that were credited to this
+    /// owner, before any merging or resolving.
+    pub aliases: BTreeSet<(String, String)>,
+    /// Paths of the files the lines are in, only tracked when the owners of
+    /// several files are merged.
+    pub files: HashSet<String>,
+}
+
+impl Owner {
+    pub fn new(name: String, email: String) -> Owner {
+        Owner {
+            name,
+            email,
+            commits: HashMap::new(),
+            dates: HashMap::new(),
+            summaries: HashMap::new(),
+            aliases: BTreeSet::new(),
+            files: HashSet::new(),
+        }
+    }
+
+    fn add_hunk(&mut self, hunk: &impl Hunk) {
+        let lines = self.commits.entry(hunk.sha1()).or_insert(0);
+        *lines = lines.saturating_add(hunk.lines());
+        self.dates.insert(hunk.sha1(), hunk.author_time());
+        if let Some(summary) = hunk.summary() {
+            self.summaries.insert(hunk.sha1(), summary);
+        }
+    }
+
+    /// Whether the owner stands in for lines not committed yet or older than
+    /// the boundary, rather than being a person.
+    pub fn is_synthetic(&self) -> bool {
+        self.email == NOT_COMMITTED_EMAIL || self.email == BOUNDARY_EMAIL
+    }
+
+    /// Add a hunk recorded on its commit as `alias`.
+    fn add_aliased_hunk(&mut self, hunk: &impl Hunk, alias: (String, String)) {
+        self.add_hunk(hunk);
+        self.aliases.insert(alias);
+    }
+
+    /// Total number of lines, saturating rather than overflowing on
+    /// implausible counts.
+    pub fn lines(&self) -> usize {
+        self.commits
+            .values()
+            .fold(0, |total, lines| total.saturating_add(*lines))
+    }
+
+    fn ignore_revs(&mut self, revs: &[String]) {
+        self.retain_commits(|hash| !revs.iter().any(|rev| hash.starts_with(rev.as_str())));
+    }
+
+    /// Drop the lines of commits authored outside `window`.
+    fn keep_within(&mut self, window: &DateWindow) {
+        let outside: HashSet<String> = self
+            .dates
+            .iter()
+            .filter(|(_, date)| !window.contains(**date))
+            .map(|(hash, _)| hash.to_string())
+            .collect();
+        self.retain_commits(|hash| !outside.contains(hash));
+    }
+
+    /// Keep only the lines of the commits whose hash `keep` accepts.
+    fn retain_commits(&mut self, keep: impl Fn(&String) -> bool) {
+        self.commits.retain(|hash, _| keep(hash));
+        self.dates.retain(|hash, _| keep(hash));
+        self.summaries.retain(|hash, _| keep(hash));
+    }
+
+    pub fn merge(&mut self, other: &Owner) {
+        other.commits.iter().for_each(|(hash, lines)| {
+            let total = self.commits.entry(hash.to_string()).or_insert(0);
+            *total = total.saturating_add(*lines);
+        });
+        other.dates.iter().for_each(|(hash, date)| {
+            self.dates.insert(hash.to_string(), *date);
+        });
+        other.summaries.iter().for_each(|(hash, summary)| {
+            self.summaries.insert(hash.to_string(), summary.to_string());
+        });
+        self.files.extend(other.files.iter().cloned());
+        self.aliases.extend(other.aliases.iter().cloned());
+    }
+
+    /// Lines weighted by the age of their commit as of `now`, each line
+    /// counting half as much for every `half_life` days since it was written.
+    pub fn weighted_lines(&self, half_life: f64, now: DateTime<FixedOffset>) -> f64 {
+        self.commits
+            .iter()
+            .map(|(hash, lines)| {
+                let age = self.dates.get(hash).map_or(0.0, |date| {
+                    (now - *date).num_seconds().max(0) as f64 / 86_400.0
+                });
+                *lines as f64 * 0.5_f64.powf(age / half_life)
+            })
+            .sum()
+    }
+
+    /// Average number of lines per commit, high for bulk imports or generated
+    /// code.
+    pub fn ratio(&self) -> f64 {
+        if self.commits.is_empty() {
+            return 0.0;
+        }
+        self.lines() as f64 / self.commits.len() as f64
+    }
+
+    /// Author time of the most recent commit.
+    pub fn last_touched(&self) -> Option<DateTime<FixedOffset>> {
+        self.dates.values().max().copied()
+    }
+
+    /// Number of commits made in each period, keyed by the formatted period.
+    pub fn activity(&self, period: Period) -> BTreeMap<String, usize> {
+        let mut activity = BTreeMap::new();
+        self.dates.values().for_each(|date| {
+            *activity
+                .entry(date.format(period.format()).to_string())
+                .or_insert(0) += 1
+        });
+        activity
+    }
+}
+
+/// Percentage `part` is of `total`, 0 when there is nothing in total.
+pub fn percentage(part: usize, total: usize) -> f64 {
+    if total == 0 {
+        0.0
+    } else {
+        part as f64 * 100.0 / total as f64
+    }
+}
+
+/// Gini coefficient of the lines owned by each owner.
+///
+/// The coefficient is normalized so a single owner of all lines gives 1 and an
+/// even split between any number of owners gives 0. There is no value when
+/// there are no lines.
+pub fn concentration<'a>(owners: impl Iterator<Item = &'a Owner>) -> Option<f64> {
+    let mut lines: Vec<f64> = owners.map(|o| o.lines() as f64).collect();
+    let total: f64 = lines.iter().sum();
+    if total == 0.0 {
+        return None;
+    }
+    if lines.len() == 1 {
+        return Some(1.0);
+    }
+
+    lines.sort_by(|a, b| a.partial_cmp(b).unwrap());
+    let n = lines.len() as f64;
+    let weighted: f64 = lines
+        .iter()
+        .enumerate()
+        .map(|(i, x)| (i + 1) as f64 * x)
+        .sum();
+    let gini = 2.0 * weighted / (n * total) - (n + 1.0) / n;
+    Some(gini * n / (n - 1.0))
+}
+
+/// Period of author times whose commits are counted, open at either end
+/// when no bound is given.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct DateWindow {
+    since: Option<DateTime<FixedOffset>>,
+    /// End of the window, not included.
+    until: Option<DateTime<FixedOffset>>,
+}
+
+impl DateWindow {
+    /// Build a window from the `--since` and `--until` dates.
+    ///
+    /// Each is either a date, taken in the local time zone, or an RFC 3339
+    /// time. A date given for `until` includes the whole of that day.
+    pub fn new(since: Option<&str>, until: Option<&str>) -> Result<DateWindow> {
+        let bound = |option: &str, s: &str, end_of_day: bool| {
+            if let Ok(time) = DateTime::parse_from_rfc3339(s) {
+                return Ok(time);
+            }
+            let error = || {
+                anyhow::Error::msg(format!(
+                    "{} '{}' is not a date such as 2023-01-31 or a time such as \
+                     2023-01-31T12:00:00+01:00",
+                    option, s
+                ))
+            };
+            let mut date = chrono::NaiveDate::parse_from_str(s, "%Y-%m-%d").map_err(|_| error())?;
+            if end_of_day {
+                date = date.succ_opt().ok_or_else(error)?;
+            }
+            let midnight = date.and_hms_opt(0, 0, 0).ok_or_else(error)?;
+            Local
+                .from_local_datetime(&midnight)
+                .earliest()
+                .map(DateTime::<FixedOffset>::from)
+                .ok_or_else(error)
+        };
+        Ok(DateWindow {
+            since: since.map(|s| bound("--since", s, false)).transpose()?,
+            until: until.map(|s| bound("--until", s, true)).transpose()?,
+        })
+    }
+
+    pub fn is_open(&self) -> bool {
+        self.since.is_none() && self.until.is_none()
+    }
+
+    pub fn contains(&self, date: DateTime<FixedOffset>) -> bool {
+        self.since.is_none_or(|since| date >= since) && self.until.is_none_or(|until| date < until)
+    }
+}
+
+/// Period that commits are grouped by when showing activity.
+#[derive(Clone, Copy)]
+pub enum Period {
+    Month,
+    Year,
+}
+
+impl Period {
+    pub fn format(self) -> &'static str {
+        match self {
+            Period::Month => "%Y-%m",
+            Period::Year => "%Y",
+        }
+    }
+}
+
+impl FromStr for Period {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Period> {
+        match s {
+            "month" => Ok(Period::Month),
+            "year" => Ok(Period::Year),
+            _ => Err(anyhow::Error::msg("Period must be one of month or year")),
+        }
+    }
+}
+
+impl fmt::Display for Owner {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{} <{}>: Lines: {} Count: {}",
+            self.name,
+            self.email,
+            self.lines(),
+            self.commits.len()
+        )
+    }
+}
+
+/// Lines of a file blamed on a single commit.
+pub trait Hunk {
+    fn sha1(&self) -> String;
+    fn author(&self) -> String;
+    fn email(&self) -> String;
+    fn author_time(&self) -> DateTime<FixedOffset>;
+    fn lines(&self) -> usize;
+    /// First line of the commit message, when known.
+    fn summary(&self) -> Option<String>;
+    /// Name and email of the committer, when known.
+    fn committer(&self) -> Option<(String, String)>;
+}
+
+/// Definition of a hunk with no dependencies.
+pub struct BasicHunk {
+    pub hash: String,
+    pub author: String,
+    pub mail: String,
+    pub time: DateTime<FixedOffset>,
+    pub num_lines: usize,
+    pub summary: Option<String>,
+    /// Name and email of the committer, when known.
+    pub committer: Option<(String, String)>,
+}
+
+impl Hunk for BasicHunk {
+    fn sha1(&self) -> String {
+        self.hash.clone()
+    }
+    fn author(&self) -> String {
+        self.author.clone()
+    }
+    fn email(&self) -> String {
+        self.mail.clone()
+    }
+    fn author_time(&self) -> DateTime<FixedOffset> {
+        self.time
+    }
+    fn lines(&self) -> usize {
+        self.num_lines
+    }
+    fn summary(&self) -> Option<String> {
+        self.summary.clone()
+    }
+    fn committer(&self) -> Option<(String, String)> {
+        self.committer.clone()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixture::{hunk, tracked_file};
+
+    #[test]
+    fn test_activity() {
+        let mut owner = Owner::new("Jane".to_string(), "jane@example.com".to_string());
+        let utc = FixedOffset::east_opt(0).unwrap();
+        [
+            ("a", 2023, 1, 3),
+            ("b", 2023, 1, 30),
+            ("c", 2023, 2, 1),
+            ("d", 2024, 5, 1),
+        ]
+        .iter()
+        .for_each(|(hash, year, month, day)| {
+            let date = chrono::NaiveDate::from_ymd_opt(*year, *month, *day).unwrap();
+            owner.add_hunk(&BasicHunk {
+                time: utc.from_utc_datetime(&date.and_hms_opt(12, 0, 0).unwrap()),
+                ..hunk(hash, &owner.email, 1)
+            })
+        });
+
+        let months: Vec<(String, usize)> = owner.activity(Period::Month).into_iter().collect();
+        assert_eq!(
+            months,
+            vec![
+                ("2023-01".to_string(), 2),
+                ("2023-02".to_string(), 1),
+                ("2024-05".to_string(), 1)
+            ]
+        );
+        let years: Vec<(String, usize)> = owner.activity(Period::Year).into_iter().collect();
+        assert_eq!(
+            years,
+            vec![("2023".to_string(), 3), ("2024".to_string(), 1)]
+        );
+    }
+
+    #[test]
+    fn test_owner_lines_saturate() {
+        let hunk = |hash: &str, num_lines| hunk(hash, "jane@example.com", num_lines);
+        let mut owner = Owner::new("Jane".to_string(), "jane@example.com".to_string());
+        owner.add_hunk(&hunk("a", usize::MAX));
+        owner.add_hunk(&hunk("a", 1));
+        owner.add_hunk(&hunk("b", usize::MAX));
+        assert_eq!(owner.lines(), usize::MAX);
+
+        let mut other = Owner::new("Jane".to_string(), "jane@example.com".to_string());
+        other.merge(&owner);
+        other.merge(&owner);
+        assert_eq!(other.lines(), usize::MAX);
+    }
+
+    #[test]
+    fn test_email_case() {
+        let hunk = |hash: &str, author: &str, mail: &str, num_lines| BasicHunk {
+            author: author.to_string(),
+            ..hunk(hash, mail, num_lines)
+        };
+        let mut file = TrackedFile::new("a".to_string(), PathBuf::from("a"));
+        let identities = Identities::default();
+        file.add_hunk(&hunk("1", "Jane Doe", "Jane@Corp.com", 2), &identities);
+        file.add_hunk(&hunk("2", "jane", "jane@corp.com", 3), &identities);
+
+        assert_eq!(file.owners.len(), 1);
+        let owner = &file.owners["jane@corp.com"];
+        assert_eq!(owner.name, "Jane Doe");
+        assert_eq!(owner.email, "Jane@Corp.com");
+        assert_eq!(owner.lines(), 5);
+    }
+
+    #[test]
+    fn test_bus_factor() {
+        assert_eq!(tracked_file("a.rs", &[]).bus_factor(), 0);
+        let mut file = tracked_file("a.rs", &[("a", 4), ("b", 3), ("c", 2), ("d", 1)]);
+        // 4 of 10 lines is not more than half, 7 is.
+        assert_eq!(file.bus_factor(), 2);
+        assert_eq!(file.bus_factor_above(70.0), 3);
+        assert_eq!(file.bus_factor_above(0.0), 1);
+        assert!(file.primary_owner().is_none());
+
+        file.add_hunk(&hunk("4", "a", 7), &Identities::default());
+        // 11 of 17 lines.
+        assert_eq!(file.primary_owner().unwrap().email, "a");
+    }
+
+    #[test]
+    fn test_owned_by() {
+        let file = tracked_file("a.rs", &[("a", 4), ("b", 3), ("c", 2), ("d", 1)]);
+        // Emails match without regard to case.
+        assert!(file.owned_by("D", 0.0));
+        // d has 10% of the lines, c has 20%.
+        assert!(!file.owned_by("d", 20.0));
+        assert!(file.owned_by("c", 20.0));
+        assert!(!file.owned_by("e", 0.0));
+    }
+
+    #[test]
+    fn test_concentration() {
+        let owners = |lines: &[usize]| -> Vec<Owner> {
+            lines
+                .iter()
+                .enumerate()
+                .map(|(i, lines)| {
+                    let mut owner = Owner::new(i.to_string(), i.to_string());
+                    owner.commits.insert(i.to_string(), *lines);
+                    owner
+                })
+                .collect()
+        };
+
+        assert_eq!(concentration(owners(&[]).iter()), None);
+        assert_eq!(concentration(owners(&[7]).iter()), Some(1.0));
+        assert_eq!(concentration(owners(&[5, 5, 5]).iter()), Some(0.0));
+        let skewed = concentration(owners(&[1, 1, 98]).iter()).unwrap();
+        assert!((skewed - 0.97).abs() < 1e-9, "{}", skewed);
+    }
+}
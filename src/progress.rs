@@ -0,0 +1,78 @@
+use std::io::{self, Write};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+
+/// Width of the bar, in characters, between its brackets.
+const BAR_WIDTH: usize = 30;
+
+/// Count of the files analyzed so far, redrawn in place on stderr.
+///
+/// Files finish on several threads at once, so the count is atomic and each
+/// redraw holds a lock to keep the lines from interleaving.
+pub struct Progress {
+    total: usize,
+    done: AtomicUsize,
+    drawn: Mutex<usize>,
+}
+
+impl Progress {
+    pub fn new(total: usize) -> Progress {
+        let progress = Progress {
+            total,
+            done: AtomicUsize::new(0),
+            drawn: Mutex::new(0),
+        };
+        progress.draw(0);
+        progress
+    }
+
+    /// Count another file as analyzed.
+    pub fn tick(&self) {
+        let done = self.done.fetch_add(1, Ordering::Relaxed) + 1;
+        self.draw(done);
+    }
+
+    /// Erase the bar, so what is printed next starts on a clean line.
+    pub fn finish(&self) {
+        let drawn = self.drawn.lock().unwrap();
+        eprint!("\r{}\r", " ".repeat(*drawn));
+        let _ = io::stderr().flush();
+    }
+
+    fn draw(&self, done: usize) {
+        let mut drawn = self.drawn.lock().unwrap();
+        let line = bar(done, self.total);
+        eprint!("\r{}", line);
+        let _ = io::stderr().flush();
+        *drawn = line.len();
+    }
+}
+
+/// Render `done` of `total` files as a bar followed by the counts.
+fn bar(done: usize, total: usize) -> String {
+    let filled = (done.min(total) * BAR_WIDTH)
+        .checked_div(total)
+        .unwrap_or(BAR_WIDTH);
+    format!(
+        "[{}{}] {}/{} files",
+        "=".repeat(filled),
+        " ".repeat(BAR_WIDTH - filled),
+        done,
+        total
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_bar() {
+        assert_eq!(bar(0, 4), format!("[{}] 0/4 files", " ".repeat(30)));
+        assert_eq!(
+            bar(1, 4),
+            format!("[{}{}] 1/4 files", "=".repeat(7), " ".repeat(23))
+        );
+        assert_eq!(bar(4, 4), format!("[{}] 4/4 files", "=".repeat(30)));
+    }
+}
@@ -0,0 +1,269 @@
+//! Serializable view of the analysis results used by the machine readable
+//! output formats.
+//!
+//! These types are kept separate from the aggregation types so the output
+//! schema only changes deliberately. Any change to the fields must bump
+//! `SCHEMA_VERSION`.
+
+use git_whoknows::{Owner, TrackedFile};
+
+use anyhow::Result;
+use chrono::Local;
+use serde::{Deserialize, Serialize};
+
+/// Version of the output schema.
+///
+/// In the bincode format it is encoded first, as a little-endian `u32`, so
+/// consumers can check it before decoding the rest of the report.
+pub const SCHEMA_VERSION: u32 = 5;
+
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+pub struct Report {
+    pub schema_version: u32,
+    /// Time the report was generated, in RFC 3339 format.
+    pub generated_at: String,
+    /// Revision the files were blamed at, when one was given.
+    pub rev: Option<String>,
+    pub files: Vec<FileReport>,
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+pub struct FileReport {
+    pub path: String,
+    /// Owners of the file ordered by the number of lines they own, then by
+    /// email.
+    pub owners: Vec<OwnerReport>,
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+pub struct OwnerReport {
+    /// Key of the owner that is stable across runs, see `owner_id`.
+    pub id: String,
+    pub name: String,
+    pub email: String,
+    pub lines: u64,
+    pub commits: u64,
+    /// Whether the owner stands in for lines not committed yet or older than
+    /// the boundary, rather than being a person.
+    pub synthetic: bool,
+    /// Lines from each commit, ordered by the number of lines, only included
+    /// with `--by-commit`.
+    pub by_commit: Option<Vec<CommitReport>>,
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+pub struct CommitReport {
+    pub sha: String,
+    pub lines: u64,
+    /// Author time of the commit, in RFC 3339 format.
+    pub date: Option<String>,
+}
+
+impl Report {
+    /// Build a report of the owners of each file accepted by `filter`, only
+    /// the first `top` of them if given, blamed at `rev` if given, with the
+    /// lines from each of their commits if `by_commit` is set.
+    pub fn new(
+        files: &[TrackedFile],
+        rev: Option<&str>,
+        by_commit: bool,
+        filter: impl Fn(&Owner) -> bool,
+        top: Option<usize>,
+    ) -> Report {
+        Report {
+            schema_version: SCHEMA_VERSION,
+            generated_at: Local::now().to_rfc3339(),
+            rev: rev.map(str::to_string),
+            files: files
+                .iter()
+                .map(|file| {
+                    let mut owners: Vec<&Owner> =
+                        file.owners.values().filter(|o| filter(o)).collect();
+                    owners.sort_by(|a, b| b.lines().cmp(&a.lines()).then(a.email.cmp(&b.email)));
+                    if let Some(top) = top {
+                        owners.truncate(top);
+                    }
+                    FileReport {
+                        path: file.path.to_string(),
+                        owners: owners
+                            .into_iter()
+                            .map(|owner| {
+                                let mut report = OwnerReport::from(owner);
+                                if by_commit {
+                                    report.by_commit = Some(CommitReport::of(owner));
+                                }
+                                report
+                            })
+                            .collect(),
+                    }
+                })
+                .collect(),
+        }
+    }
+
+    pub fn to_json(&self) -> Result<String> {
+        Ok(serde_json::to_string_pretty(self)?)
+    }
+
+    pub fn to_bincode(&self) -> Result<Vec<u8>> {
+        Ok(bincode::serialize(self)?)
+    }
+}
+
+impl From<&Owner> for OwnerReport {
+    fn from(owner: &Owner) -> OwnerReport {
+        OwnerReport {
+            id: owner_id(&owner.email),
+            name: owner.name.to_string(),
+            email: owner.email.to_string(),
+            lines: owner.lines() as u64,
+            commits: owner.commits.len() as u64,
+            synthetic: owner.is_synthetic(),
+            by_commit: None,
+        }
+    }
+}
+
+impl CommitReport {
+    /// Reports of the commits of `owner`, with the most lines first.
+    fn of(owner: &Owner) -> Vec<CommitReport> {
+        let mut commits: Vec<CommitReport> = owner
+            .commits
+            .iter()
+            .map(|(sha, lines)| CommitReport {
+                sha: sha.to_string(),
+                lines: *lines as u64,
+                date: owner.dates.get(sha).map(|date| date.to_rfc3339()),
+            })
+            .collect();
+        commits.sort_by(|a, b| b.lines.cmp(&a.lines).then(a.sha.cmp(&b.sha)));
+        commits
+    }
+}
+
+/// Stable identifier of the owner with `email`.
+///
+/// The email is trimmed and lowercased, and the identifier is the hex SHA-1
+/// git gives it as a blob, the same as
+/// `printf '%s' "$email" | git hash-object --stdin`.
+pub fn owner_id(email: &str) -> String {
+    let normalized = email.trim().to_lowercase();
+    git2::Oid::hash_object(git2::ObjectType::Blob, normalized.as_bytes())
+        .expect("Hashing an object in memory must succeed.")
+        .to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixture::tracked_file;
+    use std::convert::TryInto;
+
+    #[test]
+    fn test_decode_bincode() {
+        let report = Report {
+            schema_version: SCHEMA_VERSION,
+            generated_at: "2023-01-02T03:04:05-07:00".to_string(),
+            rev: Some("v1.0".to_string()),
+            files: vec![FileReport {
+                path: "src/main.rs".to_string(),
+                owners: vec![OwnerReport {
+                    id: owner_id("jane@example.com"),
+                    name: "Jane".to_string(),
+                    email: "jane@example.com".to_string(),
+                    lines: 12,
+                    commits: 3,
+                    synthetic: false,
+                    by_commit: Some(vec![CommitReport {
+                        sha: "dbdf0caee4e14c03e5c3b8c7575219b3affe5657".to_string(),
+                        lines: 12,
+                        date: Some("2023-01-02T03:04:05-07:00".to_string()),
+                    }]),
+                }],
+            }],
+        };
+        let bytes = report.to_bincode().unwrap();
+
+        // A consumer checks the schema version before decoding the report.
+        let version = u32::from_le_bytes(bytes[..4].try_into().unwrap());
+        assert_eq!(version, SCHEMA_VERSION);
+
+        let decoded: Report = bincode::deserialize(&bytes).unwrap();
+        assert_eq!(decoded, report);
+    }
+
+    #[test]
+    fn test_json() {
+        let report = Report {
+            schema_version: SCHEMA_VERSION,
+            generated_at: "2023-01-02T03:04:05-07:00".to_string(),
+            rev: None,
+            files: vec![FileReport {
+                path: "src/main.rs".to_string(),
+                owners: vec![OwnerReport {
+                    id: owner_id("jane@example.com"),
+                    name: "Jane".to_string(),
+                    email: "jane@example.com".to_string(),
+                    lines: 12,
+                    commits: 3,
+                    synthetic: false,
+                    by_commit: None,
+                }],
+            }],
+        };
+        let json: serde_json::Value = serde_json::from_str(&report.to_json().unwrap()).unwrap();
+        assert_eq!(json["schema_version"], SCHEMA_VERSION);
+        assert_eq!(json["files"][0]["path"], "src/main.rs");
+        assert_eq!(json["files"][0]["owners"][0]["email"], "jane@example.com");
+        assert_eq!(json["files"][0]["owners"][0]["lines"], 12);
+        assert_eq!(json["files"][0]["owners"][0]["commits"], 3);
+    }
+
+    #[test]
+    fn test_owner_order() {
+        let files = [tracked_file(
+            "a.rs",
+            &[
+                ("joe@example.com", 2),
+                ("ann@example.com", 5),
+                ("jane@example.com", 2),
+                ("bob@example.com", 2),
+            ],
+        )];
+        // Owners with as many lines are ordered by email, whatever the order
+        // of the map they are in.
+        let report = Report::new(&files, None, false, |_| true, None);
+        let emails: Vec<&str> = report.files[0]
+            .owners
+            .iter()
+            .map(|o| o.email.as_str())
+            .collect();
+        assert_eq!(
+            emails,
+            [
+                "ann@example.com",
+                "bob@example.com",
+                "jane@example.com",
+                "joe@example.com"
+            ]
+        );
+
+        // The first owners in that order are kept.
+        let report = Report::new(&files, None, false, |_| true, Some(2));
+        let emails: Vec<&str> = report.files[0]
+            .owners
+            .iter()
+            .map(|o| o.email.as_str())
+            .collect();
+        assert_eq!(emails, ["ann@example.com", "bob@example.com"]);
+    }
+
+    #[test]
+    fn test_owner_id() {
+        // printf '%s' jane@example.com | git hash-object --stdin
+        let id = "a4fc0e41821a208c1b81687393380df8886f0e99";
+        assert_eq!(owner_id("jane@example.com"), id);
+        assert_eq!(owner_id("  Jane@Example.COM "), id);
+        assert_ne!(owner_id("joe@example.com"), id);
+    }
+}
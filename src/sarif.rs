@@ -0,0 +1,264 @@
+//! SARIF 2.1.0 output for code scanning tools.
+//!
+//! Only the fields required by the specification are written, with a result
+//! for each file whose bus factor is 1 at the `--bus-factor-threshold`.
+
+use crate::number::NumberFormat;
+use git_whoknows::{Owner, TrackedFile};
+
+use anyhow::Result;
+use serde::Serialize;
+
+const SCHEMA: &str = "https://json.schemastore.org/sarif-2.1.0.json";
+const VERSION: &str = "2.1.0";
+const RULE_ID: &str = "bus-factor-1";
+
+#[derive(Serialize)]
+pub struct Log {
+    #[serde(rename = "$schema")]
+    schema: &'static str,
+    version: &'static str,
+    runs: Vec<Run>,
+}
+
+#[derive(Serialize)]
+struct Run {
+    tool: Tool,
+    results: Vec<SarifResult>,
+}
+
+#[derive(Serialize)]
+struct Tool {
+    driver: Driver,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct Driver {
+    name: &'static str,
+    version: &'static str,
+    information_uri: &'static str,
+    rules: Vec<Rule>,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct Rule {
+    id: &'static str,
+    short_description: Message,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct SarifResult {
+    rule_id: &'static str,
+    level: &'static str,
+    message: Message,
+    locations: Vec<Location>,
+    properties: ResultProperties,
+}
+
+/// Property bag of a result, describing the owner it names.
+#[derive(Serialize)]
+struct ResultProperties {
+    /// Whether the owner stands in for lines not committed yet or older than
+    /// the boundary, rather than being a person.
+    synthetic: bool,
+}
+
+#[derive(Serialize)]
+struct Message {
+    text: String,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct Location {
+    physical_location: PhysicalLocation,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct PhysicalLocation {
+    artifact_location: ArtifactLocation,
+}
+
+#[derive(Serialize)]
+struct ArtifactLocation {
+    uri: String,
+}
+
+impl Log {
+    /// Build a log with a result for each file a single owner accepted by
+    /// `filter` has more than `threshold` percent of the lines of, ordered by
+    /// path, with percentages in the messages formatted by `numbers`.
+    pub fn new(
+        files: &[TrackedFile],
+        threshold: f64,
+        filter: impl Fn(&Owner) -> bool,
+        numbers: &NumberFormat,
+    ) -> Log {
+        let mut results: Vec<SarifResult> = files
+            .iter()
+            .filter(|file| file.bus_factor_above(threshold) == 1)
+            .filter_map(|file| {
+                let owner = file
+                    .owners
+                    .values()
+                    .max_by(|a, b| a.lines().cmp(&b.lines()).then(b.email.cmp(&a.email)))
+                    .filter(|owner| filter(owner))?;
+                Some(SarifResult {
+                    rule_id: RULE_ID,
+                    level: "warning",
+                    message: Message {
+                        text: message(file, owner, numbers),
+                    },
+                    locations: vec![Location {
+                        physical_location: PhysicalLocation {
+                            artifact_location: ArtifactLocation { uri: uri(file) },
+                        },
+                    }],
+                    properties: ResultProperties {
+                        synthetic: owner.is_synthetic(),
+                    },
+                })
+            })
+            .collect();
+        results.sort_by(|a, b| a.uri().cmp(b.uri()));
+
+        Log {
+            schema: SCHEMA,
+            version: VERSION,
+            runs: vec![Run {
+                tool: Tool {
+                    driver: Driver {
+                        name: env!("CARGO_PKG_NAME"),
+                        version: env!("CARGO_PKG_VERSION"),
+                        information_uri: env!("CARGO_PKG_REPOSITORY"),
+                        rules: vec![Rule {
+                            id: RULE_ID,
+                            short_description: Message {
+                                text: format!(
+                                    "A single owner wrote more than {}% of the file",
+                                    threshold
+                                ),
+                            },
+                        }],
+                    },
+                },
+                results,
+            }],
+        }
+    }
+
+    pub fn to_json(&self) -> Result<String> {
+        Ok(serde_json::to_string_pretty(self)?)
+    }
+}
+
+impl SarifResult {
+    /// Location of the file the result is for.
+    fn uri(&self) -> &str {
+        &self.locations[0].physical_location.artifact_location.uri
+    }
+}
+
+fn message(file: &TrackedFile, owner: &Owner, numbers: &NumberFormat) -> String {
+    format!(
+        "{} <{}> owns {} of the lines of {}",
+        owner.name,
+        owner.email,
+        numbers.percent(file.owner_percentage(owner)),
+        file.repo_path.display()
+    )
+}
+
+/// Location of the file relative to the root of its repository, with forward
+/// slashes as required for URIs.
+fn uri(file: &TrackedFile) -> String {
+    let components: Vec<String> = file
+        .repo_path
+        .components()
+        .map(|c| c.as_os_str().to_string_lossy().replace(' ', "%20"))
+        .collect();
+    components.join("/")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixture::tracked_file;
+
+    #[test]
+    fn test_results_for_bus_factor_one() {
+        let files = vec![
+            tracked_file(
+                "src/main.rs",
+                &[("jane@example.com", 6), ("joe@example.com", 4)],
+            ),
+            tracked_file(
+                "src/lib.rs",
+                &[("jane@example.com", 5), ("joe@example.com", 5)],
+            ),
+            tracked_file("docs/read me.md", &[("joe@example.com", 3)]),
+        ];
+        let json: serde_json::Value = serde_json::from_str(
+            &Log::new(&files, 50.0, |_| true, &NumberFormat::default())
+                .to_json()
+                .unwrap(),
+        )
+        .unwrap();
+
+        assert_eq!(json["version"], "2.1.0");
+        let results = json["runs"][0]["results"].as_array().unwrap();
+        assert_eq!(results.len(), 2);
+        assert_eq!(
+            results[0]["locations"][0]["physicalLocation"]["artifactLocation"]["uri"],
+            "docs/read%20me.md"
+        );
+        assert_eq!(
+            results[1]["locations"][0]["physicalLocation"]["artifactLocation"]["uri"],
+            "src/main.rs"
+        );
+        assert_eq!(
+            results[1]["message"]["text"],
+            "jane@example.com <jane@example.com> owns 60.0% of the lines of src/main.rs"
+        );
+        assert_eq!(results[1]["properties"]["synthetic"], false);
+    }
+
+    #[test]
+    fn test_threshold_and_filter() {
+        let files = vec![
+            tracked_file(
+                "src/main.rs",
+                &[("jane@example.com", 6), ("joe@example.com", 4)],
+            ),
+            tracked_file("src/lib.rs", &[("joe@example.com", 3)]),
+        ];
+        let uris = |log: Log| -> Vec<String> {
+            log.runs[0]
+                .results
+                .iter()
+                .map(|r| r.uri().to_string())
+                .collect()
+        };
+        let numbers = NumberFormat::default();
+
+        // Jane's 60% is not more than a threshold of 75%.
+        assert_eq!(
+            uris(Log::new(&files, 75.0, |_| true, &numbers)),
+            ["src/lib.rs"]
+        );
+        // Only files whose single owner passes the filter are reported.
+        assert_eq!(
+            uris(Log::new(
+                &files,
+                50.0,
+                |o| o.email.starts_with("jane"),
+                &numbers
+            )),
+            ["src/main.rs"]
+        );
+    }
+}
@@ -0,0 +1,142 @@
+use git_whoknows::identity::Identities;
+use git_whoknows::language::LineFilter;
+use git_whoknows::{Analyzer, Method};
+
+use anyhow::Result;
+
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+/// Contents of the fixture file, committed by two authors.
+const FIXTURE: &[(&str, &str)] = &[
+    ("Jane <jane@example.com>", "one\ntwo\n"),
+    ("Joe <joe@example.com>", "one\ntwo\nthree\n"),
+];
+
+/// Check that the analysis works end to end in this environment, printing OK
+/// or FAIL for each step.
+///
+/// A small repository is created in a temporary directory and its file is
+/// analyzed with each blame method, so problems with finding or running git
+/// show up without a repository of the user's.
+pub fn run() -> Result<()> {
+    let dir = std::env::temp_dir().join(format!("git-whoknows-self-test-{}", std::process::id()));
+    let result = run_in(&dir);
+    let _ = fs::remove_dir_all(&dir);
+    if result? {
+        println!("OK: self test passed");
+        Ok(())
+    } else {
+        Err(anyhow::Error::msg("Self test failed"))
+    }
+}
+
+fn run_in(dir: &Path) -> Result<bool> {
+    match run_git(dir.parent().unwrap_or(dir), &["--version"]) {
+        Ok(version) => println!("OK: {}", version.trim()),
+        Err(e) => {
+            println!("FAIL: running git: {}", e);
+            return Ok(false);
+        }
+    }
+
+    let path = match create_fixture(dir) {
+        Ok(path) => {
+            println!("OK: created a repository in {}", dir.display());
+            path
+        }
+        Err(e) => {
+            println!("FAIL: creating a repository in {}: {}", dir.display(), e);
+            return Ok(false);
+        }
+    };
+
+    let mut passed = true;
+    for (name, method) in &[
+        ("libgit2", Method::Libgit2),
+        ("nom", Method::Nom),
+        ("no-extra", Method::NoExtra),
+        ("regex", Method::Regex),
+    ] {
+        let analyzer = Analyzer::new(
+            *method,
+            Identities::default(),
+            LineFilter::default(),
+            Vec::new(),
+        );
+        let owners = analyzer.analyze(&path).map(|file| {
+            let mut owners: Vec<(String, usize)> = file
+                .owners
+                .values()
+                .map(|owner| (owner.email.to_string(), owner.lines()))
+                .collect();
+            owners.sort();
+            owners
+        });
+        let expected = vec![
+            ("jane@example.com".to_string(), 2),
+            ("joe@example.com".to_string(), 1),
+        ];
+        match owners {
+            Ok(owners) if owners == expected => println!("OK: blame with {}", name),
+            Ok(owners) => {
+                println!("FAIL: blame with {}: found {:?}", name, owners);
+                passed = false;
+            }
+            Err(e) => {
+                println!("FAIL: blame with {}: {}", name, e);
+                passed = false;
+            }
+        }
+    }
+    Ok(passed)
+}
+
+/// Create a repository in `dir` with the fixture file committed, returning
+/// the path of the file.
+fn create_fixture(dir: &Path) -> Result<PathBuf> {
+    fs::create_dir_all(dir)?;
+    run_git(dir, &["init", "-q"])?;
+    for (author, contents) in FIXTURE {
+        commit_file(dir, "fixture.txt", contents, author)?;
+    }
+    Ok(dir.join("fixture.txt"))
+}
+
+/// Write `contents` to `file` in the repository at `dir`, creating its
+/// directories, and commit it as `author`.
+fn commit_file(dir: &Path, file: &str, contents: &str, author: &str) -> Result<()> {
+    let path = dir.join(file);
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    fs::write(path, contents)?;
+    run_git(dir, &["add", file])?;
+    run_git(dir, &["commit", "-q", "-m", "Fixture", "--author", author])?;
+    Ok(())
+}
+
+/// Run git in `dir` with a fixed committer identity and without signing
+/// commits, returning its output.
+fn run_git(dir: &Path, args: &[&str]) -> Result<String> {
+    let output = Command::new("git")
+        .current_dir(dir)
+        .args([
+            "-c",
+            "user.name=Self Test",
+            "-c",
+            "user.email=self-test@example.com",
+        ])
+        .args(["-c", "commit.gpgsign=false"])
+        .args(args)
+        .output()?;
+    if !output.status.success() {
+        return Err(anyhow::Error::msg(format!(
+            "git {} failed: {}",
+            args.join(" "),
+            String::from_utf8_lossy(&output.stderr).trim()
+        )));
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).to_string())
+}